@@ -0,0 +1,100 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use encase::StorageBuffer;
+use particle_3d::{generate_particles_seeded, Particle, Particles};
+
+//counting allocated bytes so per tick copies show up next to the timings
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+//bytes allocated while running `f`
+fn allocated_by<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Relaxed);
+    black_box(f());
+    ALLOCATED.load(Relaxed) - before
+}
+
+//default simulation with `count` seeded particles, stepped once so every buffer is warm
+fn simulation(count: usize) -> Particles {
+    let mut sim = Particles::builder()
+        .particles(generate_particles_seeded(10.0, count, 5, 1))
+        .build()
+        .unwrap();
+    sim.update(0.01).unwrap();
+    sim
+}
+
+//serializing the particles for the gpu the way the render path does, from a slice, into a
+//reused buffer so only a copy of the particles themselves allocates
+fn upload(particles: &[Particle], buffer: &mut Vec<u8>) {
+    let mut storage = StorageBuffer::new(std::mem::take(buffer));
+    storage.write(&particles).unwrap();
+    *buffer = storage.into_inner();
+}
+
+//one physics tick handed to the renderer: `update` returning a borrowed slice against the
+//owned copy it used to return, everything else the tick allocates (hash, forces) is the same
+//for both so the difference is the copy alone
+fn frame_handoff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_handoff");
+    for count in [10_000, 100_000] {
+        let mut sim = simulation(count);
+        let mut buffer = Vec::new();
+        upload(&sim.active_particles, &mut buffer);
+        let borrowed_tick = |sim: &mut Particles, buffer: &mut Vec<u8>| {
+            let particles = sim.update(0.01).unwrap();
+            upload(particles, buffer)
+        };
+        let copied_tick = |sim: &mut Particles, buffer: &mut Vec<u8>| {
+            let particles = sim.update(0.01).unwrap().to_vec();
+            upload(&particles, buffer)
+        };
+
+        let borrowed = allocated_by(|| borrowed_tick(&mut sim, &mut buffer));
+        let copied = allocated_by(|| copied_tick(&mut sim, &mut buffer));
+        println!(
+            "{count} particles: {borrowed} bytes allocated per tick borrowed, {copied} copied, {} of them the returned copy",
+            copied.saturating_sub(borrowed)
+        );
+
+        group.bench_function(BenchmarkId::new("borrowed", count), |b| {
+            b.iter(|| borrowed_tick(&mut sim, &mut buffer))
+        });
+        group.bench_function(BenchmarkId::new("copied", count), |b| {
+            b.iter(|| copied_tick(&mut sim, &mut buffer))
+        });
+    }
+    group.finish();
+}
+
+//the force kernel alone (hashing plus the pair loop) at a few particle counts
+fn compute_forces(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compute_forces");
+    for count in [1_000, 10_000, 100_000] {
+        let sim = simulation(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &sim, |b, sim| {
+            b.iter(|| sim.compute_forces(black_box(&sim.active_particles)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, frame_handoff, compute_forces);
+criterion_main!(benches);
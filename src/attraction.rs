@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+//how much each particle type attracts (positive) or repels (negative) each other type, stored
+//row major so `get(a, b)` is how type a reacts to type b, saved as the plain list of values
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<f32>", into = "Vec<f32>")]
+pub struct AttractionMatrix {
+    id_count: usize,//rows and columns
+    values: Vec<f32>,//id_count * id_count values, unless built from a list that isn't square
+}
+
+impl AttractionMatrix {
+    //every pair neutral
+    pub fn new(id_count: usize) -> Self {
+        Self {
+            id_count,
+            values: vec![0.0; id_count * id_count],
+        }
+    }
+
+    //types the matrix has rows and columns for
+    pub fn id_count(&self) -> usize {
+        self.id_count
+    }
+
+    //number of stored values, id_count * id_count for a well formed matrix
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    //the raw row major values
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    //how type a reacts to type b, types outside the matrix neither attract nor repel
+    pub fn get(&self, a: usize, b: usize) -> f32 {
+        self.index_of(a, b).map_or(0.0, |index| self.values[index])
+    }
+
+    //editable entry for a type pair, None for types outside the matrix
+    pub fn get_mut(&mut self, a: usize, b: usize) -> Option<&mut f32> {
+        let index = self.index_of(a, b)?;
+        self.values.get_mut(index)
+    }
+
+    //setting how type a reacts to type b, pairs outside the matrix are ignored
+    pub fn set(&mut self, a: usize, b: usize, value: f32) {
+        if let Some(entry) = self.get_mut(a, b) {
+            *entry = value;
+        }
+    }
+
+    //changing the number of types, keeping every pair both sizes have and making new pairs neutral
+    pub fn resize(&mut self, id_count: usize) {
+        let mut resized = Self::new(id_count);
+        for a in 0..id_count.min(self.id_count) {
+            for b in 0..id_count.min(self.id_count) {
+                resized.values[a * id_count + b] = self.get(a, b);
+            }
+        }
+        *self = resized;
+    }
+
+    //position of a pair in `values` if both types are inside the matrix
+    fn index_of(&self, a: usize, b: usize) -> Option<usize> {
+        let index = a * self.id_count + b;
+        (a < self.id_count && b < self.id_count && index < self.values.len()).then_some(index)
+    }
+}
+
+//a row major list of values, its side is the largest square that fits
+//(validation reports lists that aren't id_count * id_count long)
+impl From<Vec<f32>> for AttractionMatrix {
+    fn from(values: Vec<f32>) -> Self {
+        let mut id_count = (values.len() as f64).sqrt() as usize;
+        while id_count * id_count > values.len() {
+            id_count -= 1;
+        }
+        Self { id_count, values }
+    }
+}
+
+impl From<AttractionMatrix> for Vec<f32> {
+    fn from(matrix: AttractionMatrix) -> Self {
+        matrix.values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a matrix whose entry for a, b is 10a + b
+    fn numbered(id_count: usize) -> AttractionMatrix {
+        let mut matrix = AttractionMatrix::new(id_count);
+        for a in 0..id_count {
+            for b in 0..id_count {
+                matrix.set(a, b, (10 * a + b) as f32);
+            }
+        }
+        matrix
+    }
+
+    #[test]
+    fn growing_keeps_every_pair_and_adds_neutral_ones() {
+        let mut matrix = numbered(3);
+        matrix.resize(5);
+        assert_eq!((matrix.id_count(), matrix.len()), (5, 25));
+        for a in 0..5 {
+            for b in 0..5 {
+                let expected = if a < 3 && b < 3 { (10 * a + b) as f32 } else { 0.0 };
+                assert_eq!(matrix.get(a, b), expected, "{} {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn shrinking_keeps_the_remaining_pairs() {
+        let mut matrix = numbered(4);
+        matrix.resize(2);
+        assert_eq!(matrix.values(), &[0.0, 1.0, 10.0, 11.0]);
+        //dropped types are outside the matrix now
+        assert_eq!(matrix.get(3, 0), 0.0);
+        assert!(matrix.get_mut(2, 1).is_none());
+        //and growing again doesn't bring them back
+        matrix.resize(4);
+        assert_eq!(matrix.get(3, 3), 0.0);
+        assert_eq!(matrix.get(1, 0), 10.0);
+    }
+}
@@ -0,0 +1,27 @@
+//the scene after msaa resolve, drawn into egui's render pass
+@group(0) @binding(0)
+var scene: texture_2d<f32>;
+
+@group(0) @binding(1)
+var scene_sampler: sampler;
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+//one triangle covering the whole viewport, the corners come from the vertex index
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOut {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOut;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+//the texture has the viewport's pixel size, so every fragment reads exactly one texel
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return textureSample(scene, scene_sampler, in.uv);
+}
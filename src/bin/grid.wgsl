@@ -0,0 +1,34 @@
+//camera matrices
+struct Camera {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+    frustum_planes: array<vec4<f32>, 6>, //inward facing planes for culling
+    cull_enabled: u32, //1 when off-screen particles should be skipped
+    slab_plane: vec4<f32>, //center plane of the visible cross-section
+    slab_half_thickness: f32, //half width of the visible cross-section
+    slab_enabled: u32, //1 when only the cross-section is drawn
+};
+
+//line vertex coming from the cpu generated grid boxes
+struct VertexIn {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    //plain grey so the overlay doesn't compete with particle colors
+    return vec4(0.5, 0.5, 0.5, 1.0);
+}
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+    out.position = camera.projection_matrix * camera.view_matrix * vec4(in.position, 1.0);
+    return out;
+}
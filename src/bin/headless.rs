@@ -0,0 +1,123 @@
+use particle_3d::Particles;
+
+//runs the simulation without a window or gpu and prints summary stats, e.g. for ci or servers:
+//cargo run --release --bin headless -- --steps 1000 --count 5000 --seed 42 --dt 0.016
+
+//command line settings and their defaults
+struct Args {
+    steps: usize,//physics steps to run
+    count: usize,//particles to generate
+    seed: u64,//seed for the particle layout
+    dt: f32,//time step per physics step
+    report_every: usize,//steps between progress lines (0 = only the final summary)
+    csv_every: usize,//steps between `frame_{step}.csv` dumps (0 = no csv)
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            steps: 1000,
+            count: 1000,//same as the viewer's starting count
+            seed: 0,
+            dt: 1.0 / 60.0,//the viewer's default update rate
+            report_every: 100,
+            csv_every: 0,
+        }
+    }
+}
+
+impl Args {
+    //reading `--name value` pairs, anything unknown or unparsable is an error
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(String::new());
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", flag))?;
+            let invalid = |_| format!("invalid value for {}: {}", flag, value);
+            match flag.as_str() {
+                "--steps" => parsed.steps = value.parse().map_err(invalid)?,
+                "--count" => parsed.count = value.parse().map_err(invalid)?,
+                "--seed" => parsed.seed = value.parse().map_err(invalid)?,
+                "--dt" => parsed.dt = value.parse().map_err(invalid)?,
+                "--report-every" => parsed.report_every = value.parse().map_err(invalid)?,
+                "--csv-every" => parsed.csv_every = value.parse().map_err(invalid)?,
+                _ => return Err(format!("unknown argument {}", flag)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+//one line of stats about the current state
+fn summary(particles: &Particles) -> String {
+    let count = particles.active_particles.len();
+    format!(
+        "t={:.3}s particles={} mean_speed={:.5} kinetic_energy={:.5} per_type={:?}",
+        particles.sim_time(),
+        count,
+        particles.average_speed(),
+        particles.kinetic_energy(),
+        particles.count_by_type(),
+    )
+}
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}", e);
+            }
+            eprintln!(
+                "usage: headless [--steps N] [--count N] [--seed N] [--dt SECONDS] [--report-every N] [--csv-every N]"
+            );
+            std::process::exit(2);
+        }
+    };
+
+    //viewer defaults with a reproducible particle layout
+    let mut particles = Particles::builder()
+        .build()
+        .expect("default parameters are consistent");
+    particles.active_particles = particle_3d::generate_particles_seeded_in_box(
+        particles.world_extents(),
+        args.count,
+        particles.id_count,
+        args.seed,
+    );
+
+    println!("step 0: {}", summary(&particles));
+    let start = std::time::Instant::now();
+    for step in 1..=args.steps {
+        if let Err(e) = particles.update(args.dt) {
+            eprintln!("step {} failed: {}", step, e);
+            std::process::exit(1);
+        }
+        if args.csv_every > 0 && step % args.csv_every == 0 {
+            let path = format!("frame_{}.csv", step);
+            let written = std::fs::File::create(&path)
+                .map_err(particle_3d::SimError::from)
+                .and_then(|file| particles.export_csv(file));
+            if let Err(e) = written {
+                eprintln!("writing {} failed: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        if args.report_every > 0 && step % args.report_every == 0 && step != args.steps {
+            println!("step {}: {}", step, summary(&particles));
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!("step {}: {}", args.steps, summary(&particles));
+    println!(
+        "ran {} steps in {:.3}s ({:.3}ms per step)",
+        args.steps,
+        elapsed.as_secs_f64(),
+        elapsed.as_secs_f64() * 1000.0 / args.steps.max(1) as f64,
+    );
+}
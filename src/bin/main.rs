@@ -1,7 +1,7 @@
 use cgmath::prelude::*;
 use encase::{ArrayLength, ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
 use eframe::{egui, wgpu::util::DeviceExt};
-use particle_3d::{Particle, Particles};
+use particle_3d::{BoundaryMode, Dim, FlowField, GravityMode, Integrator, Precision, Particle, Particles, Scene, SimParams, SpeciesSpawn, SteadyStateDetector};
 use eframe::egui_wgpu::wgpu;
 use eframe::wgpu::include_wgsl;
 use rand::prelude::*;
@@ -9,8 +9,32 @@ use rayon::prelude::*;
 
 //constants for movement and particle types
 const ROTATION_SPEED: f32 = 90.0;
-const SPEED: f32 = 5.0;
+const SPEED: f32 = 5.0;//starting movement speed, scrolling over the view changes it
+const MOUSE_SENSITIVITY: f32 = 0.2;//degrees turned per pixel dragged
 const MAX_PARTICLE_TYPES: usize = 5;
+const MAX_GRID_CELLS: usize = 512;//most grid cells drawn by the debug overlay
+const MSAA_STORAGE_KEY: &str = "msaa_sample_count";//app storage key remembering the chosen msaa level
+const CAMERA_RANGE_SCALE: f32 = 10.0;//camera stays within this many world sizes of the origin
+const DEFAULT_UPDATE_RATE: f32 = 60.0;//physics steps per second at startup
+const FIELD_OF_VIEW: f32 = 90.0;//vertical field of view in degrees
+const FRAME_ALL_DURATION: f32 = 0.6;//seconds the "Frame All" camera flight takes
+const SCRUB_HISTORY_BYTES: usize = 256 << 20;//most memory the states to scrub back can take
+const DEBUG_BUNDLE_FILE: &str = "debug_bundle.json";//where "Export Debug Bundle" writes to
+const MAX_TRAIL_LENGTH: usize = 64;//most past positions a trail can show
+
+//how the camera is steered
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CameraMode {
+    //flying freely with position, pitch and yaw
+    Fps,
+    //circling `target` at `distance`, angles in degrees (elevation > 0 looks down from above)
+    Orbit {
+        target: cgmath::Vector3<f32>,
+        distance: f32,
+        azimuth: f32,
+        elevation: f32,
+    },
+}
 
 //camera system to control position, direction, and movements
 #[derive(Clone)]
@@ -19,13 +43,34 @@ struct CameraSystem {
     up: cgmath::Vector3<f32>,
     pitch: f32, //up/down rotation
     yaw: f32, //left/right rotation
+    speed: f32, //movement speed in world units per second
+    level_horizon: bool, //keeping the right vector flat so the horizon never tilts
+    mode: CameraMode, //free flying or orbiting, the fields above are only used when flying
 }
 
 impl CameraSystem {
+    //pitch and yaw the camera looks with in either mode
+    fn angles(&self) -> (f32, f32) {
+        match self.mode {
+            CameraMode::Fps => (self.pitch, self.yaw),
+            CameraMode::Orbit { azimuth, elevation, .. } => (-elevation, azimuth),
+        }
+    }
+    //where the camera actually is, on the orbit sphere when orbiting
+    fn eye(&self) -> cgmath::Vector3<f32> {
+        match self.mode {
+            CameraMode::Fps => self.position,
+            CameraMode::Orbit { target, distance, .. } => {
+                let (forward, _, _) = self.calculate_axes();
+                target - forward * distance
+            }
+        }
+    }
         //calculation for the camera's forward, right, and up direction vectors
     fn calculate_axes(&self) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
-        let pitch_rad = self.pitch.to_radians();
-        let yaw_rad = self.yaw.to_radians();
+        let (pitch, yaw) = self.angles();
+        let pitch_rad = pitch.to_radians();
+        let yaw_rad = yaw.to_radians();
         
         let pitch_cos = pitch_rad.cos();
         let pitch_sin = pitch_rad.sin();
@@ -39,173 +84,2665 @@ impl CameraSystem {
         )
         .normalize();
     //compute right and up vectors using cross products
-    let right = forward.cross(self.up).normalize();
+    let right = if self.level_horizon || matches!(self.mode, CameraMode::Orbit { .. }) {
+        //projecting out any world-y part so there is no roll
+        let right = forward.cross(cgmath::vec3(0.0, 1.0, 0.0));
+        let flat = cgmath::vec3(right.x, 0.0, right.z);
+        if flat.magnitude2() > 1e-8 {
+            flat.normalize()
+        } else {
+            //looking straight up/down, falling back to yaw for the right direction
+            cgmath::vec3(yaw_rad.cos(), 0.0, yaw_rad.sin())
+        }
+    } else {
+        forward.cross(self.up).normalize()
+    };
     let up = forward.cross(right).normalize();
     
     (forward, right, up)//3 direction vector
     }
-    //movement of camera based on user input and time delta
+    //movement of camera based on user input and time delta (orbiting stays on its sphere)
     fn move_camera(&mut self, delta: f32, input_vector: cgmath::Vector3<f32>) {
-        self.position += input_vector * SPEED * delta;
+        if self.mode == CameraMode::Fps {
+            self.position += input_vector * self.speed * delta;
+        }
     }
-    //rotates the camera by modifying pitch and yaw
+    //rotates the camera by modifying pitch and yaw, or circles the target when orbiting
     fn rotate_camera(&mut self, pitch_delta: f32, yaw_delta: f32) {
+        if let CameraMode::Orbit { azimuth, elevation, .. } = &mut self.mode {
+            *azimuth += yaw_delta;
+            *elevation = (*elevation - pitch_delta).clamp(-89.9, 89.9);
+            return;
+        }
         self.pitch += pitch_delta; //pitch angle
         self.yaw += yaw_delta;
         self.pitch = self.pitch.clamp(-90.9999, 90.9999);//avoid flipping 
+        if self.level_horizon {
+            //stopping short of straight up/down so the view never flips over
+            self.pitch = self.pitch.clamp(-89.9, 89.9);
+        }
+    }
+    //pulling the camera back onto a sphere around the origin if it drifted too far away
+    fn clamp_position(&mut self, max_distance: f32) {
+        if let CameraMode::Orbit { distance, .. } = &mut self.mode {
+            *distance = distance.min(max_distance);
+            return;
+        }
+        let distance = self.position.magnitude();
+        if distance > max_distance {
+            self.position *= max_distance / distance;
+        }
+    }
+    //scaling the camera's place along with the world
+    fn scale(&mut self, factor: f32) {
+        match &mut self.mode {
+            CameraMode::Fps => self.position *= factor,
+            CameraMode::Orbit { target, distance, .. } => {
+                *target *= factor;
+                *distance *= factor;
+            }
+        }
+    }
+    //scrolling changes the flying speed, or how far away the orbit is
+    fn zoom(&mut self, scroll: f32) {
+        match &mut self.mode {
+            CameraMode::Fps => self.speed = (self.speed * (scroll * 0.002).exp()).clamp(0.1, 100.0),
+            CameraMode::Orbit { distance, .. } => *distance = (*distance * (-scroll * 0.002).exp()).max(0.1),
+        }
+    }
+    //switching between flying and orbiting the world center without the view jumping
+    fn toggle_mode(&mut self) {
+        match self.mode {
+            CameraMode::Fps => self.orbit_around(cgmath::vec3(0.0, 0.0, 0.0)),
+            CameraMode::Orbit { .. } => self.set_pose(self.pose()),
+        }
+    }
+    //orbiting `target` from where the camera is now, turning to look at it
+    fn orbit_around(&mut self, target: cgmath::Vector3<f32>) {
+        let offset = self.eye() - target;
+        let distance = offset.magnitude().max(0.1);
+        self.mode = CameraMode::Orbit {
+            target,
+            distance,
+            azimuth: (-offset.x).atan2(offset.z).to_degrees(),
+            elevation: (offset.y / distance).clamp(-1.0, 1.0).asin().to_degrees().clamp(-89.9, 89.9),
+        };
+    }
+    //where the camera is and which way it looks
+    fn pose(&self) -> CameraPose {
+        let (pitch, yaw) = self.angles();
+        CameraPose {
+            position: self.eye(),
+            pitch,
+            yaw,
+        }
+    }
+    //placing the camera directly, which means flying freely from there
+    fn set_pose(&mut self, pose: CameraPose) {
+        self.mode = CameraMode::Fps;
+        self.position = pose.position;
+        self.pitch = pose.pitch;
+        self.yaw = pose.yaw;
+    }
+}
+
+//camera position and orientation, the part of the camera that gets animated
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CameraPose {
+    position: cgmath::Vector3<f32>,
+    pitch: f32,
+    yaw: f32,
+}
+
+//point the orbit camera centers on: the particles' center of mass, or the world center when
+//there are none
+fn orbit_pivot(particles: &Particles) -> cgmath::Vector3<f32> {
+    particles.center_of_mass().unwrap_or(cgmath::vec3(0.0, 0.0, 0.0))
+}
+
+//smoothstep easing, slow at both ends so the camera doesn't jerk on start or stop
+fn ease_in_out(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl CameraPose {
+    //blending between two poses, `t` = 0 gives `self` and `t` = 1 gives `to`
+    fn lerp(self, to: CameraPose, t: f32) -> CameraPose {
+        //turning the short way round instead of spinning through 360 degrees
+        let yaw_delta = (to.yaw - self.yaw + 180.0).rem_euclid(360.0) - 180.0;
+        CameraPose {
+            position: self.position.lerp(to.position, t),
+            pitch: self.pitch + (to.pitch - self.pitch) * t,
+            yaw: if t >= 1.0 { to.yaw } else { self.yaw + yaw_delta * t },
+        }
+    }
+}
+
+//camera flight from one pose to another, advanced once per frame
+#[derive(Clone, Copy, Debug)]
+struct CameraAnimation {
+    from: CameraPose,
+    to: CameraPose,
+    elapsed: f32,//seconds since the flight started
+    duration: f32,//seconds the whole flight takes
+}
+
+impl CameraAnimation {
+    //eased pose at the current point of the flight
+    fn pose(&self) -> CameraPose {
+        let t = if self.duration > 0.0 { self.elapsed / self.duration } else { 1.0 };
+        self.from.lerp(self.to, ease_in_out(t))
+    }
+    //moving the flight forward by `dt` seconds, returns true once it reached the end
+    fn advance(&mut self, dt: f32) -> bool {
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+        self.elapsed >= self.duration
+    }
+}
+
+//pose keeping the current view direction but backed off so every particle is in sight,
+//None when there is nothing to frame
+fn frame_all_pose(camera: &CameraSystem, particles: &[Particle]) -> Option<CameraPose> {
+    let first = particles.first()?.position;
+    let (min, max) = particles.iter().fold((first, first), |(min, max), particle| {
+        let p = particle.position;
+        (
+            cgmath::vec3(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+            cgmath::vec3(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+        )
+    });
+    let center = (min + max) * 0.5;
+    //fitting the bounding sphere inside the view cone, with a little margin
+    let radius = ((max - min).magnitude() * 0.5).max(0.1) * 1.1;
+    let distance = radius / (FIELD_OF_VIEW * 0.5).to_radians().sin();
+    let (forward, _, _) = camera.calculate_axes();
+    Some(CameraPose {
+        position: center - forward * distance,
+        ..camera.pose()
+    })
+}
+
+//decoding one srgb color channel (how colors are authored) to linear light
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+//encoding one linear light color channel to srgb, the inverse of `srgb_to_linear`
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+//editing an srgb color with egui's color picker, which works in linear light (only written back
+//when edited, so an untouched color doesn't drift through the round trip)
+fn srgb_color_edit(ui: &mut egui::Ui, srgb: &mut [f32; 3]) -> egui::Response {
+    let mut linear = srgb.map(srgb_to_linear);
+    let response = ui.color_edit_button_rgb(&mut linear);
+    if response.changed() {
+        *srgb = linear.map(linear_to_srgb);
+    }
+    response
+}
+
+//msaa sample counts the scene can be drawn with given the color and depth formats' features
+//(resolving needs support on the color format too)
+fn sample_counts(color: wgpu::TextureFormatFeatureFlags, depth: wgpu::TextureFormatFeatureFlags) -> Vec<u32> {
+    [1, 2, 4, 8, 16]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.sample_count_supported(count)
+                    && depth.sample_count_supported(count)
+                    && color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE))
+        })
+        .collect()
+}
+
+//msaa sample counts this adapter can draw the scene into `format` with, counts beyond the ones
+//webgpu guarantees need the adapter specific format features on the device
+fn supported_sample_counts(render_state: &eframe::egui_wgpu::RenderState) -> Vec<u32> {
+    let flags = |format: wgpu::TextureFormat| {
+        if render_state
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            render_state.adapter.get_texture_format_features(format).flags
+        } else {
+            format.describe().guaranteed_format_features.flags
+        }
+    };
+    sample_counts(flags(render_state.target_format), flags(wgpu::TextureFormat::Depth32Float))
+}
+
+//the largest supported sample count not above the requested one
+fn clamp_sample_count(requested: u32, supported: &[u32]) -> u32 {
+    supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1)
+}
+
+//where the controls are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ControlsPlacement {
+    Docked,//side panel next to the 3d view
+    Detached,//movable window over the 3d view
+}
+
+impl ControlsPlacement {
+    //label of the button moving the controls to the other place
+    fn toggle_label(self) -> &'static str {
+        match self {
+            ControlsPlacement::Docked => "Detach Controls",
+            ControlsPlacement::Detached => "Dock Controls",
+        }
+    }
+
+    fn toggled(self) -> Self {
+        match self {
+            ControlsPlacement::Docked => ControlsPlacement::Detached,
+            ControlsPlacement::Detached => ControlsPlacement::Docked,
+        }
+    }
+
+    //placement after a frame with the floating window shown, closing the window docks the
+    //controls again
+    fn after_window(self, open: bool) -> Self {
+        if open { self } else { ControlsPlacement::Docked }
+    }
+}
+
+//how often the window asks to be redrawn
+#[derive(Clone, Copy, PartialEq)]
+enum RepaintMode {
+    Continuous,//redraw as fast as possible
+    CappedFps,//redraw at most at the target fps
+    WhenRunning,//redraw continuously only while physics runs, otherwise only on input
+}
+
+//running a frame's data prep, uploads and drawing unless the view is frozen, returns whether
+//it ran (the physics steps elsewhere either way)
+fn paint_unless_frozen(freeze_view: bool, paint: impl FnOnce()) -> bool {
+    if !freeze_view {
+        paint();
+    }
+    !freeze_view
+}
+
+//time to wait between frames to hit the target fps
+fn repaint_delay(target_fps: f32) -> std::time::Duration {
+    std::time::Duration::from_secs_f32(1.0 / target_fps.max(1.0))
+}
+
+//tracking when physics steps actually ran to estimate steps per second over a time window
+struct StepRateCounter {
+    window: std::time::Duration,//how far back steps are counted
+    steps: std::collections::VecDeque<std::time::Instant>,//timestamps of recent steps
+}
+
+impl StepRateCounter {
+    fn new(window: std::time::Duration) -> Self {
+        Self {
+            window,
+            steps: std::collections::VecDeque::new(),
+        }
+    }
+
+    //remembering that a physics step ran at the given time
+    fn record(&mut self, time: std::time::Instant) {
+        self.steps.push_back(time);
+    }
+
+    //dropping old steps and returning how many steps per second ran within the window
+    fn rate(&mut self, now: std::time::Instant) -> f32 {
+        while let Some(&oldest) = self.steps.front() {
+            if now.duration_since(oldest) > self.window {
+                self.steps.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.steps.len() as f32 / self.window.as_secs_f32()
+    }
+}
+
+//turning a held scrub key into whole steps: one right away, then `rate` per second while held
+struct Scrubber {
+    rate: f32,//steps per second while a key is held
+    pending: f32,//fraction of a step carried over to the next frame
+    last_direction: i32,//1 forward, -1 backward, 0 not scrubbing
+}
+
+impl Scrubber {
+    //steps to take this frame for the held direction (changing direction starts over)
+    fn advance(&mut self, direction: i32, dt: f32) -> usize {
+        if direction != self.last_direction {
+            self.last_direction = direction;
+            //stepping immediately on a fresh press so a tap moves exactly one step
+            self.pending = if direction == 0 { 0.0 } else { 1.0 };
+        } else if direction != 0 {
+            self.pending += self.rate * dt;
+        }
+        let steps = self.pending.floor();
+        self.pending -= steps;
+        steps as usize
+    }
+}
+
+//everything a step changes, so scrubbing back to it steps on exactly as before
+#[derive(Clone, Debug, PartialEq)]
+struct ScrubState {
+    particles: Vec<Particle>,
+    previous_accelerations: Vec<cgmath::Vector3<f32>>,//velocity verlet history
+    wrapped: Vec<bool>,//which particles wrapped in the step that led here
+    double_state: Vec<[cgmath::Vector3<f64>; 2]>,//f64 state of double precision
+    sim_time: f32,
+}
+
+impl ScrubState {
+    fn capture(particles: &Particles) -> Self {
+        Self {
+            particles: particles.active_particles.clone(),
+            previous_accelerations: particles.previous_accelerations.clone(),
+            wrapped: particles.wrapped.clone(),
+            double_state: particles.double_state.clone(),
+            sim_time: particles.sim_time,
+        }
+    }
+
+    //putting the state back, the particles before it being `past_particles`
+    fn restore(self, particles: &mut Particles, past_particles: Vec<Particle>) {
+        particles.active_particles = self.particles;
+        particles.past_particles = past_particles;
+        particles.previous_accelerations = self.previous_accelerations;
+        particles.wrapped = self.wrapped;
+        particles.double_state = self.double_state;
+        particles.sim_time = self.sim_time;
+    }
+
+    fn bytes(&self) -> usize {
+        std::mem::size_of_val(self.particles.as_slice())
+            + std::mem::size_of_val(self.previous_accelerations.as_slice())
+            + std::mem::size_of_val(self.wrapped.as_slice())
+            + std::mem::size_of_val(self.double_state.as_slice())
+    }
+}
+
+//states before each scrubbed step, oldest first, dropping the oldest ones once they take more
+//than `max_bytes` (each keeps only its particles, the particles before a state are the ones of
+//the state before it)
+struct ScrubHistory {
+    states: std::collections::VecDeque<ScrubState>,
+    bytes: usize,//memory of all states
+    max_bytes: usize,
+}
+
+impl ScrubHistory {
+    fn new(max_bytes: usize) -> Self {
+        Self { states: Default::default(), bytes: 0, max_bytes }
+    }
+
+    fn push(&mut self, state: ScrubState) {
+        self.bytes += state.bytes();
+        self.states.push_back(state);
+        while self.bytes > self.max_bytes {
+            let Some(dropped) = self.states.pop_front() else { break };
+            self.bytes -= dropped.bytes();
+        }
+    }
+
+    fn pop(&mut self) -> Option<ScrubState> {
+        let state = self.states.pop_back()?;
+        self.bytes -= state.bytes();
+        Some(state)
+    }
+
+    //particles of the newest state, which came right before the last one popped
+    fn last_particles(&self) -> Option<&[Particle]> {
+        self.states.back().map(|state| state.particles.as_slice())
+    }
+
+    fn clear(&mut self) {
+        self.states.clear();
+        self.bytes = 0;
+    }
+}
+
+//cumulative physics cost since start (or the last reset)
+#[derive(Default)]
+struct StepStats {
+    steps: u64,//how many physics steps ran
+    total_time: std::time::Duration,//wall time spent inside update
+}
+
+impl StepStats {
+    //adding one physics step that took the given time
+    fn record(&mut self, duration: std::time::Duration) {
+        self.steps += 1;
+        self.total_time += duration;
+    }
+
+    //average milliseconds per step, 0 before any step ran
+    fn average_ms(&self) -> f64 {
+        if self.steps == 0 {
+            0.0
+        } else {
+            self.total_time.as_secs_f64() * 1000.0 / self.steps as f64
+        }
     }
 }
 
-//creating a range from 0 to count 
-fn generate_particles(world_size: f32, count: usize) -> Vec<Particle> {
+//one line description of a gpu adapter for the info panel and bug reports
+fn adapter_summary(info: &wgpu::AdapterInfo) -> String {
+    format!("{} ({:?}, {:?}, driver {} {})", info.name, info.backend, info.device_type, info.driver, info.driver_info)
+}
+
+//gpu details for troubleshooting: the adapter egui_wgpu picked and the device limits in use
+fn gpu_info(render_state: &eframe::egui_wgpu::RenderState) -> Vec<String> {
+    gpu_info_lines(
+        &render_state.adapter.get_info(),
+        render_state.target_format,
+        &render_state.device.limits(),
+    )
+}
+
+//the info panel lines for an adapter, the surface format and the device limits
+fn gpu_info_lines(adapter: &wgpu::AdapterInfo, target_format: wgpu::TextureFormat, limits: &wgpu::Limits) -> Vec<String> {
+    vec![
+        format!("Adapter: {}", adapter_summary(adapter)),
+        format!("Surface Format: {:?}", target_format),
+        format!("Max Texture Size: {}", limits.max_texture_dimension_2d),
+        format!("Max Storage Buffer: {} bytes", limits.max_storage_buffer_binding_size),
+        format!("Max Buffer: {} bytes", limits.max_buffer_size),
+        format!("Max Bind Groups: {}", limits.max_bind_groups),
+    ]
+}
+
+//default simulation parameters, the single source for startup and "Reset Parameters"
+//(no particles, callers generate their own)
+fn default_particles() -> Particles {
+    //the library defaults are the viewer's defaults
+    Particles::builder()
+        .build()
+        .expect("default parameters are consistent")
+}
+
+//setting every tunable of `particles` back to `default_particles`, keeping the world size and
+//the particles, returns how many particles were removed for having a type the defaults don't have
+fn reset_tunables(particles: &mut Particles) -> usize {
+    let defaults = default_particles();
+    particles.id_count = defaults.id_count;
+    particles.colors = defaults.colors;
+    particles.attraction_matrix = defaults.attraction_matrix;
+    particles.attraction_range = defaults.attraction_range;
+    particles.particle_effect_radius = defaults.particle_effect_radius;
+    particles.radius_matrix = defaults.radius_matrix;
+    particles.hard_radius = defaults.hard_radius;
+    particles.hard_iterations = defaults.hard_iterations;
+    particles.coefficient = defaults.coefficient;
+    particles.friction_scale = defaults.friction_scale;
+    particles.interaction_force = defaults.interaction_force;
+    particles.pair_force_scale = defaults.pair_force_scale;
+    particles.symmetric_forces = defaults.symmetric_forces;
+    particles.min_pull_ratio = defaults.min_pull_ratio;
+    particles.boundary = defaults.boundary;
+    particles.restitution = defaults.restitution;
+    particles.gravity = defaults.gravity;
+    particles.gravity_scale = defaults.gravity_scale;
+    particles.masses = defaults.masses;
+    particles.flow_field = defaults.flow_field;
+    particles.lock_y = defaults.lock_y;
+    particles.dimensions = defaults.dimensions;
+    particles.max_speed = defaults.max_speed;
+    particles.cell_size = defaults.cell_size;
+    particles.force_curve = defaults.force_curve;
+    particles.integrator = defaults.integrator;
+    particles.precision = defaults.precision;
+    particles.deterministic = defaults.deterministic;
+    particles.focus_point = defaults.focus_point;
+    particles.focus_strength = defaults.focus_strength;
+    //a loaded scene may have had more types than the defaults know about
+    let id_count = particles.id_count;
+    let count = particles.active_particles.len();
+    particles.active_particles.retain(|particle| particle.id < id_count);
+    particles.past_particles.clear();
+    count - particles.active_particles.len()
+}
+
+//creating a range from 0 to count with types below id_count
+fn generate_particles(extents: cgmath::Vector3<f32>, count: usize, id_count: u32) -> Vec<Particle> {
     (0..count)
         .into_par_iter()//speed up processing
         .map_init(
             || rand::thread_rng(),//creating a random number generator for each thread
-            |rng, _| {
-                let half_size = world_size * 0.5;//calculate half of world size for positioning
-                let position = cgmath::Vector3::new(
-                    rng.gen_range(-half_size..=half_size),//random X position
-                    rng.gen_range(-half_size..=half_size),
-                    rng.gen_range(-half_size..=half_size),
-                );
-                //starting with no movement
-                let velocity = cgmath::Vector3::new(0.0, 0.0, 0.0); 
-                //assigning a random type ID
-                let id = rng.gen_range(0..MAX_PARTICLE_TYPES) as u32;
-                
-                Particle {//storing generated values
-                    position,
-                    velocity,
-                    id,
-                }
-                
-            },
+            |rng, _| particle_3d::random_particle_in_box(rng, extents, id_count),
         )
         .collect()//get all generated particles into a vector and return
 
 }
 
+//placing exactly counts[id] particles of each type at random positions
+fn generate_particles_with_composition(extents: cgmath::Vector3<f32>, counts: &[usize]) -> Vec<Particle> {
+    let ids: Vec<u32> = counts
+        .iter()
+        .enumerate()
+        .flat_map(|(id, &count)| std::iter::repeat(id as u32).take(count))
+        .collect();
+    let mut particles = generate_particles(extents, ids.len(), counts.len() as u32);
+    //keeping the random positions but overriding the random types
+    particles
+        .par_iter_mut()
+        .zip(ids.par_iter())
+        .for_each(|(particle, &id)| particle.id = id);
+    particles
+}
+
 #[derive(ShaderType)]
 struct GpuParticles<'a> {
-    //simulation size
-    pub world_size: f32,
+    //simulation box edge lengths
+    pub world_extents: cgmath::Vector3<f32>,
     pub length: ArrayLength,//active particles size
     #[size(runtime)]
     pub particles: &'a [Particle],//storing particle data in compatible with gpu
 }
 
-#[derive(ShaderType)]
-struct GpuColors<'a> {
-    pub length: ArrayLength,//no. of colors available for particles
-    #[size(runtime)]
-    pub particles: &'a [cgmath::Vector3<f32>],//storing color data for each particle
-}
+//the particle buffer contents for these particles
+fn particle_buffer_data(world_extents: cgmath::Vector3<f32>, particles: &[Particle]) -> Vec<u8> {
+    let mut particles_storage = StorageBuffer::new(vec![]);
+    particles_storage
+        .write(&GpuParticles {
+            world_extents,
+            length: ArrayLength,
+            particles,
+        })
+        .unwrap();
+    particles_storage.into_inner()
+}
+
+#[derive(ShaderType)]
+struct GpuDrawOrder<'a> {
+    pub length: ArrayLength,//0 draws particles in their own order
+    #[size(runtime)]
+    pub indices: &'a [u32],//particle index drawn by each instance
+}
+
+#[derive(ShaderType)]
+struct GpuColors<'a> {
+    pub alpha: f32,//opacity applied to every particle
+    pub length: ArrayLength,//no. of colors available for particles
+    #[size(runtime)]
+    pub particles: &'a [GpuTypeStyle],//storing color and render config for each particle type
+}
+
+#[derive(ShaderType)]
+struct GpuColorMode {
+    pub mode: u32,//0 = by type, 1 = by speed, 2 = by id (see `ColorMode::shader_mode`)
+    pub min_speed: f32,//speed drawn with the slow color
+    pub max_speed: f32,//speed drawn with the fast color
+    pub slow_color: cgmath::Vector3<f32>,//gradient start, already in the surface's color space
+    pub fast_color: cgmath::Vector3<f32>,//gradient end
+}
+
+#[derive(ShaderType)]
+struct GpuCamera {
+    pub view_matrix: cgmath::Matrix4<f32>,//camera's view transformation
+    pub projection_matrix: cgmath::Matrix4<f32>,//camera's projection transformation
+    pub frustum_planes: [cgmath::Vector4<f32>; 6],//normalized planes for culling particles
+    pub cull_enabled: u32,//1 = skip particles outside the frustum in the vertex shader
+    pub slab_plane: cgmath::Vector4<f32>,//(normal, -offset) of the cross-section center plane
+    pub slab_half_thickness: f32,//particles farther than this from the plane are hidden
+    pub slab_enabled: u32,//1 = only draw particles inside the slab
+}
+
+//shape instanced for every particle
+#[derive(Clone, Copy, PartialEq)]
+enum Glyph {
+    Quad,//camera facing disc (the classic look)
+    Hexagon,//camera facing disc on a tighter fitting hexagon, fewer discarded fragments
+    Octagon,//tighter still, at the cost of a few more triangles
+    Tetrahedron,
+    Cube,
+}
+
+//corners of a regular polygon around the disc of diameter 1 (center vertex first),
+//pushed out so the edges just touch the disc instead of cutting into it
+fn billboard_polygon(corners: usize) -> Vec<[f32; 3]> {
+    let radius = 0.5 / (std::f32::consts::PI / corners as f32).cos();
+    std::iter::once([0.0, 0.0, 0.0])
+        .chain((0..corners).map(|i| {
+            let angle = std::f32::consts::TAU * i as f32 / corners as f32;
+            [radius * angle.cos(), radius * angle.sin(), 0.0]
+        }))
+        .collect()
+}
+
+//triangle fan around the center vertex of `billboard_polygon`
+fn billboard_polygon_indices(corners: usize) -> Vec<u16> {
+    (0..corners)
+        .flat_map(|i| [0, 1 + i as u16, 1 + ((i + 1) % corners) as u16])
+        .collect()
+}
+
+impl Glyph {
+    const ALL: [Glyph; 5] = [Glyph::Quad, Glyph::Hexagon, Glyph::Octagon, Glyph::Tetrahedron, Glyph::Cube];
+
+    //flat glyphs face the camera and get cut into discs
+    fn is_billboard(self) -> bool {
+        matches!(self, Glyph::Quad | Glyph::Hexagon | Glyph::Octagon)
+    }
+
+    //glyph-local vertex positions, about one unit across
+    fn vertices(self) -> Vec<[f32; 3]> {
+        match self {
+            Glyph::Quad => vec![
+                [-0.5, -0.5, 0.0],
+                [0.5, -0.5, 0.0],
+                [-0.5, 0.5, 0.0],
+                [0.5, 0.5, 0.0],
+            ],
+            Glyph::Hexagon => billboard_polygon(6),
+            Glyph::Octagon => billboard_polygon(8),
+            Glyph::Tetrahedron => vec![
+                [0.35, 0.35, 0.35],
+                [0.35, -0.35, -0.35],
+                [-0.35, 0.35, -0.35],
+                [-0.35, -0.35, 0.35],
+            ],
+            //corner i has bit 0/1/2 set for +x/+y/+z
+            Glyph::Cube => (0..8)
+                .map(|i| {
+                    let axis = |bit: usize| if i & bit == 0 { -0.35 } else { 0.35 };
+                    [axis(1), axis(2), axis(4)]
+                })
+                .collect(),
+        }
+    }
+
+    //triangle list indices into `vertices`
+    fn indices(self) -> Vec<u16> {
+        match self {
+            Glyph::Quad => vec![0, 1, 2, 2, 1, 3],
+            Glyph::Hexagon => billboard_polygon_indices(6),
+            Glyph::Octagon => billboard_polygon_indices(8),
+            Glyph::Tetrahedron => vec![0, 1, 2, 0, 3, 1, 0, 2, 3, 1, 3, 2],
+            Glyph::Cube => vec![
+                0, 2, 1, 1, 2, 3, //-z
+                4, 5, 6, 5, 7, 6, //+z
+                0, 1, 4, 1, 5, 4, //-y
+                2, 6, 3, 3, 6, 7, //+y
+                0, 4, 2, 2, 4, 6, //-x
+                1, 3, 5, 3, 7, 5, //+x
+            ],
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Glyph::Quad => "Quad",
+            Glyph::Hexagon => "Hexagon",
+            Glyph::Octagon => "Octagon",
+            Glyph::Tetrahedron => "Tetrahedron",
+            Glyph::Cube => "Cube",
+        }
+    }
+}
+
+//how one particle type is drawn
+#[derive(Clone, Copy, PartialEq)]
+struct TypeRenderConfig {
+    glyph: Glyph,//shape instanced for this type
+    size: f32,//glyph size in world units
+    alpha: f32,//opacity multiplier, used when transparency is on
+    depth_write: bool,//false draws the type as haze that never hides others
+    trail_length: Option<usize>,//past positions in this type's trails, none uses the global length
+    trail_color: Option<[f32; 3]>,//srgb color this type's trails fade in, none uses the type color
+}
+
+impl Default for TypeRenderConfig {
+    fn default() -> Self {
+        Self {
+            glyph: Glyph::Quad,//flat discs
+            size: 0.1,
+            alpha: 1.0,
+            depth_write: true,//every type occludes
+            trail_length: None,//same trails for every type
+            trail_color: None,
+        }
+    }
+}
+
+//trail length and color of each type, falling back to the global length and the type's color
+fn trail_styles(
+    colors: &[cgmath::Vector3<f32>],
+    configs: &[TypeRenderConfig],
+    default_length: usize,
+) -> Vec<(usize, cgmath::Vector3<f32>)> {
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| {
+            let config = configs.get(i).copied().unwrap_or_default();
+            (
+                config.trail_length.unwrap_or(default_length).min(MAX_TRAIL_LENGTH),
+                config.trail_color.map_or(color, cgmath::Vector3::from),
+            )
+        })
+        .collect()
+}
+
+//opacity of a trail point `steps_back` steps before the newest one, transparent at the oldest
+//point of a trail with `segments` segments up to opaque at the newest
+fn trail_fade(steps_back: usize, segments: usize) -> f32 {
+    1.0 - steps_back as f32 / segments as f32
+}
+
+//one fading line segment (position and rgba per end) per particle between each pair of recorded
+//steps, each type going back as many steps as its trail length allows
+fn trail_vertices(
+    history: &std::collections::VecDeque<(Vec<cgmath::Vector3<f32>>, Vec<bool>)>,
+    particles: &[Particle],
+    styles: &[(usize, cgmath::Vector3<f32>)],
+) -> Vec<f32> {
+    let mut vertices = vec![];
+    let newest = history.len().saturating_sub(1);
+    for (age, ((older, _), (newer, wrapped))) in history.iter().zip(history.iter().skip(1)).enumerate() {
+        let steps_back = newest - age;
+        for (((start, end), particle), &wrapped) in older.iter().zip(newer).zip(particles).zip(wrapped) {
+            let Some(&(length, color)) = styles.get(particle.id as usize) else { continue };
+            let segments = length.min(history.len()).saturating_sub(1);
+            //a wrap around isn't motion, so the trail breaks there instead of crossing the world
+            if steps_back > segments || wrapped {
+                continue;
+            }
+            let (start_alpha, end_alpha) = (trail_fade(steps_back, segments), trail_fade(steps_back - 1, segments));
+            vertices.extend([start.x, start.y, start.z, color.x, color.y, color.z, start_alpha]);
+            vertices.extend([end.x, end.y, end.z, color.x, color.y, color.z, end_alpha]);
+        }
+    }
+    vertices
+}
+
+//per type color and render config as the shader reads it, indexed by particle id
+#[derive(ShaderType)]
+struct GpuTypeStyle {
+    pub color: cgmath::Vector3<f32>,
+    pub size: f32,
+    pub alpha: f32,
+    pub billboard: u32,//1 = camera facing disc glyph, 0 = solid mesh
+}
+
+//packing each type's color and render config for the per type buffer (missing configs use defaults)
+fn pack_type_styles(colors: &[cgmath::Vector3<f32>], configs: &[TypeRenderConfig]) -> Vec<GpuTypeStyle> {
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| {
+            let config = configs.get(i).copied().unwrap_or_default();
+            GpuTypeStyle {
+                color,
+                size: config.size,
+                alpha: config.alpha,
+                billboard: config.glyph.is_billboard() as u32,
+            }
+        })
+        .collect()
+}
+
+//particle instances drawn with one glyph mesh and one pipeline
+struct RenderBatch {
+    glyph: Glyph,
+    depth_write: bool,
+    instances: std::ops::Range<u32>,
+}
+
+//grouping particles into draw batches by depth writing and glyph, depth writing types first so
+//haze types are still hidden behind them, returns the order to draw in as indices into
+//`particles` (None = their own order) and the batches as ranges of that order, when every
+//particle is drawn the same way that's a single batch and nothing is sorted
+//(the sort is stable, so a back to front `order` survives inside each batch)
+fn render_batches(
+    particles: &[Particle],
+    order: Option<Vec<u32>>,
+    configs: &[TypeRenderConfig],
+) -> (Option<Vec<u32>>, Vec<RenderBatch>) {
+    let config_of = |id: u32| configs.get(id as usize).copied().unwrap_or_default();
+    let key = |config: TypeRenderConfig| (!config.depth_write, config.glyph as usize);
+    let Some(first) = particles.first().map(|particle| config_of(particle.id)) else {
+        return (order, vec![]);
+    };
+    if particles.par_iter().all(|particle| key(config_of(particle.id)) == key(first)) {
+        let batch = RenderBatch {
+            glyph: first.glyph,
+            depth_write: first.depth_write,
+            instances: 0..particles.len() as u32,
+        };
+        return (order, vec![batch]);
+    }
+
+    let mut order = order.unwrap_or_else(|| (0..particles.len() as u32).collect());
+    order.par_sort_by_key(|&index| key(config_of(particles[index as usize].id)));
+
+    let mut batches: Vec<RenderBatch> = vec![];
+    for (i, &index) in order.iter().enumerate() {
+        let config = config_of(particles[index as usize].id);
+        let i = i as u32;
+        match batches.last_mut() {
+            Some(batch) if batch.glyph == config.glyph && batch.depth_write == config.depth_write => {
+                batch.instances.end = i + 1;
+            }
+            _ => batches.push(RenderBatch {
+                glyph: config.glyph,
+                depth_write: config.depth_write,
+                instances: i..i + 1,
+            }),
+        }
+    }
+    (Some(order), batches)
+}
+
+//what decides each particle's color
+#[derive(Clone, Copy, PartialEq)]
+enum ColorMode {
+    ByType,//the type's color from the colors table
+    BySpeed,//position of the speed inside the speed range along the slow to fast gradient
+    ById,//pseudo random color per particle index, whatever order particles are drawn in
+}
+
+impl ColorMode {
+    const ALL: [ColorMode; 3] = [ColorMode::ByType, ColorMode::BySpeed, ColorMode::ById];
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorMode::ByType => "By Type",
+            ColorMode::BySpeed => "By Speed",
+            ColorMode::ById => "By Id",
+        }
+    }
+
+    //value the fragment shader branches on
+    fn shader_mode(self) -> u32 {
+        match self {
+            ColorMode::ByType => 0,
+            ColorMode::BySpeed => 1,
+            ColorMode::ById => 2,
+        }
+    }
+}
+
+//what the debug text overlay shows next to each particle
+#[derive(Clone, Copy, PartialEq)]
+enum LabelMode {
+    Index,//position in the particle list, the closest thing to a particle uid
+    Type,//particle type index
+}
+
+//projecting a world point to a pixel position inside `rect`, None if it's behind the camera or
+//off screen
+fn world_to_screen(
+    view_projection: cgmath::Matrix4<f32>,
+    point: cgmath::Vector3<f32>,
+    rect: egui::Rect,
+) -> Option<egui::Pos2> {
+    let clip = view_projection * point.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+    if ndc.x.abs() > 1.0 || ndc.y.abs() > 1.0 || ndc.z.abs() > 1.0 {
+        return None;
+    }
+    //ndc y points up, screen y points down
+    Some(egui::pos2(
+        rect.left() + (ndc.x + 1.0) * 0.5 * rect.width(),
+        rect.top() + (1.0 - ndc.y) * 0.5 * rect.height(),
+    ))
+}
+
+//plane through `center` along one world axis (0 = x, 1 = y, 2 = z) as (normal, distance)
+fn slab_clip_plane(axis: usize, center: f32) -> cgmath::Vector4<f32> {
+    let mut plane = cgmath::vec4(0.0, 0.0, 0.0, -center);
+    plane[axis] = 1.0;
+    plane
+}
+
+//ordering particle indices from farthest to nearest along the view direction (for alpha blending)
+fn depth_sorted_indices(
+    particles: &[Particle],
+    eye: cgmath::Vector3<f32>,
+    forward: cgmath::Vector3<f32>,
+) -> Vec<u32> {
+    let depths: Vec<f32> = particles
+        .par_iter()
+        .map(|particle| (particle.position - eye).dot(forward))
+        .collect();
+    let mut indices: Vec<u32> = (0..particles.len() as u32).collect();
+    indices.par_sort_unstable_by(|&a, &b| depths[b as usize].total_cmp(&depths[a as usize]));
+    indices
+}
+
+//pulling the 6 frustum planes out of a projection * view matrix (gribb-hartmann method)
+//each plane is (normal, distance) with the normal pointing inside and normalized to unit length
+fn frustum_planes(view_projection: cgmath::Matrix4<f32>) -> [cgmath::Vector4<f32>; 6] {
+    let row = |i: usize| view_projection.row(i);
+    let planes = [
+        row(3) + row(0),//left
+        row(3) - row(0),//right
+        row(3) + row(1),//bottom
+        row(3) - row(1),//top
+        row(3) + row(2),//near
+        row(3) - row(2),//far
+    ];
+    planes.map(|plane| plane / plane.truncate().magnitude())
+}
+
+struct SimulationApp {
+    particles: Particles,//holding all particle data and behavior
+    camera: CameraSystem,//handling the 3D camera view
+    camera_animation: Option<CameraAnimation>,//flight in progress, cancelled by manual input
+    last_time: std::time::Instant, //tracking when the last frame was processed
+    fixed_time: std::time::Duration,//accumulated time for physics updates
+    update_rate: f32,//how many physics updates per second
+    window: bool,//controls if settings window is shown
+    smooth_rendering: bool,//blending between physics steps when drawing
+    paused: bool,//stops physics updates while still rendering
+    use_gpu_physics: bool,//stepping with the compute shader instead of the cpu solver
+    adaptive_substeps: bool,//splitting steps so fast particles can't skip past neighbors
+    last_substeps: usize,//substeps the last physics step took
+    render_state: eframe::egui_wgpu::RenderState,//device, queue and the renderer gpu physics steps through
+    gpu_upload: bool,//the cpu particles were edited, gpu physics has to start over from them
+    gpu_generation: u64,//uploads so far, readbacks from before the last one are stale
+    gpu_unread_steps: bool,//gpu steps ran since the last readback was requested
+    sim_error: Option<String>,//why the last physics step was skipped, cleared once one runs
+    scrubber: Scrubber,//stepping with held keys while paused
+    scrub_history: ScrubHistory,//states before each scrubbed step
+    show_grid: bool,//drawing occupied spatial hash cells as wireframe boxes
+    trail_length: usize,//past positions drawn behind each particle (0 = no trails)
+    trail_history: std::collections::VecDeque<(Vec<cgmath::Vector3<f32>>, Vec<bool>)>,//positions and wrap flags after each recent step, oldest first
+    show_border: bool,//drawing the border, separate from the physics boundary mode
+    clamp_camera: bool,//keeping the camera from drifting far away from the scene
+    look_with_right_button: bool,//only turning the camera while the right mouse button drags
+    frustum_culling: bool,//skipping off-screen particles on the gpu
+    controls_placement: ControlsPlacement,//side panel, or a floating window so the 3d view fills the screen
+    freeze_view: bool,//stopping gpu uploads and drawing to measure the solver alone
+    focus_gravity: bool,//pulling particles towards the point in front of the camera
+    focus_distance: f32,//how far in front of the camera the focus point is
+    brush_radius: f32,//size of the sphere the brush adds particles to or removes them from
+    brush_count: usize,//particles added per brush click
+    brush_type: u32,//type of the particles the brush adds
+    brush_distance: f32,//how far in front of the camera the brush sits
+    slab_enabled: bool,//only rendering a cross-section of the volume
+    slab_axis: usize,//axis the slab is perpendicular to (0 = x, 1 = y, 2 = z)
+    slab_center: f32,//slab position along its axis
+    slab_thickness: f32,//slab width
+    type_render: Vec<TypeRenderConfig>,//shape, size, alpha and depth writing per type
+    show_labels: bool,//debug text overlay with each particle's index or type
+    label_mode: LabelMode,
+    label_limit: usize,//most labels drawn, more just clutters the view
+    transparency: bool,//blending particles back to front instead of drawing them opaque
+    particle_alpha: f32,//opacity used when transparency is on
+    color_mode: ColorMode,
+    speed_range: (f32, f32),//speeds mapped to the ends of the gradient in speed color mode
+    slow_color: [f32; 3],//srgb gradient color for slow particles
+    fast_color: [f32; 3],//srgb gradient color for fast particles
+    repaint_mode: RepaintMode,//trading smoothness for power use
+    target_fps: f32,//fps cap used by the capped repaint mode
+    scene_path: String,//file used by scene save/load
+    scene_status: String,//result of the last scene save/load
+    spawn_path: String,//json list of per type spawn regions
+    preset_path: String,//toml file used by preset save/load
+    step_rate: StepRateCounter,//measuring actual physics steps per second
+    step_stats: StepStats,//total steps and time spent in physics
+    energy_readout: (f32, f32),//total kinetic energy and average speed, sampled once a second
+    neighbor_readout: f32,//average neighbors per particle, sampled with energy_readout
+    energy_sampled: std::time::Instant,//when energy_readout was last refreshed
+    steady_state: SteadyStateDetector,//watching the kinetic energy settle
+    pause_when_steady: bool,//auto pausing once the system settles
+    seed: u64,//seed used by "Reset Particles"
+    particle_seed: Option<u64>,//seed the current particles were generated from, if any
+    stir_strength: f32,//rms speed added by the stir button
+    stir_seed: u64,//seed of the next stirring field
+    fixed_composition: bool,//regenerating with exact per-type counts instead of random types
+    composition: Vec<usize>,//how many particles of each type to generate
+    sample_count: u32,//msaa samples the renderer was created with
+    supported_sample_counts: Vec<u32>,//msaa sample counts the adapter can draw the scene with
+    target_is_srgb: bool,//surface encodes to srgb itself, so shaders must output linear colors
+    requested_sample_count: u32,//msaa samples the renderer is rebuilt with on the next frame
+    gpu_info: Vec<String>,//adapter and limits captured at startup
+}
+
+//one click bundles of view settings trading speed for looks, the particles are left alone
+#[derive(Clone, Copy, PartialEq)]
+enum QualityPreset {
+    Performance,//no msaa, 30 tps, no interpolation
+    Balanced,//4x msaa, 60 tps, no interpolation
+    Quality,//4x msaa, 120 tps, smooth rendering
+}
+
+//every field a preset governs
+#[derive(Debug, PartialEq)]
+struct PresetSettings {
+    sample_count: u32,//rounded down to what the adapter supports when applied
+    update_rate: f32,
+    smooth_rendering: bool,
+    frustum_culling: bool,
+}
+
+impl QualityPreset {
+    //the documented values of every field this preset governs
+    fn settings(self) -> PresetSettings {
+        let (sample_count, update_rate, smooth_rendering) = match self {
+            QualityPreset::Performance => (1, 30.0, false),
+            QualityPreset::Balanced => (4, 60.0, false),
+            QualityPreset::Quality => (4, 120.0, true),
+        };
+        PresetSettings {
+            sample_count,
+            update_rate,
+            smooth_rendering,
+            frustum_culling: true,
+        }
+    }
+
+    //setting every field the preset governs at once
+    fn apply(self, app: &mut SimulationApp) {
+        let settings = self.settings();
+        app.requested_sample_count = clamp_sample_count(settings.sample_count, &app.supported_sample_counts);
+        app.update_rate = settings.update_rate;
+        app.smooth_rendering = settings.smooth_rendering;
+        app.frustum_culling = settings.frustum_culling;
+    }
+}
+
+impl SimulationApp {
+    //restoring every tunable to `default_particles` without touching the particles themselves
+    fn reset_parameters(&mut self) {
+        let dropped = reset_tunables(&mut self.particles);
+        self.update_rate = DEFAULT_UPDATE_RATE;
+        self.particles_edited();
+        //telling the user where the particles of the types that no longer exist went
+        if dropped > 0 {
+            self.scene_status = format!(
+                "Reset removed {} particles of types beyond the default {}",
+                dropped, self.particles.id_count
+            );
+        }
+    }
+
+    //writing the current tunables (no particles) to a preset file
+    fn save_preset(&self, path: &str) -> Result<(), particle_3d::SimError> {
+        self.particles.to_params(self.update_rate).save(path)
+    }
+
+    //switching to a preset's tunables, the particles stay where they are
+    fn load_preset(&mut self, path: &str) -> Result<(), particle_3d::SimError> {
+        let params = SimParams::load(path)?;
+        self.particles.apply_params(&params);
+        self.update_rate = params.update_rate.clamp(1.0, 1000.0);
+        Ok(())
+    }
+
+    //one physics step with the cpu solver, or queued on the compute shader when gpu physics is on
+    fn physics_step(&mut self, ts: f32) -> Result<(), particle_3d::SimError> {
+        if self.use_gpu_physics {
+            self.gpu_step(ts)?;
+            self.last_substeps = 1;
+            //trails are recorded once the results are read back
+            return Ok(());
+        }
+        if self.adaptive_substeps {
+            self.last_substeps = self.particles.update_adaptive(ts)?;
+        } else {
+            self.particles.update(ts)?;
+            self.last_substeps = 1;
+        }
+        self.record_trail();
+        Ok(())
+    }
+
+    //replacing the renderer with one drawing at `requested_sample_count`, gpu physics particles
+    //live in the old renderer's buffers so they are read back first and uploaded again
+    fn rebuild_renderer(&mut self) {
+        if self.use_gpu_physics {
+            self.sync_gpu_physics();
+        }
+        let renderer = Renderer::new(
+            &self.render_state,
+            self.requested_sample_count,
+            self.particles.active_particles.len(),
+        );
+        self.render_state
+            .renderer
+            .write()
+            .paint_callback_resources
+            .insert(renderer);
+        self.sample_count = self.requested_sample_count;
+        self.particles_edited();
+    }
+
+    //the cpu particles were changed outside a physics step, gpu physics picks them up before its
+    //next step (and the renderer draws them until then)
+    fn particles_edited(&mut self) {
+        self.gpu_upload = true;
+    }
+
+    //queueing one step on the gpu without waiting for it, the cpu particles only catch up when a
+    //readback arrives, so edits made in between work on particles up to a frame old
+    fn gpu_step(&mut self, ts: f32) -> Result<(), particle_3d::SimError> {
+        self.particles.validate()?;
+        let (device, queue) = (&self.render_state.device, &self.render_state.queue);
+        let mut egui_renderer = self.render_state.renderer.write();
+        let renderer: &mut Renderer = egui_renderer.paint_callback_resources.get_mut().unwrap();
+        if std::mem::take(&mut self.gpu_upload) {
+            let data = particle_buffer_data(self.particles.world_extents(), &self.particles.active_particles);
+            renderer.upload_particles(device, queue, &data);
+            self.gpu_generation += 1;
+        }
+        renderer.physics.step(device, queue, &renderer.particles_storage_buffer, &self.particles, ts);
+        self.particles.sim_time += ts;
+        self.gpu_unread_steps = true;
+        Ok(())
+    }
+
+    //asking for the gpu particles, at most one readback is in flight at a time
+    fn request_gpu_readback(&mut self) {
+        //pending edits replace whatever is on the gpu anyway
+        if self.gpu_upload {
+            return;
+        }
+        let (device, queue) = (&self.render_state.device, &self.render_state.queue);
+        let mut egui_renderer = self.render_state.renderer.write();
+        let renderer: &mut Renderer = egui_renderer.paint_callback_resources.get_mut().unwrap();
+        let count = self.particles.active_particles.len();
+        if renderer.physics.request_readback(device, queue, &renderer.particles_storage_buffer, count, self.gpu_generation) {
+            self.gpu_unread_steps = false;
+        }
+    }
+
+    //taking over the requested readback if it has arrived (`wait` blocks until it has), returns
+    //false when there is nothing to take over
+    fn poll_gpu_readback(&mut self, wait: bool) -> bool {
+        let readback = {
+            let mut egui_renderer = self.render_state.renderer.write();
+            let renderer: &mut Renderer = egui_renderer.paint_callback_resources.get_mut().unwrap();
+            renderer.physics.poll_readback(&self.render_state.device, wait)
+        };
+        let Some(readback) = readback else {
+            return false;
+        };
+        //particles edited since the readback was asked for win over it
+        if readback.generation != self.gpu_generation || self.gpu_upload {
+            return true;
+        }
+        //keeping the same bookkeeping as a cpu step
+        let particles = &mut self.particles;
+        particles.past_particles = std::mem::replace(&mut particles.active_particles, readback.particles);
+        particles.previous_accelerations.clear();
+        particles.wrapped = readback.wrapped;
+        particles.energy_absorbed_by_walls = readback.energy_absorbed_by_walls;
+        if particles.collect_stats {
+            particles.step_stats = readback.step_stats;
+        }
+        //the shader leaves escaped particles where they are, removing them here means uploading again
+        if particles.boundary == BoundaryMode::Open {
+            let inside: Vec<bool> = particles
+                .active_particles
+                .iter()
+                .map(|particle| particles.contains(particle.position))
+                .collect();
+            if inside.contains(&false) {
+                let mut keep = inside.iter();
+                particles.active_particles.retain(|_| *keep.next().unwrap());
+                let mut keep = inside.iter();
+                particles.wrapped.retain(|_| *keep.next().unwrap());
+                self.gpu_upload = true;
+            }
+        }
+        self.record_trail();
+        self.observe_steady_state();
+        true
+    }
+
+    //blocking until the cpu particles include every gpu step so far, for turning gpu physics off
+    //and for scrubbing, where each step has to be seen
+    fn sync_gpu_physics(&mut self) {
+        self.poll_gpu_readback(true);
+        if self.gpu_unread_steps {
+            self.request_gpu_readback();
+            self.poll_gpu_readback(true);
+        }
+    }
+
+    //feeding the steady state detector, pausing on the step steady state is reached (so
+    //resuming keeps running), true if it paused
+    fn observe_steady_state(&mut self) -> bool {
+        let was_steady = self.steady_state.is_steady();
+        let steady = self.steady_state.observe(self.particles.kinetic_energy());
+        if steady && !was_steady && self.pause_when_steady {
+            self.paused = true;
+            return true;
+        }
+        false
+    }
+
+    //positions the longest trail of any type in use needs
+    fn longest_trail(&self) -> usize {
+        trail_styles(&self.particles.colors, &self.type_render, self.trail_length)
+            .into_iter()
+            .take(self.particles.id_count as usize)
+            .map(|(length, _)| length)
+            .max()
+            .unwrap_or(0)
+    }
+
+    //remembering where every particle is for the trails, starting over when particles were
+    //added or removed since positions are matched up by index
+    fn record_trail(&mut self) {
+        let longest = self.longest_trail();
+        if longest == 0 {
+            self.trail_history.clear();
+            return;
+        }
+        let count = self.particles.active_particles.len();
+        if self.trail_history.back().is_some_and(|(positions, _)| positions.len() != count) {
+            self.trail_history.clear();
+        }
+        let positions = self.particles.active_particles.iter().map(|p| p.position).collect();
+        let mut wrapped = self.particles.wrapped.clone();
+        wrapped.resize(count, false);
+        self.trail_history.push_back((positions, wrapped));
+        while self.trail_history.len() > longest {
+            self.trail_history.pop_front();
+        }
+    }
+
+    fn new(cc: &eframe::CreationContext) -> Self {
+        //creating a new particle system with initial settings and 1000 starting particles
+        let mut particles = default_particles();
+        particles.active_particles = generate_particles(particles.world_extents(), 1000, particles.id_count);
+
+        //setting up camera
+        let camera = CameraSystem {
+            position: cgmath::vec3(1.0, 0.0, particles.world_size * 1.6),//starting position
+            up: cgmath::vec3(0.0, 1.0, 0.0),// way is up
+            pitch: 0.0, //looking up/down angle
+            yaw: 0.0,//looking left/right angle
+            speed: SPEED,
+            level_horizon: true,//no roll by default
+            mode: CameraMode::Fps,//free flying like before
+        };
+
+        let render_state = cc.wgpu_render_state.as_ref().unwrap();
+        //the msaa level chosen last time, if the adapter can still do it
+        let supported_sample_counts = supported_sample_counts(render_state);
+        let sample_count = clamp_sample_count(
+            cc.storage
+                .and_then(|storage| eframe::get_value(storage, MSAA_STORAGE_KEY))
+                .unwrap_or(1),
+            &supported_sample_counts,
+        );
+
+        //main app with everything initialized
+        let app = Self {
+            particles,
+            camera,
+            camera_animation: None,
+            last_time: std::time::Instant::now(),//starting timing now
+            fixed_time: std::time::Duration::ZERO,//no accumulated time yet
+            update_rate: DEFAULT_UPDATE_RATE, //physics updates 60 times per second
+            window: false,//start with settings window closed
+            smooth_rendering: false,//drawing raw physics steps by default
+            paused: false,//running physics from the start
+            use_gpu_physics: false,//the cpu solver supports every feature
+            adaptive_substeps: false,//one update per step like before
+            last_substeps: 1,
+            render_state: render_state.clone(),
+            gpu_upload: true,//nothing is on the gpu yet
+            gpu_generation: 0,
+            gpu_unread_steps: false,
+            sim_error: None,
+            scrubber: Scrubber { rate: 10.0, pending: 0.0, last_direction: 0 },
+            scrub_history: ScrubHistory::new(SCRUB_HISTORY_BYTES),
+            show_grid: false,//grid overlay is a debug view so start hidden
+            trail_length: 0,//no trails until asked for
+            trail_history: Default::default(),
+            show_border: true,//border is visible regardless of boundary mode
+            clamp_camera: true,//stay near the scene by default
+            look_with_right_button: false,//any drag looks around
+            frustum_culling: true,//off-screen particles cost nothing to skip
+            controls_placement: ControlsPlacement::Docked,//docked side panel by default
+            freeze_view: false,
+            focus_gravity: false,//normal gravity only
+            focus_distance: 10.0,
+            brush_radius: 2.0,
+            brush_count: 100,
+            brush_type: 0,
+            brush_distance: 10.0,
+            slab_enabled: false,//showing the whole volume
+            slab_axis: 2,//z slab by default
+            slab_center: 0.0,
+            slab_thickness: 1.0,
+            type_render: vec![TypeRenderConfig::default(); MAX_PARTICLE_TYPES],
+            show_labels: false,
+            label_mode: LabelMode::Index,
+            label_limit: 100,
+            transparency: false,//opaque particles need no sorting
+            particle_alpha: 0.5,
+            color_mode: ColorMode::ByType,//the classic per type colors
+            speed_range: (0.0, 1.0),
+            slow_color: [0.0, 0.2, 1.0],//blue
+            fast_color: [1.0, 0.1, 0.0],//red
+            repaint_mode: RepaintMode::Continuous,//same as before, redraw every frame
+            target_fps: 60.0,
+            scene_path: "scene.json".to_string(),//default scene file next to the executable
+            scene_status: String::new(),
+            spawn_path: "spawn.json".to_string(),
+            preset_path: "preset.toml".to_string(),
+            step_rate: StepRateCounter::new(std::time::Duration::from_secs(1)),//one second window
+            step_stats: StepStats::default(),
+            energy_readout: (0.0, 0.0),
+            neighbor_readout: 0.0,
+            energy_sampled: std::time::Instant::now(),
+            steady_state: SteadyStateDetector::new(1e-3, 60),//a second of calm at 60 tps
+            pause_when_steady: false,
+            seed: 42,
+            particle_seed: None,//the starting particles are unseeded
+            stir_strength: 1.0,
+            stir_seed: 0,
+            fixed_composition: false,//random types like the count slider always did
+            composition: vec![200; MAX_PARTICLE_TYPES],//even split of the starting 1000
+            sample_count,
+            supported_sample_counts,
+            target_is_srgb: render_state.target_format.describe().srgb,
+            requested_sample_count: sample_count,
+            gpu_info: gpu_info(render_state),
+        };
+
+        //setting up the graphics renderer
+        let renderer = Renderer::new(render_state, sample_count, app.particles.active_particles.len());
+        render_state
+            .renderer
+            .write()
+            .paint_callback_resources
+            .insert(renderer);
+
+        app
+    }
+}
+
+
+impl SimulationApp {
+    //filling the controls panel (docked side panel or detached window)
+    fn controls_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        time: std::time::Instant,
+        ts: f32,
+        update_elapsed: std::time::Duration,
+    ) {
+        //show performance information
+        ui.label(format!("Render FPS: {:.3}", 1.0 / ts)); //frames per second
+        ui.label(format!("Frame Time: {:.3}ms", ts * 1000.0));//time per frame
+        //physics steps actually executed, not derived from the frame time
+        let physics_tps = self.step_rate.rate(time);
+        if self.paused {
+            ui.label("Physics: paused");
+        } else {
+            ui.label(format!("Physics TPS: {:.1}", physics_tps));
+        }
+        ui.label(format!(
+            "Updated Time: {:.3}ms",
+            update_elapsed.as_secs_f64() * 1000.0//time for physics
+        ));
+        //pair work per step, to see what the radius settings cost
+        ui.horizontal(|ui| {
+            ui.label("Interaction Stats: ");
+            ui.checkbox(&mut self.particles.collect_stats, "");
+        });
+        if self.particles.collect_stats {
+            let stats = self.particles.step_stats;
+            ui.label(format!("Pair Checks: {}", stats.pair_checks));
+            ui.label(format!("Interactions: {}", stats.effective_interactions));
+            ui.label(format!("Max Pair Force: {:.3}", stats.max_force_magnitude));
+        }
+        //explaining why physics isn't stepping instead of crashing
+        if let Some(error) = &self.sim_error {
+            ui.colored_label(ui.visuals().warn_fg_color, format!("Physics stopped: {}", error));
+        }
+
+        //cumulative physics cost for comparing settings and machines
+        ui.label(format!("Simulated Time: {:.3}s", self.particles.sim_time()));
+        ui.label(format!("Total Steps: {}", self.step_stats.steps));
+        ui.label(format!(
+            "Total Update Time: {:.3}s",
+            self.step_stats.total_time.as_secs_f64()
+        ));
+        ui.label(format!("Average Step: {:.3}ms", self.step_stats.average_ms()));
+        //energy readout for telling settling parameters from runaway ones, refreshed once a
+        //second so the numbers stay readable (and the neighbor count costs nothing per frame)
+        if time.duration_since(self.energy_sampled) >= std::time::Duration::from_secs(1) {
+            self.energy_readout = (
+                self.particles.kinetic_energy(),
+                self.particles.average_speed(),
+            );
+            //cost indicator: neighbors each particle checks against every step
+            self.neighbor_readout = self
+                .particles
+                .average_neighbor_count(self.particles.particle_effect_radius);
+            self.energy_sampled = time;
+        }
+        ui.label(format!("Kinetic Energy: {:.4}", self.energy_readout.0));
+        ui.label(format!("Average Speed: {:.4}", self.energy_readout.1));
+        if self.particles.boundary == BoundaryMode::Walls {
+            ui.label(format!("Wall Energy Loss: {:.4}", self.particles.energy_absorbed_by_walls));
+        }
+        ui.label(format!("Avg Neighbors: {:.1}", self.neighbor_readout));
+        if ui.button("Reset Stats").clicked() {
+            self.step_stats = StepStats::default();
+        }
+        
+        //moving the controls between the side panel and a floating window
+        if ui.button(self.controls_placement.toggle_label()).clicked() {
+            self.controls_placement = self.controls_placement.toggled();
+        }
+        
+        //going back to the startup parameters while keeping the current particles
+        if ui.button("Reset Parameters").clicked() {
+            self.reset_parameters();
+        }
+        
+        //picking a speed/quality tradeoff in one click
+        ui.horizontal(|ui| {
+            ui.label("Preset: ");
+            for (preset, name) in [
+                (QualityPreset::Performance, "Performance"),
+                (QualityPreset::Balanced, "Balanced"),
+                (QualityPreset::Quality, "Quality"),
+            ] {
+                if ui.button(name).clicked() {
+                    preset.apply(self);
+                }
+            }
+        });
+        
+        //slider to change number of particles
+        ui.horizontal(|ui| {
+            ui.label("Particle Count: ");
+            let mut particle_count = self.particles.active_particles.len();
+            //count comes from the per-type composition when it is fixed
+            if ui
+                .add_enabled(
+                    !self.fixed_composition,
+                    egui::DragValue::new(&mut particle_count).speed(0.1),
+                )
+                .changed()
+            {
+                let current_count = self.particles.active_particles.len();
+                self.particle_seed = None;
+                if particle_count < current_count {
+                    //remove particles if I decreased the count
+                    self.particles.active_particles.truncate(particle_count);
+                } else if particle_count > current_count {
+                    //add new particles if I increased the count
+                    let additional = particle_count - current_count;
+                    self.particles.active_particles.reserve(additional);
+                    let new_particles = generate_particles(
+                        self.particles.world_extents(),
+                        additional,
+                        self.particles.id_count,
+                    );
+                    self.particles.active_particles.extend(new_particles);
+                    self.particles.apply_dimensions();
+                }
+                self.particles_edited();
+            }
+        });
+        
+        //regenerating the same layout every time for reproducing bugs
+        ui.horizontal(|ui| {
+            ui.label("Seed: ");
+            ui.add(egui::DragValue::new(&mut self.seed));
+            //a fresh layout keeping every tuning, also dropping the old particles' verlet history
+            if ui.button("Reset Particles").clicked() {
+                self.particles.reseed(self.seed);
+                self.steady_state.reset();
+                self.particle_seed = Some(self.seed);
+                self.particles_edited();
+            }
+        });
+        
+        //holding the number of particles of each type fixed for controlled experiments
+        ui.horizontal(|ui| {
+            ui.label("Fixed Composition: ");
+            ui.checkbox(&mut self.fixed_composition, "");
+        });
+        if self.fixed_composition {
+            self.composition.resize(self.particles.id_count as usize, 0);
+            ui.horizontal(|ui| {
+                for count in self.composition.iter_mut() {
+                    ui.add(egui::DragValue::new(count).speed(0.1));
+                }
+            });
+            if ui.button("Regenerate").clicked() {
+                self.particle_seed = None;
+                self.particles.active_particles = generate_particles_with_composition(
+                    self.particles.world_extents(),
+                    &self.composition,
+                );
+                self.particles.apply_dimensions();
+                self.particles.past_particles.clear();
+                self.particles.sim_time = 0.0;
+                self.steady_state.reset();
+                self.particles_edited();
+            }
+        }
+        
+        //controlling for simulation boundary size
+        ui.horizontal(|ui| {
+            ui.label("Simulation Boundary: ");
+            ui.add(egui::DragValue::new(&mut self.particles.world_size).speed(0.1));
+            //making sure the world is at least big enough for particle interactions
+            let aspect = self.particles.world_aspect;
+            let min_aspect = aspect.x.min(aspect.y).min(aspect.z);
+            self.particles.world_size = self
+                .particles
+                .world_size
+                .max(self.particles.particle_effect_radius * 2.0 / min_aspect.max(1e-3));
+        });
+        
+        //stretching the box per axis (1 = the full boundary size), e.g. into a tall thin tank
+        ui.horizontal(|ui| {
+            ui.label("Box Shape: ");
+            for (axis, name) in ["x: ", "y: ", "z: "].into_iter().enumerate() {
+                ui.add(
+                    egui::DragValue::new(&mut self.particles.world_aspect[axis])
+                        .prefix(name)
+                        .speed(0.01)
+                        .clamp_range(0.05..=1.0),
+                );
+            }
+        });
+        
+        //zooming the whole system (particles, radii and camera) without changing its look
+        ui.horizontal(|ui| {
+            ui.label("Scale World: ");
+            for (label, factor) in [("x0.5", 0.5), ("x0.8", 0.8), ("x1.25", 1.25), ("x2", 2.0)] {
+                if ui.button(label).clicked() && self.particles.scale_world(factor).is_ok() {
+                    self.camera.scale(factor);
+                    self.particles_edited();
+                }
+            }
+        });
+        
+        //kicking a settled system back into motion with a smooth random velocity field
+        ui.horizontal(|ui| {
+            ui.label("Stir: ");
+            ui.add(egui::Slider::new(&mut self.stir_strength, 0.0..=5.0));
+            if ui.button("Stir").clicked() {
+                self.particles.stir(self.stir_strength, self.stir_seed);
+                //a fresh field every click
+                self.stir_seed += 1;
+                self.steady_state.reset();
+                self.particles_edited();
+            }
+        });
+        
+        //controlling for physics update rate
+        ui.horizontal(|ui| {
+            ui.label("Update Rate (TPS): ");
+            ui.add(egui::Slider::new(&mut self.update_rate, 1.0..=1000.0));
+        });
+        
+        //keeping low update rates stable by splitting steps fast particles would tunnel through
+        ui.horizontal(|ui| {
+            ui.label("Adaptive Substeps: ");
+            ui.add_enabled(!self.use_gpu_physics, egui::Checkbox::new(&mut self.adaptive_substeps, ""))
+                .on_disabled_hover_text("gpu physics takes one step per update");
+            if self.adaptive_substeps {
+                ui.label(format!("last step: {}", self.last_substeps));
+            }
+        });
+        
+        //pausing and resuming the physics
+        ui.horizontal(|ui| {
+            ui.label("Paused: ");
+            ui.checkbox(&mut self.paused, "");
+        });
+        if self.paused {
+            ui.horizontal(|ui| {
+                ui.label("Scrub Rate (L/J): ");
+                ui.add(egui::Slider::new(&mut self.scrubber.rate, 1.0..=60.0).suffix(" steps/s"));
+            });
+        }
+        
+        //detecting when the system stops changing
+        ui.horizontal(|ui| {
+            ui.label("Pause When Steady: ");
+            ui.checkbox(&mut self.pause_when_steady, "");
+            ui.add(
+                egui::DragValue::new(&mut self.steady_state.threshold)
+                    .prefix("threshold: ")
+                    .speed(0.0001)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.steady_state.window)
+                    .prefix("frames: ")
+                    .clamp_range(1..=10000),
+            );
+        });
+        if self.steady_state.is_steady() {
+            ui.label("Steady state reached");
+        }
+        
+        //choosing how often to redraw to save power
+        ui.horizontal(|ui| {
+            ui.label("Repaint: ");
+            ui.radio_value(&mut self.repaint_mode, RepaintMode::Continuous, "Continuous");
+            ui.radio_value(&mut self.repaint_mode, RepaintMode::CappedFps, "Capped");
+            ui.radio_value(&mut self.repaint_mode, RepaintMode::WhenRunning, "When Running");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Target FPS: ");
+            ui.add(egui::Slider::new(&mut self.target_fps, 1.0..=240.0));
+        });
+        
+        //freezing the view to benchmark physics without gpu work
+        ui.horizontal(|ui| {
+            ui.label("Freeze View: ");
+            ui.checkbox(&mut self.freeze_view, "");
+        });
+        
+        //toggling for interpolated rendering between physics steps
+        ui.horizontal(|ui| {
+            ui.label("Smooth Rendering: ");
+            ui.checkbox(&mut self.smooth_rendering, "");
+        });
+        
+        //toggling for the spatial hash grid overlay
+        ui.horizontal(|ui| {
+            ui.label("Show Grid Cells: ");
+            ui.checkbox(&mut self.show_grid, "");
+        });
+        
+        //fading lines behind each particle showing where it came from
+        ui.horizontal(|ui| {
+            ui.label("Trail Length: ");
+            ui.add(egui::Slider::new(&mut self.trail_length, 0..=MAX_TRAIL_LENGTH));
+        });
+        
+        //toggling for see-through particles (sorted on the cpu every frame)
+        ui.horizontal(|ui| {
+            ui.label("Transparency: ");
+            ui.checkbox(&mut self.transparency, "");
+            ui.add_enabled(
+                self.transparency,
+                egui::Slider::new(&mut self.particle_alpha, 0.0..=1.0),
+            );
+        });
+        
+        //choosing what the particle colors show, applied on the next frame
+        ui.horizontal(|ui| {
+            ui.label("Color Mode: ");
+            egui::ComboBox::from_id_source("Color Mode")
+                .selected_text(self.color_mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in ColorMode::ALL {
+                        ui.selectable_value(&mut self.color_mode, mode, mode.name());
+                    }
+                });
+        });
+        if self.color_mode == ColorMode::BySpeed {
+            ui.horizontal(|ui| {
+                ui.label("Speed Range: ");
+                ui.add(
+                    egui::DragValue::new(&mut self.speed_range.0)
+                        .speed(0.01)
+                        .clamp_range(0.0..=self.speed_range.1),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut self.speed_range.1)
+                        .speed(0.01)
+                        .clamp_range(self.speed_range.0..=f32::MAX),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("Gradient: ");
+                srgb_color_edit(ui, &mut self.slow_color);
+                srgb_color_edit(ui, &mut self.fast_color);
+            });
+        }
+        
+        //choosing anti-aliasing out of what the adapter supports, applied on the next frame
+        ui.horizontal(|ui| {
+            ui.label("MSAA: ");
+            egui::ComboBox::from_id_source("MSAA")
+                .selected_text(format!("{}x", self.requested_sample_count))
+                .show_ui(ui, |ui| {
+                    for &count in &self.supported_sample_counts {
+                        ui.selectable_value(
+                            &mut self.requested_sample_count,
+                            count,
+                            format!("{}x", count),
+                        );
+                    }
+                });
+        });
+        
+        //showing only a slab of the volume to look inside dense clouds (rendering only)
+        ui.horizontal(|ui| {
+            ui.label("Cross Section: ");
+            ui.checkbox(&mut self.slab_enabled, "");
+            ui.radio_value(&mut self.slab_axis, 0, "X");
+            ui.radio_value(&mut self.slab_axis, 1, "Y");
+            ui.radio_value(&mut self.slab_axis, 2, "Z");
+        });
+        if self.slab_enabled {
+            let half_world = self.particles.world_size * 0.5;
+            ui.horizontal(|ui| {
+                ui.label("Slab Position: ");
+                ui.add(egui::Slider::new(&mut self.slab_center, -half_world..=half_world));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Slab Thickness: ");
+                ui.add(egui::Slider::new(&mut self.slab_thickness, 0.0..=self.particles.world_size));
+            });
+        }
+        
+        //flying the camera back until every particle is in view
+        if ui.button("Frame All").clicked() {
+            if let Some(to) = frame_all_pose(&self.camera, &self.particles.active_particles) {
+                self.camera_animation = Some(CameraAnimation {
+                    from: self.camera.pose(),
+                    to,
+                    elapsed: 0.0,
+                    duration: FRAME_ALL_DURATION,
+                });
+            }
+        }
+        
+        //moving the camera to look at the most crowded grid cell
+        if ui.button("Jump To Densest Region").clicked() {
+            let (cell, count) = self.particles.densest_cell();
+            if count > 0 {
+                let (min, max) = self.particles.cell_bounds(cell);
+                let (forward, _, _) = self.camera.calculate_axes();
+                //backing off along the view direction so the whole cell is in sight
+                let pose = self.camera.pose();
+                self.camera.set_pose(CameraPose {
+                    position: (min + max) * 0.5 - forward * (max - min).magnitude(),
+                    ..pose
+                });
+            }
+        }
+        
+        //text overlay for debugging small simulations
+        ui.horizontal(|ui| {
+            ui.label("Particle Labels: ");
+            ui.checkbox(&mut self.show_labels, "");
+            ui.radio_value(&mut self.label_mode, LabelMode::Index, "Index");
+            ui.radio_value(&mut self.label_mode, LabelMode::Type, "Type");
+            ui.add(
+                egui::DragValue::new(&mut self.label_limit)
+                    .prefix("max: ")
+                    .clamp_range(1..=1000),
+            );
+        });
+        
+        //toggling for gpu culling of particles outside the view
+        ui.horizontal(|ui| {
+            ui.label("Frustum Culling: ");
+            ui.checkbox(&mut self.frustum_culling, "");
+        });
+        
+        //flying freely or circling the world center (tab switches too)
+        ui.horizontal(|ui| {
+            ui.label("Camera Mode: ");
+            let orbiting = matches!(self.camera.mode, CameraMode::Orbit { .. });
+            let fly = ui.radio(!orbiting, "Fly").clicked();
+            let orbit = ui.radio(orbiting, "Orbit").clicked();
+            if (fly && orbiting) || (orbit && !orbiting) {
+                self.camera.toggle_mode();
+                self.camera_animation = None;
+            }
+            //circling the particles wherever they drifted to (F does the same)
+            if ui.button("Center on Mass").clicked() {
+                self.camera.orbit_around(orbit_pivot(&self.particles));
+                self.camera_animation = None;
+            }
+        });
+        
+        //toggling for a level horizon (free mode lets the camera roll)
+        ui.horizontal(|ui| {
+            ui.label("Keep Horizon Level: ");
+            ui.checkbox(&mut self.camera.level_horizon, "");
+        });
+        
+        //mouse look settings, right button only leaves left dragging free for other tools
+        ui.horizontal(|ui| {
+            ui.label("Look With Right Button Only: ");
+            ui.checkbox(&mut self.look_with_right_button, "");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Move Speed: ");
+            ui.add(egui::Slider::new(&mut self.camera.speed, 0.1..=100.0).logarithmic(true));
+        });
+        
+        //toggling for keeping the camera close to the scene
+        ui.horizontal(|ui| {
+            ui.label("Limit Camera Range: ");
+            ui.checkbox(&mut self.clamp_camera, "");
+        });
+        
+        //toggling for drawing the border (only visual, the boundary mode below controls physics)
+        ui.horizontal(|ui| {
+            ui.label("Show Border: ");
+            ui.checkbox(&mut self.show_border, "");
+        });
+        
+        //toggling for reproducible (but slower) physics
+        ui.horizontal(|ui| {
+            ui.label("Deterministic: ");
+            ui.checkbox(&mut self.particles.deterministic, "");
+        });
+        
+        //averaging each pair's attraction both ways so momentum is conserved
+        ui.horizontal(|ui| {
+            ui.label("Symmetric Forces: ");
+            ui.checkbox(&mut self.particles.symmetric_forces, "");
+        });
+        
+        //brute force compute shader, only the built-in force shape and semi-implicit euler
+        ui.horizontal(|ui| {
+            ui.label("GPU Physics: ");
+            let was_on = self.use_gpu_physics;
+            ui.checkbox(&mut self.use_gpu_physics, "")
+                .on_hover_text("no force curves, flow fields, focus pull, hard separation or smooth rendering");
+            if was_on && !self.use_gpu_physics {
+                //the cpu solver carries on from the last gpu step
+                self.sync_gpu_physics();
+            } else if !was_on && self.use_gpu_physics {
+                self.particles_edited();
+            }
+        });
+        
+        //the compute shader always steps in f32 with semi-implicit euler
+        ui.add_enabled_ui(!self.use_gpu_physics, |ui| {
+            //trading speed for accuracy in long or stiff runs
+            ui.horizontal(|ui| {
+                ui.label("Solver Precision: ");
+                ui.radio_value(&mut self.particles.precision, Precision::Single, "f32");
+                ui.radio_value(&mut self.particles.precision, Precision::Double, "f64");
+            });
+            
+            //picking a time stepping scheme, verlet copes better with stiff matrices at larger steps
+            ui.horizontal(|ui| {
+                ui.label("Integrator: ");
+                let integrator = &mut self.particles.integrator;
+                ui.radio_value(integrator, Integrator::ExplicitEuler, "Euler");
+                ui.radio_value(integrator, Integrator::SemiImplicitEuler, "Semi-Implicit");
+                ui.radio_value(integrator, Integrator::VelocityVerlet, "Verlet");
+            });
+        })
+        .response
+        .on_disabled_hover_text("gpu physics always uses f32 semi-implicit euler");
+        
+        //choosing what happens at the edge of the world
+        ui.horizontal(|ui| {
+            ui.label("Boundary: ");
+            ui.radio_value(&mut self.particles.boundary, BoundaryMode::Wrap, "Wrap");
+            ui.radio_value(&mut self.particles.boundary, BoundaryMode::Walls, "Solid Walls");//bounce off walls
+            ui.radio_value(&mut self.particles.boundary, BoundaryMode::Open, "Open");//remove escaping particles
+        });
+        if self.particles.boundary == BoundaryMode::Walls {
+            //how bouncy the walls are
+            ui.horizontal(|ui| {
+                ui.label("Restitution: ");
+                ui.add(egui::Slider::new(&mut self.particles.restitution, 0.0..=1.0));
+            });
+        }
+        
+        //controlling for how far particles can affect each other
+        ui.horizontal(|ui| {
+            ui.label("Effect Radius: ");
+            ui.add(egui::Slider::new(
+                &mut self.particles.particle_effect_radius,
+                0.0..=self.particles.world_size / 2.0,
+            ));
+        });
+        
+        //controlling for strength of particle interactions
+        ui.horizontal(|ui| {
+            ui.label("Interaction Scale Rate: ");
+            ui.add(egui::Slider::new(
+                &mut self.particles.interaction_force,
+                0.0..=10.0,
+            ));
+        });
+        
+        //toggling for friction
+        ui.horizontal(|ui| {
+            ui.label("Drag (Friction): ");
+            ui.add(
+                egui::Slider::new(&mut self.particles.coefficient, 0.0..=1.0)
+                    .drag_value_speed(0.01),
+            );
+        });
+        
+        //controlling for when to push vs pull particles
+        ui.horizontal(|ui| {
+            ui.label("Repulsion Threshold: ");
+            ui.add(egui::Slider::new(
+                &mut self.particles.min_pull_ratio,
+                0.0..=1.0,
+            ));
+        });
+        
+        //hard-core separation so dense packings never fully overlap
+        ui.horizontal(|ui| {
+            ui.label("Hard Radius: ");
+            ui.add(egui::Slider::new(&mut self.particles.hard_radius, 0.0..=1.0));
+            ui.add(
+                egui::DragValue::new(&mut self.particles.hard_iterations)
+                    .prefix("passes: ")
+                    .clamp_range(1..=16),
+            );
+        });
+        
+        //limiting how fast particles can move so stiff settings can't explode
+        ui.horizontal(|ui| {
+            ui.label("Max Speed: ");
+            let mut limited = self.particles.max_speed.is_some();
+            if ui.checkbox(&mut limited, "").changed() {
+                //starting at a speed that crosses the world in one second
+                self.particles.max_speed = limited.then_some(self.particles.world_size);
+            }
+            if let Some(max_speed) = self.particles.max_speed.as_mut() {
+                ui.add(egui::Slider::new(max_speed, 0.0..=100.0).logarithmic(true));
+            }
+        });
+        
+        //tuning the spatial hash cell size, unchecked sizes cells to the effect radius
+        ui.horizontal(|ui| {
+            ui.label("Grid Cell Size: ");
+            let mut custom = self.particles.cell_size.is_some();
+            if ui.checkbox(&mut custom, "").changed() {
+                self.particles.cell_size = custom.then_some(self.particles.particle_effect_radius);
+            }
+            if let Some(cell_size) = self.particles.cell_size.as_mut() {
+                ui.add(egui::Slider::new(cell_size, 0.1..=10.0).logarithmic(true));
+            }
+        });
+        
+        //keeping particles on a tabletop while still viewing them in 3d
+        ui.horizontal(|ui| {
+            ui.label("Lock Y Axis: ");
+            ui.checkbox(&mut self.particles.lock_y, "");
+        });
+        
+        //flattening the whole simulation into the xy plane, cheaper and easier to read
+        ui.horizontal(|ui| {
+            ui.label("Dimensions: ");
+            let previous = self.particles.dimensions;
+            ui.radio_value(&mut self.particles.dimensions, Dim::Two, "2D");
+            ui.radio_value(&mut self.particles.dimensions, Dim::Three, "3D");
+            if self.particles.dimensions != previous {
+                self.particles.apply_dimensions();
+                self.particles.past_particles.clear();
+                self.particles_edited();
+            }
+        });
+        
+        //picking an analytic flow field for swirling motion without tuning the matrix
+        ui.horizontal(|ui| {
+            ui.label("Flow Field: ");
+            let flow_field = &mut self.particles.flow_field;
+            if ui.radio(flow_field.is_none(), "None").clicked() {
+                *flow_field = None;
+            }
+            if ui.radio(matches!(flow_field, Some(FlowField::Vortex { .. })), "Vortex").clicked() {
+                *flow_field = Some(FlowField::Vortex { axis: [0.0, 1.0, 0.0], strength: 0.5 });
+            }
+            if ui.radio(matches!(flow_field, Some(FlowField::Shear { .. })), "Shear").clicked() {
+                *flow_field = Some(FlowField::Shear {
+                    direction: [1.0, 0.0, 0.0],
+                    gradient: [0.0, 1.0, 0.0],
+                    strength: 0.5,
+                });
+            }
+        });
+        match &mut self.particles.flow_field {
+            Some(FlowField::Vortex { axis, strength }) => {
+                ui.horizontal(|ui| {
+                    ui.label("Vortex Axis: ");
+                    for (value, name) in axis.iter_mut().zip(["x: ", "y: ", "z: "]) {
+                        ui.add(egui::DragValue::new(value).prefix(name).speed(0.01));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Vortex Strength: ");
+                    ui.add(egui::DragValue::new(strength).speed(0.01));
+                });
+            }
+            Some(FlowField::Shear { strength, .. }) => {
+                ui.horizontal(|ui| {
+                    ui.label("Shear Strength: ");
+                    ui.add(egui::DragValue::new(strength).speed(0.01));
+                });
+            }
+            None => {}
+        }
+        
+        //picking uniform gravity or a pull towards a point, switching keeps sensible starting values
+        ui.horizontal(|ui| {
+            ui.label("Gravity: ");
+            let gravity = &mut self.particles.gravity;
+            if ui.radio(*gravity == GravityMode::None, "None").clicked() {
+                *gravity = GravityMode::None;
+            }
+            if ui.radio(matches!(gravity, GravityMode::Uniform(_)), "Uniform").clicked()
+                && !matches!(gravity, GravityMode::Uniform(_))
+            {
+                *gravity = GravityMode::Uniform(cgmath::vec3(0.0, 0.0, 0.0));
+            }
+            if ui.radio(matches!(gravity, GravityMode::Point { .. }), "Point").clicked()
+                && !matches!(gravity, GravityMode::Point { .. })
+            {
+                *gravity = GravityMode::Point { center: cgmath::vec3(0.0, 0.0, 0.0), strength: 1.0 };
+            }
+        });
+        match &mut self.particles.gravity {
+            GravityMode::None => {}
+            GravityMode::Uniform(acceleration) => {
+                ui.horizontal(|ui| {
+                    ui.label("Global Gravity: ");
+                    ui.add(egui::DragValue::new(&mut acceleration.y).prefix("y: ").speed(0.01));
+                    ui.add(egui::DragValue::new(&mut acceleration.x).prefix("x: ").speed(0.01));
+                    ui.add(egui::DragValue::new(&mut acceleration.z).prefix("z: ").speed(0.01));
+                });
+            }
+            GravityMode::Point { center, strength } => {
+                ui.horizontal(|ui| {
+                    ui.label("Gravity Center: ");
+                    ui.add(egui::DragValue::new(&mut center.x).prefix("x: ").speed(0.01));
+                    ui.add(egui::DragValue::new(&mut center.y).prefix("y: ").speed(0.01));
+                    ui.add(egui::DragValue::new(&mut center.z).prefix("z: ").speed(0.01));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Gravity Strength: ");
+                    ui.add(egui::DragValue::new(strength).speed(0.01));
+                });
+            }
+        }
+        
+        //herding particles towards wherever the camera is looking
+        ui.horizontal(|ui| {
+            ui.label("Focus Gravity: ");
+            ui.checkbox(&mut self.focus_gravity, "");
+            ui.add(
+                egui::DragValue::new(&mut self.particles.focus_strength)
+                    .prefix("strength: ")
+                    .speed(0.01),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.focus_distance)
+                    .prefix("distance: ")
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+        });
+        
+        //adding or removing particles in a sphere in front of the camera
+        ui.horizontal(|ui| {
+            ui.label("Brush: ");
+            ui.add(
+                egui::DragValue::new(&mut self.brush_radius)
+                    .prefix("radius: ")
+                    .speed(0.05)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.brush_distance)
+                    .prefix("distance: ")
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.brush_count).prefix("count: "));
+            ui.add(
+                egui::DragValue::new(&mut self.brush_type)
+                    .prefix("type: ")
+                    .clamp_range(0..=self.particles.id_count.saturating_sub(1)),
+            );
+            let (forward, _, _) = self.camera.calculate_axes();
+            let center = particle_3d::focus_point(self.camera.eye(), forward, self.brush_distance);
+            if ui.button("Add").clicked() {
+                let type_id = self.brush_type.min(self.particles.id_count.saturating_sub(1));
+                let _ = self
+                    .particles
+                    .spawn_sphere(center, self.brush_radius, self.brush_count, type_id);
+                self.particles_edited();
+            }
+            if ui.button("Remove").clicked() {
+                self.particles.despawn_sphere(center, self.brush_radius);
+                self.particles_edited();
+            }
+        });
+        
+        //legend showing each type's color, index and particle count
+        ui.separator();
+        ui.label("Legend:");
+        let type_counts = self.particles.count_by_type();
+        for (i, count) in type_counts.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let color = self.particles.colors[i];
+                egui::widgets::color_picker::show_color(
+                    ui,
+                    //egui's rgba is linear light
+                    egui::Rgba::from_rgb(srgb_to_linear(color.x), srgb_to_linear(color.y), srgb_to_linear(color.z)),
+                    egui::vec2(16.0, 16.0),
+                );
+                ui.label(format!("Type {}: {} particles", i, count));
+            });
+        }
+        ui.separator();
+
+        //saving and loading the whole simulation (including positions) as a scene file
+        ui.horizontal(|ui| {
+            ui.label("Scene File: ");
+            ui.text_edit_singleline(&mut self.scene_path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save Scene").clicked() {
+                self.scene_status = match self.particles.to_scene().save(&self.scene_path) {
+                    Ok(()) => format!("Saved {}", self.scene_path),
+                    Err(e) => format!("Save failed: {}", e),
+                };
+            }
+            if ui.button("Load Scene").clicked() {
+                self.scene_status = match Scene::load(&self.scene_path)
+                    .and_then(|scene| Particles::from_scene(&scene))
+                {
+                    Ok(particles) => {
+                        self.particles = particles;
+                        self.particle_seed = None;
+                        self.particles_edited();
+                        format!("Loaded {}", self.scene_path)
+                    }
+                    Err(e) => format!("Load failed: {}", e),
+                };
+            }
+            //exact repro of the current state to attach to issues
+            if ui.button("Export Debug Bundle").clicked() {
+                self.scene_status = match self
+                    .particles
+                    .debug_bundle(self.step_stats.steps, self.particle_seed)
+                    .save(DEBUG_BUNDLE_FILE)
+                {
+                    Ok(()) => format!("Saved {}", DEBUG_BUNDLE_FILE),
+                    Err(e) => format!("Export failed: {}", e),
+                };
+            }
+            //particle positions and velocities for analysis elsewhere, named after the step count
+            if ui.button("Export CSV").clicked() {
+                let path = format!("frame_{}.csv", self.step_stats.steps);
+                self.scene_status = match std::fs::File::create(&path)
+                    .map_err(particle_3d::SimError::from)
+                    .and_then(|file| self.particles.export_csv(file))
+                {
+                    Ok(()) => format!("Saved {}", path),
+                    Err(e) => format!("Export failed: {}", e),
+                };
+            }
+        });
+
+        //saving and loading just the tunables, handy for switching between favorite setups
+        ui.horizontal(|ui| {
+            ui.label("Preset File: ");
+            ui.text_edit_singleline(&mut self.preset_path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save Preset").clicked() {
+                self.scene_status = match self.save_preset(&self.preset_path) {
+                    Ok(()) => format!("Saved {}", self.preset_path),
+                    Err(e) => format!("Save failed: {}", e),
+                };
+            }
+            if ui.button("Load Preset").clicked() {
+                let path = self.preset_path.clone();
+                self.scene_status = match self.load_preset(&path) {
+                    Ok(()) => format!("Loaded {}", path),
+                    Err(e) => format!("Load failed: {}", e),
+                };
+            }
+        });
+
+        //replacing all particles with ones placed per type from a spawn spec
+        ui.horizontal(|ui| {
+            ui.label("Spawn File: ");
+            ui.text_edit_singleline(&mut self.spawn_path);
+        });
+        if ui.button("Spawn From File").clicked() {
+            let id_count = self.particles.id_count;
+            let spec = std::fs::read_to_string(&self.spawn_path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| {
+                    serde_json::from_str::<Vec<SpeciesSpawn>>(&text).map_err(|e| e.to_string())
+                })
+                .and_then(|spec| match spec.iter().find(|s| s.id >= id_count) {
+                    Some(s) => Err(format!("type {} is out of range for {} types", s.id, id_count)),
+                    None => Ok(spec),
+                });
+            self.scene_status = match spec {
+                Ok(spec) => {
+                    self.particles.active_particles =
+                        particle_3d::spawn_particles(&spec, &mut rand::thread_rng());
+                    self.particles.apply_dimensions();
+                    self.particle_seed = None;
+                    self.particles.past_particles.clear();
+                    self.particles.sim_time = 0.0;
+                    self.steady_state.reset();
+                    self.particles_edited();
+                    format!("Spawned {} particles", self.particles.active_particles.len())
+                }
+                Err(e) => format!("Spawn failed: {}", e),
+            };
+        }
+        if !self.scene_status.is_empty() {
+            ui.label(&self.scene_status);
+        }
 
-#[derive(ShaderType)]
-struct GpuCamera {
-    pub view_matrix: cgmath::Matrix4<f32>,//camera's view transformation
-    pub projection_matrix: cgmath::Matrix4<f32>,//camera's projection transformation
-}
+        //gpu details for bug reports
+        egui::CollapsingHeader::new("GPU Info").show(ui, |ui| {
+            for line in &self.gpu_info {
+                ui.label(line);
+            }
+        });
 
-struct SimulationApp {
-    particles: Particles,//holding all particle data and behavior
-    camera: CameraSystem,//handling the 3D camera view
-    last_time: std::time::Instant, //tracking when the last frame was processed
-    fixed_time: std::time::Duration,//accumulated time for physics updates
-    update_rate: f32,//how many physics updates per second
-    window: bool,//controls if settings window is shown
-}
+        //button to open particle settings window
+        self.window |= ui.button("Particle Settings").clicked();
+    }
 
-impl SimulationApp {
-    fn new(cc: &eframe::CreationContext) -> Self {
-        //creating a new particle system with initial settings
-        let particles = Particles {
-            world_size: 10.0, //size of the simulation space
-            id_count: MAX_PARTICLE_TYPES as u32,//no. of different particle types
-            colors: vec![//colors for different particle types
-                cgmath::vec3(1.0, 0.0, 0.0), // red
-                cgmath::vec3(0.0, 1.0, 0.0), // green
-                cgmath::vec3(0.0, 0.0, 1.0), // blue
-                cgmath::vec3(1.0, 1.0, 0.0), // yellow
-                cgmath::vec3(1.0, 0.0, 1.0), // magenta
-            ],
-            attraction_matrix: vec![//how different particles attract/repel each other
-                0.5, 1.0, -0.5, 0.0, -1.0,//positive values = attraction, negative = repulsion
-                1.0, 1.0, 1.0, 0.0, -1.0,
-                0.0, 0.0, 0.5, 1.5, -1.0,
-                0.0, 0.0, 0.0, 0.0, -1.0,
-                1.0, 1.0, 1.0, 1.0, 0.5,
-            ],
-            particle_effect_radius: 2.0,//how far particles can affect each other
-            coefficient: 0.97,//friction drag (1.0 = no friction)
-            interaction_force: 1.0,//strength of particle interactions
-            min_pull_ratio: 0.3, //when to push instead of pull
-            active_particles: generate_particles(10.0, 1000),//creating 1000 starting particles
-            past_particles: vec![],//storage for previous frames (not used here)
-            walls: false,//whether particles bounce off walls
-            acceleration: cgmath::vec3(0.0, 0.0, 0.0),  // gravity
+    //preparing camera, particle and color data and queuing the 3d paint callback
+    fn paint_scene(&self, ui: &egui::Ui, rect: egui::Rect) {
+        let (forward, _, up) = self.camera.calculate_axes();
+        //created view matrix (camera position and orientation), orbiting looks at its target
+        let eye = self.camera.eye();
+        let eye = cgmath::point3(eye.x, eye.y, eye.z);
+        let view_matrix = match self.camera.mode {
+            CameraMode::Fps => cgmath::Matrix4::look_to_rh(eye, forward, up),
+            CameraMode::Orbit { target, .. } => {
+                cgmath::Matrix4::look_at_rh(eye, cgmath::point3(target.x, target.y, target.z), up)
+            }
+        };
+        //created projection matrix
+        let projection_matrix = cgmath::perspective(
+            cgmath::Rad::from(cgmath::Deg(FIELD_OF_VIEW)),
+            rect.width() / rect.height(),//screen ratio 
+            0.001,//clipping plane
+            1000.0,
+        );
+        let mut camera_uniform =
+            UniformBuffer::new([0; <GpuCamera as ShaderSize>::SHADER_SIZE.get() as _]);
+        camera_uniform
+            .write(&GpuCamera {
+                view_matrix,
+                projection_matrix,
+                frustum_planes: frustum_planes(projection_matrix * view_matrix),
+                cull_enabled: self.frustum_culling as u32,
+                slab_plane: slab_clip_plane(self.slab_axis, self.slab_center),
+                slab_half_thickness: self.slab_thickness * 0.5,
+                slab_enabled: self.slab_enabled as u32,
+            })
+            .unwrap();
+        let camera = camera_uniform.into_inner();
+        //blending towards the next physics step using the leftover accumulated time, otherwise
+        //uploading straight from the simulation's own particles without a copy, gpu physics
+        //draws its own buffer so there is nothing to blend
+        let render_particles: std::borrow::Cow<[Particle]> = if self.smooth_rendering && !self.use_gpu_physics {
+            let alpha = self.fixed_time.as_secs_f32() * self.update_rate;
+            self.particles.interpolated(alpha).into()
+        } else {
+            self.particles.active_particles.as_slice().into()
+        };
+        //labels follow the drawn (possibly blended) positions, in simulation order
+        let labels: Vec<(egui::Pos2, String)> = if self.show_labels {
+            let view_projection = projection_matrix * view_matrix;
+            render_particles
+                .iter()
+                .enumerate()
+                .take(self.label_limit)
+                .filter_map(|(i, particle)| {
+                    let text = match self.label_mode {
+                        LabelMode::Index => i.to_string(),
+                        LabelMode::Type => particle.id.to_string(),
+                    };
+                    world_to_screen(view_projection, particle.position, rect).map(|pos| (pos, text))
+                })
+                .collect()
+        } else {
+            vec![]
         };
+        //drawing back to front so blending overlaps correctly
+        let order = self
+            .transparency
+            .then(|| depth_sorted_indices(&render_particles, self.camera.eye(), forward));
+        //one draw per glyph and depth write group, the particles themselves stay in simulation order
+        let (order, batches) = render_batches(&render_particles, order, &self.type_render);
+        let mut draw_order_storage = StorageBuffer::new(vec![]);
+        draw_order_storage
+            .write(&GpuDrawOrder {
+                length: ArrayLength,
+                indices: order.as_deref().unwrap_or_default(),
+            })
+            .unwrap();
+        let draw_order = draw_order_storage.into_inner();
+        //preparing particle data for gpu, with gpu physics the particles are already there (unless
+        //they were just edited) so only the header goes up
+        let particles = if self.use_gpu_physics && !self.gpu_upload {
+            let mut header = particle_buffer_data(self.particles.world_extents(), &[]);
+            header.truncate(GPU_PARTICLES_HEADER_SIZE);
+            header
+        } else {
+            particle_buffer_data(self.particles.world_extents(), &render_particles)
+        };
+        //colors are authored in srgb, an srgb surface expects linear values and re-encodes them
+        let shader_colors: Vec<cgmath::Vector3<f32>> = if self.target_is_srgb {
+            self.particles
+                .colors
+                .iter()
+                .map(|c| cgmath::vec3(srgb_to_linear(c.x), srgb_to_linear(c.y), srgb_to_linear(c.z)))
+                .collect()
+        } else {
+            self.particles.colors.clone()
+        };
+        //preparing color and per type render data for gpu
+        let type_styles = pack_type_styles(&shader_colors, &self.type_render);
+        let mut colors_storage = StorageBuffer::new(vec![]);
+        colors_storage
+            .write(&GpuColors {
+                alpha: if self.transparency { self.particle_alpha } else { 1.0 },
+                length: ArrayLength,
+                particles: &type_styles,
+            })
+            .unwrap();
+        let colors = colors_storage.into_inner();
+        //gradient colors go through the same srgb handling as the type colors
+        let gradient_color = |c: [f32; 3]| {
+            if self.target_is_srgb {
+                cgmath::vec3(srgb_to_linear(c[0]), srgb_to_linear(c[1]), srgb_to_linear(c[2]))
+            } else {
+                c.into()
+            }
+        };
+        let mut color_mode_uniform =
+            UniformBuffer::new([0; <GpuColorMode as ShaderSize>::SHADER_SIZE.get() as _]);
+        color_mode_uniform
+            .write(&GpuColorMode {
+                mode: self.color_mode.shader_mode(),
+                min_speed: self.speed_range.0,
+                max_speed: self.speed_range.1,
+                slow_color: gradient_color(self.slow_color),
+                fast_color: gradient_color(self.fast_color),
+            })
+            .unwrap();
+        let color_mode = color_mode_uniform.into_inner();
 
-        //setting up camera
-        let camera = CameraSystem {
-            position: cgmath::vec3(1.0, 0.0, particles.world_size * 1.6),//starting position
-            up: cgmath::vec3(0.0, 1.0, 0.0),// way is up
-            pitch: 0.0, //looking up/down angle
-            yaw: 0.0,//looking left/right angle
+        //building wireframe boxes for occupied grid cells (capped to keep it cheap)
+        let grid: Vec<u8> = if self.show_grid {
+            self.particles
+                .occupied_cells()
+                .into_iter()
+                .take(MAX_GRID_CELLS)
+                .flat_map(|cell| self.particles.cell_box_vertices(cell))
+                .flat_map(|v| [v.x, v.y, v.z])
+                .flat_map(f32::to_ne_bytes)
+                .collect()
+        } else {
+            vec![]
         };
+        let grid_vertex_count = grid.len() / GRID_VERTEX_SIZE;
 
-        //main app with everything initialized
-        let app = Self {
-            particles,
-            camera,
-            last_time: std::time::Instant::now(),//starting timing now
-            fixed_time: std::time::Duration::ZERO,//no accumulated time yet
-            update_rate: 60.0, //physics updates 60 times per second
-            window: false,//start with settings window closed
+        //fading lines behind each particle, in its type's trail length and color
+        let count = self.particles.active_particles.len();
+        let trail_vertices = if self.trail_history.back().is_some_and(|(positions, _)| positions.len() == count) {
+            let mut styles = trail_styles(&self.particles.colors, &self.type_render, self.trail_length);
+            if self.target_is_srgb {
+                for (_, color) in &mut styles {
+                    *color = color.map(srgb_to_linear);
+                }
+            }
+            trail_vertices(&self.trail_history, &self.particles.active_particles, &styles)
+        } else {
+            vec![]
         };
+        let trails: Vec<u8> = trail_vertices.into_iter().flat_map(f32::to_ne_bytes).collect();
+        let trail_vertex_count = trails.len() / TRAIL_VERTEX_SIZE;
 
-        //setting up the graphics renderer
-        let render_state = cc.wgpu_render_state.as_ref().unwrap();
-        let renderer = Renderer::new(render_state);
-        render_state
-            .renderer
-            .write()
-            .paint_callback_resources
-            .insert(renderer);
+        let transparent = self.transparency;
+        let show_border = self.show_border;
+        //the scene target matches the view in physical pixels and starts out as the panel
+        //background the scene used to be drawn over
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let target_size = [
+            (rect.width() * pixels_per_point).round() as u32,
+            (rect.height() * pixels_per_point).round() as u32,
+        ];
+        let background = ui.style().visuals.panel_fill.to_array().map(|c| {
+            let c = c as f32 / 255.0;
+            (if self.target_is_srgb { srgb_to_linear(c) } else { c }) as f64
+        });
+        let clear_color = wgpu::Color {
+            r: background[0],
+            g: background[1],
+            b: background[2],
+            a: 1.0,
+        };
 
-        app
+        //setting up the 3d rendering callback
+        ui.painter().add(egui::PaintCallback {
+            rect,
+            callback: std::sync::Arc::new(
+                eframe::egui_wgpu::CallbackFn::new()
+                   //setting up for rendering data
+                    .prepare(move |device, queue, encoder, paint_callback_resources| {
+                        let renderer: &mut Renderer =
+                            paint_callback_resources.get_mut().unwrap();
+                        let mut commands = renderer
+                            .update_resources(
+                                &camera,
+                                &particles,
+                                &draw_order,
+                                &colors,
+                                &color_mode,
+                                &grid,
+                                &trails,
+                                device,
+                                queue,
+                                encoder,
+                            );
+                        //rendering into the scene target
+                        commands.push(renderer.render_scene(
+                            device,
+                            target_size,
+                            clear_color,
+                            &batches,
+                            grid_vertex_count as _,
+                            trail_vertex_count as _,
+                            transparent,
+                            show_border,
+                        ));
+                        commands
+                    })
+                    //showing the rendered scene
+                    .paint(move |_info, render_pass, paint_callback_resources| {
+                        let renderer: &Renderer = paint_callback_resources.get().unwrap();
+                        renderer.blit(render_pass);
+                    }),
+            ),
+        });
+
+        //debug labels on top of the 3d view
+        for (pos, text) in labels {
+            ui.painter().text(
+                pos,
+                egui::Align2::CENTER_BOTTOM,
+                text,
+                egui::FontId::monospace(10.0),
+                egui::Color32::WHITE,
+            );
+        }
     }
 }
 
-
 impl eframe::App for SimulationApp {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
+        //every scene pipeline is built for one msaa level, so a new level means a new renderer
+        if self.requested_sample_count != self.sample_count {
+            self.rebuild_renderer();
+        }
         //calculating time since last frame
         let time = std::time::Instant::now();
         let ts = time.duration_since(self.last_time);
         self.last_time = time;
 
-        //handling physics updates at a fixed rate (for stability)
-        self.fixed_time += ts;
+        //handling physics updates at a fixed rate (for stability), no time builds up while paused
+        if !self.paused {
+            self.fixed_time += ts;
+        }
+        //moving the focus point along with the camera
+        self.particles.focus_point = if self.focus_gravity {
+            let (forward, _, _) = self.camera.calculate_axes();
+            Some(particle_3d::focus_point(self.camera.eye(), forward, self.focus_distance))
+        } else {
+            None
+        };
+        //taking over gpu physics results that arrived since the last frame
+        if self.use_gpu_physics {
+            self.poll_gpu_readback(false);
+        }
         let start_update = std::time::Instant::now();
-        if self.fixed_time.as_secs_f32() >= 1.0 / self.update_rate {
+        if !self.paused && self.fixed_time.as_secs_f32() >= 1.0 / self.update_rate {
             let ts = 1.0 / self.update_rate;
             let fixed_step = std::time::Duration::from_secs_f32(1.0 / self.update_rate);
             
             //catching up on physics if its behind, but not too many at once
             let updates_needed = (self.fixed_time.as_secs_f32() * self.update_rate).min(5.0) as usize;
             for _ in 0..updates_needed {
-                self.particles.update(ts); //updating all particle positions
+                let step_start = std::time::Instant::now();
+                //updating all particle positions, a step that can't run is skipped and reported
+                if let Err(e) = self.physics_step(ts) {
+                    self.sim_error = Some(e.to_string());
+                    //not building up a backlog of steps while the settings are broken
+                    self.fixed_time = std::time::Duration::ZERO;
+                    break;
+                }
+                self.sim_error = None;
                 self.fixed_time -= fixed_step;//subtracting the time i just simulated
+                self.step_stats.record(step_start.elapsed());
+                self.step_rate.record(std::time::Instant::now());
+                //gpu steps are observed when they are read back
+                if !self.use_gpu_physics && self.observe_steady_state() {
+                    break;
+                }
             }
         }
+        //one readback per frame at most, taken over on a later frame once it has arrived
+        if self.use_gpu_physics && self.gpu_unread_steps {
+            self.request_gpu_readback();
+        }
         let update_elapsed = start_update.elapsed();//checking how long physics updates it took
 
         let ts = ts.as_secs_f32();//converting time to seconds for movement calculations
 
+        //scrubbing step by step while paused, L steps forward and J undoes scrubbed steps
+        if self.paused {
+            let direction = if ctx.wants_keyboard_input() {
+                0
+            } else {
+                ctx.input(|i| i.key_down(egui::Key::L) as i32 - i.key_down(egui::Key::J) as i32)
+            };
+            for _ in 0..self.scrubber.advance(direction, ts) {
+                if direction > 0 {
+                    //each scrubbed gpu step has to be on the cpu to be scrubbed back
+                    if self.use_gpu_physics {
+                        self.sync_gpu_physics();
+                    }
+                    //remembering the state so the step can be scrubbed back exactly
+                    self.scrub_history.push(ScrubState::capture(&self.particles));
+                    if let Err(e) = self.physics_step(1.0 / self.update_rate) {
+                        //nothing changed, so there is nothing to scrub back
+                        self.scrub_history.pop();
+                        self.sim_error = Some(e.to_string());
+                        break;
+                    }
+                    if self.use_gpu_physics {
+                        self.sync_gpu_physics();
+                    }
+                    self.sim_error = None;
+                } else if let Some(state) = self.scrub_history.pop() {
+                    let past_particles =
+                        self.scrub_history.last_particles().map(<[Particle]>::to_vec).unwrap_or_default();
+                    state.restore(&mut self.particles, past_particles);
+                    self.trail_history.pop_back();
+                    self.particles_edited();
+                }
+            }
+        } else {
+            //history only lines up with the paused timeline
+            self.scrub_history.clear();
+        }
+
+        //flying the camera towards its target, any camera key takes control back right away
+        if let Some(animation) = &mut self.camera_animation {
+            let camera_keys = [
+                egui::Key::W,
+                egui::Key::A,
+                egui::Key::S,
+                egui::Key::D,
+                egui::Key::Q,
+                egui::Key::E,
+                egui::Key::ArrowUp,
+                egui::Key::ArrowDown,
+                egui::Key::ArrowLeft,
+                egui::Key::ArrowRight,
+            ];
+            let manual = !ctx.wants_keyboard_input()
+                && ctx.input(|i| camera_keys.iter().any(|&key| i.key_down(key)));
+            if manual {
+                self.camera_animation = None;
+            } else {
+                let done = animation.advance(ts);
+                self.camera.set_pose(animation.pose());
+                if done {
+                    self.camera_animation = None;
+                }
+            }
+        }
+
         //handling keyboard input for camera movement
         if !ctx.wants_keyboard_input() {    //won't move camera if typing in a text field
+            //tab switches between flying and orbiting
+            if ctx.input(|i| i.key_pressed(egui::Key::Tab)) {
+                self.camera.toggle_mode();
+                self.camera_animation = None;
+            }
+            //F orbits the particles' center of mass
+            if ctx.input(|i| i.key_pressed(egui::Key::F)) {
+                self.camera.orbit_around(orbit_pivot(&self.particles));
+                self.camera_animation = None;
+            }
             ctx.input(|i| {
                 //camera's current position
                 let (forward, right, up) = self.camera.calculate_axes();
@@ -244,261 +2781,887 @@ impl eframe::App for SimulationApp {
                     self.camera.rotate_camera(0.0, ROTATION_SPEED * ts); //right
                 }
             });
+
+            //stopping the camera from getting lost far from the particles
+            if self.clamp_camera {
+                self.camera
+                    .clamp_position(self.particles.world_size * CAMERA_RANGE_SCALE);
+            }
         }
 
         //creating and filling the side panel with controls
-        egui::SidePanel::left("Left Panel").show(ctx, |ui| {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                //show performance information
-                ui.label(format!("FPS: {:.3}", 1.0 / ts)); //frames per second
-                ui.label(format!("Frame Time: {:.3}ms", ts * 1000.0));//time per frame
-                ui.label(format!(
-                    "Updated Time: {:.3}ms",
-                    update_elapsed.as_secs_f64() * 1000.0//time for physics
-                ));
-                
-                //slider to change number of particles
-                ui.horizontal(|ui| {
-                    ui.label("Particle Count: ");
-                    let mut particle_count = self.particles.active_particles.len();
-                    if ui
-                        .add(egui::DragValue::new(&mut particle_count).speed(0.1))
-                        .changed()
-                    {
-                        let current_count = self.particles.active_particles.len();
-                        if particle_count < current_count {
-                            //remove particles if I decreased the count
-                            self.particles.active_particles.truncate(particle_count);
-                        } else if particle_count > current_count {
-                            //add new particles if I increased the count
-                            let additional = particle_count - current_count;
-                            self.particles.active_particles.reserve(additional);
-                            let new_particles = generate_particles(self.particles.world_size, additional);
-                            self.particles.active_particles.extend(new_particles);
-                        }
-                    }
-                });
-                
-                //controlling for simulation boundary size
-                ui.horizontal(|ui| {
-                    ui.label("Simulation Boundary: ");
-                    ui.add(egui::DragValue::new(&mut self.particles.world_size).speed(0.1));
-                    //making sure the world is at least big enough for particle interactions
-                    self.particles.world_size = self
-                        .particles
-                        .world_size
-                        .max(self.particles.particle_effect_radius * 2.0);
-                });
-                
-                //controlling for physics update rate
-                ui.horizontal(|ui| {
-                    ui.label("Update Rate (TPS): ");
-                    ui.add(egui::Slider::new(&mut self.update_rate, 1.0..=1000.0));
+        //controls live in the side panel, or in a movable window when detached
+        if self.controls_placement == ControlsPlacement::Detached {
+            let mut open = true;
+            egui::Window::new("Controls")
+                .open(&mut open)
+                .vscroll(true)
+                .show(ctx, |ui| {
+                    self.controls_ui(ui, time, ts, update_elapsed);
                 });
-                
-                //toggling for solid walls
-                ui.horizontal(|ui| {
-                    ui.label("Use Solid Walls: ");
-                    ui.checkbox(&mut self.particles.walls, "");//checking to make particles bounce off walls
+            self.controls_placement = self.controls_placement.after_window(open);
+        } else {
+            egui::SidePanel::left("Left Panel").show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.controls_ui(ui, time, ts, update_elapsed);
+                    ui.allocate_space(ui.available_size());
                 });
-                
-                //controlling for how far particles can affect each other
+            });
+        }
+
+        egui::Window::new("Properties")
+        .open(&mut self.window)
+        .resizable(false)
+        .show(ctx, |ui| {
+            //widening the allowed attraction values (large values can make the simulation unstable)
+            ui.horizontal(|ui| {
+                ui.label("Attraction Range: ");
+                ui.add(
+                    egui::DragValue::new(&mut self.particles.attraction_range.0)
+                        .clamp_range(f32::MIN..=-1.0)
+                        .prefix("min: ")
+                        .speed(0.01),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut self.particles.attraction_range.1)
+                        .clamp_range(1.0..=f32::MAX)
+                        .prefix("max: ")
+                        .speed(0.01),
+                );
+            });
+
+            ui.horizontal(|ui| {
+                for i in 0..self.particles.id_count as usize {
+                    let mut ui_color = [
+                        self.particles.colors[i].x,
+                        self.particles.colors[i].y,
+                        self.particles.colors[i].z,
+                    ];
+                    srgb_color_edit(ui, &mut ui_color);
+                    self.particles.colors[i] = cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
+                }
+            });
+            
+            for i in 0..self.particles.id_count as usize {
                 ui.horizontal(|ui| {
-                    ui.label("Effect Radius: ");
-                    ui.add(egui::Slider::new(
-                        &mut self.particles.particle_effect_radius,
-                        0.0..=self.particles.world_size / 2.0,
-                    ));
+                    // Show color for this row
+                    let mut ui_color = [
+                        self.particles.colors[i].x,
+                        self.particles.colors[i].y,
+                        self.particles.colors[i].z,
+                    ];
+                    srgb_color_edit(ui, &mut ui_color);
+                    self.particles.colors[i] = cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
+                    
+                    //attraction/repulsion sliders for each particle type
+                    let attraction_range = self.particles.attraction_range;
+                    for j in 0..self.particles.id_count as usize {
+                        if let Some(attraction) = self.particles.attraction_matrix.get_mut(i, j) {
+                            ui.add(
+                                egui::DragValue::new(attraction)
+                                    .clamp_range(attraction_range.0..=attraction_range.1)
+                                    .speed(0.01)
+                            );
+                        }
+                    }
                 });
-                
-                //controlling for strength of particle interactions
+            }
+
+            //per pair force multipliers, laid out like the attraction matrix above
+            ui.label("Pair Force Scale:");
+            let id_count = self.particles.id_count as usize;
+            for i in 0..id_count {
                 ui.horizontal(|ui| {
-                    ui.label("Interaction Scale Rate: ");
-                    ui.add(egui::Slider::new(
-                        &mut self.particles.interaction_force,
-                        0.0..=10.0,
-                    ));
+                    for j in 0..id_count {
+                        ui.add(
+                            egui::DragValue::new(&mut self.particles.pair_force_scale[i * id_count + j])
+                                .clamp_range(0.0..=f32::MAX)
+                                .speed(0.01),
+                        );
+                    }
                 });
-                
-                //toggling for friction
-                ui.horizontal(|ui| {
-                    ui.label("Drag (Friction): ");
+            }
+
+            //per pair cutoff distances, off means every pair uses the global effect radius
+            let mut per_pair_radius = !self.particles.radius_matrix.is_empty();
+            if ui.checkbox(&mut per_pair_radius, "Per Pair Radius").changed() {
+                self.particles.radius_matrix = if per_pair_radius {
+                    vec![self.particles.particle_effect_radius; id_count * id_count]
+                } else {
+                    vec![]
+                };
+            }
+            if per_pair_radius {
+                let max_radius = self.particles.world_size * 0.5;
+                for i in 0..id_count {
+                    ui.horizontal(|ui| {
+                        for j in 0..id_count {
+                            ui.add(
+                                egui::DragValue::new(&mut self.particles.radius_matrix[i * id_count + j])
+                                    .clamp_range(0.0..=max_radius)
+                                    .speed(0.01),
+                            );
+                        }
+                    });
+                }
+            }
+
+            //editing the force curve as control points over the effect radius
+            let mut custom_curve = !self.particles.force_curve.is_empty();
+            if ui.checkbox(&mut custom_curve, "Custom Force Curve").changed() {
+                self.particles.force_curve = if custom_curve {
+                    //starting from the built-in shape (repel, then attract, then fade out)
+                    let m = self.particles.min_pull_ratio;
+                    vec![(0.0, -1.0), (m, 0.0), ((1.0 + m) / 2.0, 1.0), (1.0, 0.0)]
+                } else {
+                    vec![]
+                };
+            }
+            if custom_curve {
+                let mut remove = None;
+                for (i, point) in self.particles.force_curve.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut point.0)
+                                .clamp_range(0.0..=1.0)
+                                .prefix("distance: ")
+                                .speed(0.01),
+                        );
+                        ui.add(egui::DragValue::new(&mut point.1).prefix("force: ").speed(0.01));
+                        if ui.button("Remove").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    self.particles.force_curve.remove(i);
+                }
+                if ui.button("Add Point").clicked() {
+                    self.particles.force_curve.push((1.0, 0.0));
+                }
+                //keeping points ordered by distance so sampling works
+                self.particles
+                    .force_curve
+                    .sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                let points: Vec<[f64; 2]> = self
+                    .particles
+                    .force_curve
+                    .iter()
+                    .map(|&(x, y)| [x as f64, y as f64])
+                    .collect();
+                egui::plot::Plot::new("Force Curve")
+                    .height(120.0)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(points)));
+                    });
+            }
+
+            //how strongly gravity pulls each particle type
+            ui.horizontal(|ui| {
+                ui.label("Gravity Scale: ");
+                for i in 0..self.particles.id_count as usize {
+                    ui.add(egui::DragValue::new(&mut self.particles.gravity_scale[i]).speed(0.01));
+                }
+            });
+
+            //how strongly friction slows each particle type
+            ui.horizontal(|ui| {
+                ui.label("Friction Scale: ");
+                for i in 0..self.particles.id_count as usize {
                     ui.add(
-                        egui::Slider::new(&mut self.particles.coefficient, 0.0..=1.0)
-                            .drag_value_speed(0.01),
+                        egui::DragValue::new(&mut self.particles.friction_scale[i])
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
                     );
-                });
-                
-                //controlling for when to push vs pull particles
-                ui.horizontal(|ui| {
-                    ui.label("Repulsion Threshold: ");
-                    ui.add(egui::Slider::new(
-                        &mut self.particles.min_pull_ratio,
-                        0.0..=1.0,
-                    ));
-                });
-                
-                //toggling for gravity
-                ui.horizontal(|ui| {
-                    ui.label("Global Gravity: ");
+                }
+            });
+
+            //heavier types are pushed around less by the other particles
+            ui.horizontal(|ui| {
+                ui.label("Mass: ");
+                self.particles.masses.resize(self.particles.id_count as usize, 1.0);
+                for mass in self.particles.masses.iter_mut() {
                     ui.add(
-                        egui::DragValue::new(&mut self.particles.acceleration.y)
-                            .prefix("y: ")
-                            .speed(0.01),
+                        egui::DragValue::new(mass)
+                            .speed(0.01)
+                            .clamp_range(0.01..=f32::MAX),
                     );
+                }
+            });
+
+            //how each type is drawn (types that don't write depth are drawn as haze behind nothing)
+            ui.label("Type Rendering:");
+            let trail_length = self.trail_length;
+            let type_colors: Vec<[f32; 3]> = self.particles.colors.iter().map(|&c| c.into()).collect();
+            for (i, config) in self
+                .type_render
+                .iter_mut()
+                .enumerate()
+                .take(self.particles.id_count as usize)
+            {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source(("glyph", i))
+                        .selected_text(config.glyph.name())
+                        .show_ui(ui, |ui| {
+                            for glyph in Glyph::ALL {
+                                ui.selectable_value(&mut config.glyph, glyph, glyph.name());
+                            }
+                        });
                     ui.add(
-                        egui::DragValue::new(&mut self.particles.acceleration.x)
-                            .prefix("x: ")
-                            .speed(0.01),
+                        egui::DragValue::new(&mut config.size)
+                            .prefix("size: ")
+                            .speed(0.005)
+                            .clamp_range(0.001..=10.0),
                     );
                     ui.add(
-                        egui::DragValue::new(&mut self.particles.acceleration.z)
-                            .prefix("z: ")
-                            .speed(0.01),
+                        egui::DragValue::new(&mut config.alpha)
+                            .prefix("alpha: ")
+                            .speed(0.01)
+                            .clamp_range(0.0..=1.0),
                     );
+                    ui.checkbox(&mut config.depth_write, "depth");
+                    //own trail length and color instead of the global length and the type color
+                    let mut own_length = config.trail_length.is_some();
+                    if ui.checkbox(&mut own_length, "trail").changed() {
+                        config.trail_length = own_length.then_some(trail_length);
+                    }
+                    if let Some(length) = &mut config.trail_length {
+                        ui.add(egui::Slider::new(length, 0..=MAX_TRAIL_LENGTH));
+                    }
+                    let mut own_color = config.trail_color.is_some();
+                    if ui.checkbox(&mut own_color, "trail color").changed() {
+                        config.trail_color = own_color.then_some(type_colors.get(i).copied().unwrap_or([1.0; 3]));
+                    }
+                    if let Some(color) = &mut config.trail_color {
+                        srgb_color_edit(ui, color);
+                    }
                 });
-                
-                //button to open particle settings window
-                self.window |= ui.button("Particle Settings").clicked();
-                ui.allocate_space(ui.available_size());
+            }
+        });
+        //created the main 3d view panel
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(ctx.style().visuals.panel_fill))
+            .show(ctx, |ui| {
+                let (rect, response) =
+                    ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+
+                //looking around by dragging, up and right look up and right like the arrow keys
+                let dragging = if self.look_with_right_button {
+                    response.dragged_by(egui::PointerButton::Secondary)
+                } else {
+                    response.dragged()
+                };
+                if dragging {
+                    let delta = response.drag_delta();
+                    self.camera
+                        .rotate_camera(-delta.y * MOUSE_SENSITIVITY, delta.x * MOUSE_SENSITIVITY);
+                    //taking control back from a camera flight
+                    self.camera_animation = None;
+                }
+                //scrolling over the view to move faster or slower, or closer and further when orbiting
+                if response.hovered() {
+                    let scroll = ui.input(|i| i.scroll_delta.y);
+                    if scroll != 0.0 {
+                        self.camera.zoom(scroll);
+                    }
+                }
+
+                //skipping all upload and drawing work while the view is frozen (physics keeps going)
+                if !paint_unless_frozen(self.freeze_view, || self.paint_scene(ui, rect)) {
+                    ui.painter().text(
+                        rect.center(),
+                        egui::Align2::CENTER_CENTER,
+                        "View frozen",
+                        egui::FontId::proportional(20.0),
+                        ui.visuals().text_color(),
+                    );
+                }
+                //updating the display based on the chosen repaint mode
+                match self.repaint_mode {
+                    RepaintMode::Continuous => ctx.request_repaint(),
+                    RepaintMode::CappedFps => ctx.request_repaint_after(repaint_delay(self.target_fps)),
+                    RepaintMode::WhenRunning => {
+                        //a camera flight still needs frames while paused
+                        if !self.paused || self.camera_animation.is_some() {
+                            ctx.request_repaint();
+                        }
+                    }
+                }
             });
+    }
+
+    //remembering the msaa level for the next start
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, MSAA_STORAGE_KEY, &self.requested_sample_count);
+    }
+}
+
+#[derive(ShaderType)]
+struct GpuPhysicsParams {
+    pub world_extents: cgmath::Vector3<f32>,//simulation box edge lengths
+    pub count: u32,//no. of particles
+    pub gravity: cgmath::Vector3<f32>,//uniform acceleration or point gravity center
+    pub id_count: u32,//no. of particle types
+    pub ts: f32,//step length in seconds
+    pub interaction_force: f32,
+    pub effect_radius: f32,
+    pub min_pull_ratio: f32,
+    pub friction: f32,//decay rate per second
+    pub restitution: f32,
+    pub max_speed: f32,//negative = no speed limit
+    pub rest_speed: f32,//speed below which particles stop
+    pub boundary: u32,//0 = wrap, 1 = walls, 2 = open
+    pub lock_y: u32,
+    pub flat: u32,//1 = 2D
+    pub gravity_mode: u32,//0 = none, 1 = uniform, 2 = towards a point
+    pub gravity_strength: f32,//point gravity strength
+    pub gravity_softening: f32,
+}
+
+#[derive(ShaderType)]
+struct GpuPhysicsPair {
+    pub attraction: f32,
+    pub radius: f32,//cutoff distance
+    pub force_scale: f32,
+}
+
+#[derive(ShaderType)]
+struct GpuPhysicsKind {
+    pub mass: f32,
+    pub gravity_scale: f32,
+    pub friction_scale: f32,
+}
+
+//particles per compute workgroup, has to match `@workgroup_size` in physics.wgsl
+const PHYSICS_WORKGROUP_SIZE: u32 = 64;
+//bytes per particle in the particle buffer (position, velocity and type, each padded to 16)
+const GPU_PARTICLE_SIZE: usize = 32;
+//bytes per particle in the step events buffer (wrapped flag and absorbed energy)
+const GPU_EVENT_SIZE: usize = 8;
+//bytes in the step stats buffer (pair checks, interactions and max force bits)
+const GPU_STATS_SIZE: usize = 12;
+//bytes in front of the particles in the particle buffer (world extents and length)
+const GPU_PARTICLES_HEADER_SIZE: usize = 16;
+
+//what a finished readback brings back from the gpu
+struct GpuReadback {
+    generation: u64,//upload generation the stepped particles started from
+    particles: Vec<Particle>,
+    wrapped: Vec<bool>,//wrapped in any step since the last readback
+    energy_absorbed_by_walls: f32,//in the last step
+    step_stats: particle_3d::StepStats,//of the last step
+}
+
+//a readback copied into `readback_buffer` and waiting for the buffer to map
+struct PendingReadback {
+    generation: u64,
+    count: usize,
+    mapped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+//stepping the simulation with a compute shader instead of the cpu solver, brute force so every
+//particle checks every other one, and only the built-in force shape with semi-implicit euler
+//(force curves, flow fields, focus pull and hard separation are cpu only)
+//the particles live in the renderer's particle buffer: every step reads it, writes a scratch
+//buffer and copies the result back, so nothing goes through the cpu until a readback is asked for
+//every buffer here is kept between steps and only reallocated when it has to grow
+struct GpuPhysics {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    pairs_buffer: wgpu::Buffer,
+    pairs_buffer_size: usize,
+    kinds_buffer: wgpu::Buffer,
+    kinds_buffer_size: usize,
+    output_buffer: wgpu::Buffer,//stepped particles before they are copied back
+    output_buffer_size: usize,
+    events_buffer: wgpu::Buffer,//per particle wraps and wall energy
+    events_buffer_size: usize,
+    stats_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    readback_buffer_size: usize,
+    bind_group: Option<wgpu::BindGroup>,//dropped whenever a buffer it points at is replaced
+    clear_events: bool,//the particles were replaced, so old events don't belong to them
+    readback: Option<PendingReadback>,
+}
+
+//a zeroed buffer, wgpu clears new buffers
+fn physics_buffer(device: &wgpu::Device, label: &str, size: usize, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: size as wgpu::BufferAddress,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+impl GpuPhysics {
+    fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(include_wgsl!("physics.wgsl"));
+
+        //params, pair table, type table, particles in, particles out, events and stats
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Physics Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(<GpuPhysicsParams as ShaderSize>::SHADER_SIZE),
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, true),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Physics Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Physics Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "step",
+        });
+
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        let pair_size = <GpuPhysicsPair as ShaderSize>::SHADER_SIZE.get() as usize;
+        let kind_size = <GpuPhysicsKind as ShaderSize>::SHADER_SIZE.get() as usize;
+        Self {
+            params_buffer: physics_buffer(
+                device,
+                "Physics Params Buffer",
+                <GpuPhysicsParams as ShaderSize>::SHADER_SIZE.get() as usize,
+                wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            ),
+            pairs_buffer: physics_buffer(device, "Physics Pairs Buffer", pair_size, storage),
+            pairs_buffer_size: pair_size,
+            kinds_buffer: physics_buffer(device, "Physics Types Buffer", kind_size, storage),
+            kinds_buffer_size: kind_size,
+            output_buffer: physics_buffer(
+                device,
+                "Physics Output Buffer",
+                GPU_PARTICLE_SIZE,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            output_buffer_size: GPU_PARTICLE_SIZE,
+            events_buffer: physics_buffer(
+                device,
+                "Physics Events Buffer",
+                GPU_EVENT_SIZE,
+                storage | wgpu::BufferUsages::COPY_SRC,
+            ),
+            events_buffer_size: GPU_EVENT_SIZE,
+            stats_buffer: physics_buffer(
+                device,
+                "Physics Stats Buffer",
+                GPU_STATS_SIZE,
+                storage | wgpu::BufferUsages::COPY_SRC,
+            ),
+            readback_buffer: physics_buffer(
+                device,
+                "Physics Readback Buffer",
+                GPU_STATS_SIZE,
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            ),
+            readback_buffer_size: GPU_STATS_SIZE,
+            bind_group_layout,
+            pipeline,
+            bind_group: None,
+            clear_events: false,
+            readback: None,
+        }
+    }
+
+    //the particle buffer was replaced, the next step binds the new one
+    fn invalidate(&mut self) {
+        self.bind_group = None;
+    }
+
+    //the particles in the particle buffer were replaced from the cpu
+    fn particles_replaced(&mut self) {
+        self.clear_events = true;
+    }
+
+    //queueing one step of ts on the particles in `particles_buffer`, `particles` only supplies
+    //the settings and the particle count, call `validate` on it first
+    fn step(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles_buffer: &wgpu::Buffer,
+        particles: &Particles,
+        ts: f32,
+    ) {
+        let count = particles.active_particles.len();
+        if count == 0 {
+            return;
+        }
+        let (params, pairs, kinds) = physics_tables(particles, ts);
+
+        let mut params_data = UniformBuffer::new(Vec::new());
+        params_data.write(&params).unwrap();
+        let mut pairs_data = StorageBuffer::new(Vec::new());
+        pairs_data.write(&pairs).unwrap();
+        let pairs_data = pairs_data.into_inner();
+        let mut kinds_data = StorageBuffer::new(Vec::new());
+        kinds_data.write(&kinds).unwrap();
+        let kinds_data = kinds_data.into_inner();
+
+        //growing whatever is too small for this step, with the same 1.2 headroom as the renderer
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        let grow = |buffer: &mut wgpu::Buffer, size: &mut usize, needed: usize, label: &str, usage: wgpu::BufferUsages| {
+            if *size < needed {
+                *size = ((needed as f32 * 1.2) as usize + 3) & !3;
+                *buffer = physics_buffer(device, label, *size, usage);
+                true
+            } else {
+                false
+            }
+        };
+        let mut replaced = grow(
+            &mut self.pairs_buffer,
+            &mut self.pairs_buffer_size,
+            pairs_data.len(),
+            "Physics Pairs Buffer",
+            storage,
+        );
+        replaced |= grow(
+            &mut self.kinds_buffer,
+            &mut self.kinds_buffer_size,
+            kinds_data.len(),
+            "Physics Types Buffer",
+            storage,
+        );
+        replaced |= grow(
+            &mut self.output_buffer,
+            &mut self.output_buffer_size,
+            count * GPU_PARTICLE_SIZE,
+            "Physics Output Buffer",
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        replaced |= grow(
+            &mut self.events_buffer,
+            &mut self.events_buffer_size,
+            count * GPU_EVENT_SIZE,
+            "Physics Events Buffer",
+            storage | wgpu::BufferUsages::COPY_SRC,
+        );
+        if replaced {
+            self.bind_group = None;
+        }
+        queue.write_buffer(&self.params_buffer, 0, &params_data.into_inner());
+        queue.write_buffer(&self.pairs_buffer, 0, &pairs_data);
+        queue.write_buffer(&self.kinds_buffer, 0, &kinds_data);
+
+        let bind_group = self.bind_group.get_or_insert_with(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Physics Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: self.params_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: self.pairs_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: self.kinds_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: particles_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 4, resource: self.output_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 5, resource: self.events_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 6, resource: self.stats_buffer.as_entire_binding() },
+                ],
+            })
         });
 
-        egui::Window::new("Properties")
-        .open(&mut self.window)
-        .resizable(false)
-        .show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                for i in 0..self.particles.id_count as usize {
-                    let mut ui_color = [
-                        self.particles.colors[i].x,
-                        self.particles.colors[i].y,
-                        self.particles.colors[i].z,
-                    ];
-                    ui.color_edit_button_rgb(&mut ui_color);
-                    self.particles.colors[i] = cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
-                }
+        //one invocation per particle, then copying the result over the particles it read
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Physics Encoder"),
+        });
+        if std::mem::take(&mut self.clear_events) {
+            encoder.clear_buffer(&self.events_buffer, 0, None);
+        }
+        //stats only cover the last step
+        encoder.clear_buffer(&self.stats_buffer, 0, None);
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Physics Pass"),
             });
-            
-            for i in 0..self.particles.id_count as usize {
-                ui.horizontal(|ui| {
-                    // Show color for this row
-                    let mut ui_color = [
-                        self.particles.colors[i].x,
-                        self.particles.colors[i].y,
-                        self.particles.colors[i].z,
-                    ];
-                    ui.color_edit_button_rgb(&mut ui_color);
-                    self.particles.colors[i] = cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
-                    
-                    //attraction/repulsion sliders for each particle type
-                    for j in 0..self.particles.id_count as usize {
-                        ui.add(
-                            egui::DragValue::new(&mut self.particles.attraction_matrix[i * self.particles.id_count as usize + j])
-                                .clamp_range(-1.0..=1.0)
-                                .speed(0.01)
-                        );
-                    }
-                });
-            }
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups((count as u32).div_ceil(PHYSICS_WORKGROUP_SIZE), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.output_buffer,
+            0,
+            particles_buffer,
+            GPU_PARTICLES_HEADER_SIZE as wgpu::BufferAddress,
+            (count * GPU_PARTICLE_SIZE) as wgpu::BufferAddress,
+        );
+        queue.submit(Some(encoder.finish()));
+    }
+
+    //copying the first `count` particles, their events and the last step's stats somewhere the
+    //cpu can read them, false while an earlier readback is still in flight
+    fn request_readback(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles_buffer: &wgpu::Buffer,
+        count: usize,
+        generation: u64,
+    ) -> bool {
+        if self.readback.is_some() {
+            return false;
+        }
+        let particles_size = count * GPU_PARTICLE_SIZE;
+        let events_size = count * GPU_EVENT_SIZE;
+        let size = particles_size + events_size + GPU_STATS_SIZE;
+        if self.readback_buffer_size < size {
+            self.readback_buffer_size = ((size as f32 * 1.2) as usize + 3) & !3;
+            self.readback_buffer = physics_buffer(
+                device,
+                "Physics Readback Buffer",
+                self.readback_buffer_size,
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            );
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Physics Readback Encoder"),
         });
-        //created the main 3d view panel
-        egui::CentralPanel::default()
-            .frame(egui::Frame::none().fill(ctx.style().visuals.panel_fill))
-            .show(ctx, |ui| {
-                let (rect, _response) =
-                    ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+        if count > 0 {
+            encoder.copy_buffer_to_buffer(
+                particles_buffer,
+                GPU_PARTICLES_HEADER_SIZE as wgpu::BufferAddress,
+                &self.readback_buffer,
+                0,
+                particles_size as wgpu::BufferAddress,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.events_buffer,
+                0,
+                &self.readback_buffer,
+                particles_size as wgpu::BufferAddress,
+                events_size as wgpu::BufferAddress,
+            );
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.stats_buffer,
+            0,
+            &self.readback_buffer,
+            (particles_size + events_size) as wgpu::BufferAddress,
+            GPU_STATS_SIZE as wgpu::BufferAddress,
+        );
+        //wraps are reported once, the next readback starts counting again
+        encoder.clear_buffer(&self.events_buffer, 0, None);
+        queue.submit(Some(encoder.finish()));
 
-                let mut camera_uniform =
-                    UniformBuffer::new([0; <GpuCamera as ShaderSize>::SHADER_SIZE.get() as _]);
-                camera_uniform
-                    .write(&{
-                        let (forward, _, up) = self.camera.calculate_axes();
-                        GpuCamera {
-                         //created view matrix (camera position and orientation)
-                            view_matrix: cgmath::Matrix4::look_to_rh(
-                                cgmath::point3(
-                                    self.camera.position.x,
-                                    self.camera.position.y,
-                                    self.camera.position.z,
-                                ),
-                                forward,
-                                up,
-                            ),
-                             //created projection matrix
-                            projection_matrix: cgmath::perspective(
-                                cgmath::Rad::from(cgmath::Deg(90.0)),//90 degree field of view
-                                rect.width() / rect.height(),//screen ratio 
-                                0.001,//clipping plane
-                                1000.0,
-                            ),
-                        }
-                    })
-                    .unwrap();
-                let camera = camera_uniform.into_inner();
-                //preparing particle data for gpu
-                let mut particles_storage = StorageBuffer::new(vec![]);
-                particles_storage
-                    .write(&GpuParticles {
-                        world_size: self.particles.world_size,
-                        length: ArrayLength,
-                        particles: &self.particles.active_particles,
-                    })
-                    .unwrap();
-                let particles = particles_storage.into_inner();
-                //preparing color data for gpu
-                let mut colors_storage = StorageBuffer::new(vec![]);
-                colors_storage
-                    .write(&GpuColors {
-                        length: ArrayLength,
-                        particles: &self.particles.colors,
-                    })
-                    .unwrap();
-                let colors = colors_storage.into_inner();
-
-                let sphere_count = self.particles.active_particles.len();
-
-                //setting up the 3d rendering callback
-                ui.painter().add(egui::PaintCallback {
-                    rect,
-                    callback: std::sync::Arc::new(
-                        eframe::egui_wgpu::CallbackFn::new()
-                           //setting up for rendering data
-                            .prepare(move |device, queue, encoder, paint_callback_resources| {
-                                let renderer: &mut Renderer =
-                                    paint_callback_resources.get_mut().unwrap();
-                                renderer
-                                    .update_resources(&camera, &particles, &colors, device, queue, encoder)
-                            })
-                            //rendering
-                            .paint(move |_info, render_pass, paint_callback_resources| {
-                                let renderer: &Renderer = paint_callback_resources.get().unwrap();
-                                renderer.render(sphere_count as _, render_pass);
-                            }),
-                    ),
-                });
-                //updating the display continuously
-                ctx.request_repaint();
+        let mapped = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let done = mapped.clone();
+        self.readback_buffer
+            .slice(..size as wgpu::BufferAddress)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                done.store(result.is_ok(), std::sync::atomic::Ordering::Release)
             });
+        self.readback = Some(PendingReadback { generation, count, mapped });
+        true
+    }
+
+    //the requested readback once it has arrived, `wait` blocks until it has
+    fn poll_readback(&mut self, device: &wgpu::Device, wait: bool) -> Option<GpuReadback> {
+        let pending = self.readback.as_ref()?;
+        device.poll(if wait { wgpu::Maintain::Wait } else { wgpu::Maintain::Poll });
+        if !pending.mapped.load(std::sync::atomic::Ordering::Acquire) {
+            return None;
+        }
+        let pending = self.readback.take().unwrap();
+        let particles_size = pending.count * GPU_PARTICLE_SIZE;
+        let events_size = pending.count * GPU_EVENT_SIZE;
+        let size = particles_size + events_size + GPU_STATS_SIZE;
+        let readback = {
+            let data = self.readback_buffer.slice(..size as wgpu::BufferAddress).get_mapped_range();
+            let particles: Vec<Particle> = if pending.count > 0 {
+                StorageBuffer::new(&data[..particles_size])
+                    .create()
+                    .expect("reading back gpu physics results")
+            } else {
+                vec![]
+            };
+            let word = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+            let events = (0..pending.count).map(|i| particles_size + i * GPU_EVENT_SIZE);
+            let stats = particles_size + events_size;
+            GpuReadback {
+                generation: pending.generation,
+                particles,
+                wrapped: events.clone().map(|event| word(event) != 0).collect(),
+                energy_absorbed_by_walls: events.map(|event| f32::from_bits(word(event + 4))).sum(),
+                step_stats: particle_3d::StepStats {
+                    pair_checks: word(stats) as usize,
+                    effective_interactions: word(stats + 4) as usize,
+                    max_force_magnitude: f32::from_bits(word(stats + 8)),
+                },
+            }
+        };
+        self.readback_buffer.unmap();
+        Some(readback)
     }
 }
 
+//flattening the settings and the per pair and per type tables the shader indexes by particle type
+fn physics_tables(particles: &Particles, ts: f32) -> (GpuPhysicsParams, Vec<GpuPhysicsPair>, Vec<GpuPhysicsKind>) {
+    let id_count = particles.id_count as usize;
+    let pairs: Vec<GpuPhysicsPair> = (0..id_count * id_count)
+        .map(|pair| GpuPhysicsPair {
+            attraction: particles.pair_attraction((pair / id_count) as u32, (pair % id_count) as u32),
+            radius: particles
+                .radius_matrix
+                .get(pair)
+                .copied()
+                .unwrap_or(particles.particle_effect_radius)
+                .max(particle_3d::MIN_EFFECT_RADIUS),
+            force_scale: particles.pair_scale((pair / id_count) as u32, (pair % id_count) as u32),
+        })
+        .collect();
+    let kinds: Vec<GpuPhysicsKind> = (0..id_count)
+        .map(|id| GpuPhysicsKind {
+            mass: particles.masses.get(id).copied().unwrap_or(1.0),
+            gravity_scale: particles.gravity_scale.get(id).copied().unwrap_or(1.0),
+            friction_scale: particles.friction_scale.get(id).copied().unwrap_or(1.0),
+        })
+        .collect();
+    let params = GpuPhysicsParams {
+        world_extents: particles.world_extents(),
+        count: particles.active_particles.len() as u32,
+        gravity: match particles.gravity {
+            GravityMode::None => cgmath::vec3(0.0, 0.0, 0.0),
+            GravityMode::Uniform(acceleration) => acceleration,
+            GravityMode::Point { center, .. } => center,
+        },
+        id_count: particles.id_count,
+        ts,
+        interaction_force: particles.interaction_force,
+        effect_radius: particles.particle_effect_radius,
+        min_pull_ratio: particles.min_pull_ratio,
+        friction: particles.coefficient,
+        restitution: particles.restitution,
+        max_speed: particles.max_speed.map_or(-1.0, |max_speed| max_speed.max(0.0)),
+        rest_speed: particle_3d::REST_SPEED,
+        boundary: match particles.boundary {
+            BoundaryMode::Wrap => 0,
+            BoundaryMode::Walls => 1,
+            BoundaryMode::Open => 2,
+        },
+        lock_y: particles.lock_y as u32,
+        flat: (particles.dimensions == Dim::Two) as u32,
+        gravity_mode: match particles.gravity {
+            GravityMode::None => 0,
+            GravityMode::Uniform(_) => 1,
+            GravityMode::Point { .. } => 2,
+        },
+        gravity_strength: match particles.gravity {
+            GravityMode::Point { strength, .. } => strength,
+            _ => 0.0,
+        },
+        gravity_softening: GravityMode::SOFTENING,
+    };
+    (params, pairs, kinds)
+}
+
 //rendering handles the gpu drawing operations
 struct Renderer {
     camera_uniform_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,//connect camera data to shaders
     particles_storage_buffer: wgpu::Buffer,// buffering for particle positions
     particles_storage_buffer_size: usize,// size tracking for efficient updates
+    draw_order_storage_buffer: wgpu::Buffer,//particle index drawn by each instance
+    draw_order_storage_buffer_size: usize,//size tracking for efficient updates
     colors_storage_buffer: wgpu::Buffer,// buffering for particle colors
     colors_storage_buffer_size: usize,// size tracking for efficient updates
+    color_mode_uniform_buffer: wgpu::Buffer,//how particles are colored
     particles_bind_group_layout: wgpu::BindGroupLayout,//connect particle data to shaders
     particles_bind_group: wgpu::BindGroup, //connection of particle data
     particles_render_pipeline: wgpu::RenderPipeline,//draw particles
+    particles_transparent_render_pipeline: wgpu::RenderPipeline,//draw sorted, blended particles
+    particles_haze_render_pipeline: wgpu::RenderPipeline,//draw opaque particles without depth writes
+    glyph_meshes: Vec<(wgpu::Buffer, wgpu::Buffer, u32)>,//vertex buffer, index buffer and index count per glyph
     border_render_pipeline: wgpu::RenderPipeline,//draw world boundaries
+    grid_vertex_buffer: wgpu::Buffer,//line vertices for the grid overlay
+    grid_vertex_buffer_size: usize,//size tracking for efficient updates
+    grid_render_pipeline: wgpu::RenderPipeline,//draw grid cell wireframes
+    trail_vertex_buffer: wgpu::Buffer,//line vertices for the particle trails
+    trail_vertex_buffer_size: usize,//size tracking for efficient updates
+    trail_render_pipeline: wgpu::RenderPipeline,//draw fading particle trails
+    physics: GpuPhysics,//stepping the particles in particles_storage_buffer when gpu physics is on
+    sample_count: u32,//msaa samples every scene pipeline is built for
+    target_format: wgpu::TextureFormat,//format of the window the scene ends up in
+    scene_target: Option<SceneTarget>,//created on the first frame, recreated when the view resizes
+    blit_bind_group_layout: wgpu::BindGroupLayout,
+    blit_sampler: wgpu::Sampler,
+    blit_render_pipeline: wgpu::RenderPipeline,//copy the resolved scene into egui's render pass
+}
+
+//textures the scene is drawn into before egui shows it, so the msaa level doesn't depend on the
+//window's render pass
+struct SceneTarget {
+    size: [u32; 2],//in physical pixels
+    color: Option<wgpu::TextureView>,//multisampled color, None without msaa
+    resolved: wgpu::TextureView,//single sampled color the blit reads
+    depth: wgpu::TextureView,
+    blit_bind_group: wgpu::BindGroup,
+}
+
+//bytes needed for a runtime sized storage buffer holding `count` elements on top of its
+//minimum size (header plus one element), rounded up to 4 bytes like the resize path
+fn storage_buffer_capacity(min_size: usize, element_size: usize, count: usize) -> usize {
+    (min_size + element_size * count.saturating_sub(1) + 3) & !3
+}
+
+//vertices of the border drawn this frame, the 12 edges of the world box as a line list while
+//it's shown (only this toggle decides, the boundary mode only changes the physics)
+fn border_vertices(show_border: bool) -> std::ops::Range<u32> {
+    if show_border { 0..24 } else { 0..0 }
+}
+
+//bytes per grid line vertex (three f32 coordinates)
+const GRID_VERTEX_SIZE: usize = 3 * std::mem::size_of::<f32>();
+//bytes per trail vertex (three f32 coordinates and an rgba color)
+const TRAIL_VERTEX_SIZE: usize = 7 * std::mem::size_of::<f32>();
+//bytes per glyph vertex (three f32 coordinates)
+const GLYPH_VERTEX_SIZE: usize = 3 * std::mem::size_of::<f32>();
+
+//vertex and index buffer contents of a glyph's mesh, and the index count its draw call uses
+fn glyph_mesh_data(glyph: Glyph) -> (Vec<u8>, Vec<u8>, u32) {
+    let vertices: Vec<u8> = glyph
+        .vertices()
+        .into_iter()
+        .flatten()
+        .flat_map(f32::to_ne_bytes)
+        .collect();
+    let indices = glyph.indices();
+    let index_bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_ne_bytes()).collect();
+    (vertices, index_bytes, indices.len() as u32)
 }
 
 impl Renderer {
-    fn new(render_state: &eframe::egui_wgpu::RenderState) -> Self {
+    //`initial_particles` presizes the particle buffer so the first upload doesn't reallocate
+    fn new(render_state: &eframe::egui_wgpu::RenderState, sample_count: u32, initial_particles: usize) -> Self {
          // loading shader code for particles
         let particles_shader = render_state
             .device
@@ -507,6 +3670,14 @@ impl Renderer {
         let border_shader = render_state
             .device
             .create_shader_module(include_wgsl!("./border.wgsl"));
+        // loading shader code for the grid overlay
+        let grid_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./grid.wgsl"));
+        // loading shader code for the particle trails
+        let trail_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./trails.wgsl"));
 
          //camera data will be passing to shaders
         let camera_bind_group_layout =
@@ -516,7 +3687,7 @@ impl Renderer {
                     label: Some("Camera Bind Group Layout"),
                     entries: &[wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -565,7 +3736,7 @@ impl Renderer {
                         },
                         wgpu::BindGroupLayoutEntry {
                             binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                             ty: wgpu::BindingType::Buffer {
                                 ty: wgpu::BufferBindingType::Storage { read_only: true },
                                 has_dynamic_offset: false,
@@ -573,18 +3744,54 @@ impl Renderer {
                             },
                             count: None,
                         },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(<GpuColorMode as ShaderSize>::SHADER_SIZE),
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(<GpuDrawOrder as ShaderType>::min_size()),
+                            },
+                            count: None,
+                        },
                     ],
                 });
 
         //creating empty buffer to store particle positions on gpu for fast access
-        const PARTICLES_STORAGE_BUFFER_SIZE: usize =
-            <GpuParticles as ShaderType>::METADATA.min_size().get() as _;
+        let particles_storage_buffer_size = storage_buffer_capacity(
+            <GpuParticles as ShaderType>::METADATA.min_size().get() as _,
+            <Particle as ShaderSize>::SHADER_SIZE.get() as _,
+            initial_particles,
+        );
         let particles_storage_buffer =
             render_state
                 .device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: Some("Particles Storage Buffer"),
-                    contents: &[0; PARTICLES_STORAGE_BUFFER_SIZE],
+                    contents: &vec![0; particles_storage_buffer_size],
+                    //copy source so gpu physics can read the particles back
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+                });
+
+        //creating empty buffer for the draw order, grows once something is sorted
+        const DRAW_ORDER_STORAGE_BUFFER_SIZE: usize =
+            <GpuDrawOrder as ShaderType>::METADATA.min_size().get() as _;
+        let draw_order_storage_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Draw Order Storage Buffer"),
+                    contents: &[0; DRAW_ORDER_STORAGE_BUFFER_SIZE],
                     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
                 });
 
@@ -600,6 +3807,16 @@ impl Renderer {
                     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
                 });
 
+        //creating a buffer for the color mode settings
+        let color_mode_uniform_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Color Mode Uniform Buffer"),
+                    contents: &[0; <GpuColorMode as ShaderSize>::SHADER_SIZE.get() as _],
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+                });
+
         //connecting our particle and color data to gpu memory
         let particles_bind_group =
             render_state
@@ -616,6 +3833,14 @@ impl Renderer {
                             binding: 1,
                             resource: colors_storage_buffer.as_entire_binding(),
                         },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: color_mode_uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: draw_order_storage_buffer.as_entire_binding(),
+                        },
                     ],
                 });
 
@@ -630,25 +3855,174 @@ impl Renderer {
                 });
 
         //setting up how particles will be drawn fast because gpu handles all particles in parallel
-        let particles_render_pipeline =
+        //(transparent version blends and skips depth writes so sorted particles show through,
+        //haze version is opaque but skips depth writes so it never hides other types)
+        let create_particles_pipeline = |label, transparent: bool, depth_write: bool| {
             render_state
                 .device
                 .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Particles Render Pipeline"),
+                    label: Some(label),
                     layout: Some(&particles_pipeline_layout),
                     vertex: wgpu::VertexState {
                         module: &particles_shader,
                         entry_point: "vs_main",
-                        buffers: &[],
+                        //glyph-local positions, the same mesh repeated for every particle instance
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: GLYPH_VERTEX_SIZE as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                        }],
                     },
                     fragment: Some(wgpu::FragmentState {
                         module: &particles_shader,
                         entry_point: "fs_main",
-                        targets: &[Some(render_state.target_format.into())],
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: render_state.target_format,
+                            blend: transparent.then_some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
                     }),
                     primitive: wgpu::PrimitiveState {
                         polygon_mode: wgpu::PolygonMode::Fill,
-                        topology: wgpu::PrimitiveTopology::TriangleStrip,
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: wgpu::TextureFormat::Depth32Float,
+                        depth_write_enabled: depth_write,
+                        depth_compare: wgpu::CompareFunction::Less,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                })
+        };
+        let particles_render_pipeline = create_particles_pipeline("Particles Render Pipeline", false, true);
+        let particles_transparent_render_pipeline =
+            create_particles_pipeline("Particles Transparent Render Pipeline", true, false);
+        let particles_haze_render_pipeline =
+            create_particles_pipeline("Particles Haze Render Pipeline", false, false);
+
+        //uploading every glyph's mesh once, the glyph uniform picks which one is drawn
+        let glyph_meshes = Glyph::ALL
+            .iter()
+            .map(|&glyph| {
+                let (vertices, index_bytes, index_count) = glyph_mesh_data(glyph);
+                let vertex_buffer =
+                    render_state
+                        .device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Glyph Vertex Buffer"),
+                            contents: &vertices,
+                            usage: wgpu::BufferUsages::VERTEX,
+                        });
+                let index_buffer =
+                    render_state
+                        .device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Glyph Index Buffer"),
+                            contents: &index_bytes,
+                            usage: wgpu::BufferUsages::INDEX,
+                        });
+                (vertex_buffer, index_buffer, index_count)
+            })
+            .collect();
+
+        //setting up how borders will use camera and particle data
+        let border_pipeline_layout =
+            render_state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Border Pipeline Layout"),
+                    bind_group_layouts: &[&camera_bind_group_layout, &particles_bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+
+        //setting up how box borders will be drawn
+        let border_render_pipeline = {
+            let vertex_state = wgpu::VertexState {
+                module: &border_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            };
+        
+            let fragment_state = wgpu::FragmentState {
+                module: &border_shader,
+                entry_point: "fs_main",
+                targets: &[Some(render_state.target_format.into())],
+            };
+        
+            let primitive_state = wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Line,
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            };
+        
+            let depth_stencil_state = wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            };
+        
+            let multisample_state = wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            };
+        
+            render_state
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Border Render Pipeline"),
+                    layout: Some(&border_pipeline_layout),
+                    vertex: vertex_state,
+                    fragment: Some(fragment_state),
+                    primitive: primitive_state,
+                    depth_stencil: Some(depth_stencil_state),
+                    multisample: multisample_state,
+                    multiview: None,
+                })
+        };
+
+        //grid overlay only needs the camera, its vertices come from a vertex buffer
+        let grid_pipeline_layout =
+            render_state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Grid Pipeline Layout"),
+                    bind_group_layouts: &[&camera_bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+
+        //setting up how grid cell boxes will be drawn as lines
+        let grid_render_pipeline =
+            render_state
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Grid Render Pipeline"),
+                    layout: Some(&grid_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &grid_shader,
+                        entry_point: "vs_main",
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: GRID_VERTEX_SIZE as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                        }],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &grid_shader,
+                        entry_point: "fs_main",
+                        targets: &[Some(render_state.target_format.into())],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        polygon_mode: wgpu::PolygonMode::Line,
+                        topology: wgpu::PrimitiveTopology::LineList,
                         ..Default::default()
                     },
                     depth_stencil: Some(wgpu::DepthStencilState {
@@ -659,80 +4033,168 @@ impl Renderer {
                         bias: wgpu::DepthBiasState::default(),
                     }),
                     multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                });
+
+        //trails are blended over the scene without hiding what's behind them
+        let trail_render_pipeline =
+            render_state
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Trail Render Pipeline"),
+                    layout: Some(&grid_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &trail_shader,
+                        entry_point: "vs_main",
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: TRAIL_VERTEX_SIZE as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
+                        }],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &trail_shader,
+                        entry_point: "fs_main",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: render_state.target_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::LineList,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: wgpu::TextureFormat::Depth32Float,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::Less,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
                         ..Default::default()
                     },
                     multiview: None,
                 });
-
-        //setting up how borders will use camera and particle data
-        let border_pipeline_layout =
+
+        //starting with room for a single box, grows when needed
+        const GRID_VERTEX_BUFFER_SIZE: usize = 24 * GRID_VERTEX_SIZE;
+        let grid_vertex_buffer = render_state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grid Vertex Buffer"),
+            size: GRID_VERTEX_BUFFER_SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        //starting with room for a single segment, grows when needed
+        const TRAIL_VERTEX_BUFFER_SIZE: usize = 2 * TRAIL_VERTEX_SIZE;
+        //drawing the finished scene into egui's render pass, no depth or msaa there
+        let blit_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./blit.wgsl"));
+        let blit_bind_group_layout =
+            render_state
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Blit Bind Group Layout"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                });
+        let blit_pipeline_layout =
             render_state
                 .device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Border Pipeline Layout"),
-                    bind_group_layouts: &[&camera_bind_group_layout, &particles_bind_group_layout],
+                    label: Some("Blit Pipeline Layout"),
+                    bind_group_layouts: &[&blit_bind_group_layout],
                     push_constant_ranges: &[],
                 });
-
-        //setting up how box borders will be drawn
-        let border_render_pipeline = {
-            let vertex_state = wgpu::VertexState {
-                module: &border_shader,
-                entry_point: "vs_main",
-                buffers: &[],
-            };
-        
-            let fragment_state = wgpu::FragmentState {
-                module: &border_shader,
-                entry_point: "fs_main",
-                targets: &[Some(render_state.target_format.into())],
-            };
-        
-            let primitive_state = wgpu::PrimitiveState {
-                polygon_mode: wgpu::PolygonMode::Line,
-                topology: wgpu::PrimitiveTopology::LineList,
-                ..Default::default()
-            };
-        
-            let depth_stencil_state = wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            };
-        
-            let multisample_state = wgpu::MultisampleState {
-                ..Default::default()
-            };
-        
+        let blit_render_pipeline =
             render_state
                 .device
                 .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Border Render Pipeline"),
-                    layout: Some(&border_pipeline_layout),
-                    vertex: vertex_state,
-                    fragment: Some(fragment_state),
-                    primitive: primitive_state,
-                    depth_stencil: Some(depth_stencil_state),
-                    multisample: multisample_state,
+                    label: Some("Blit Render Pipeline"),
+                    layout: Some(&blit_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &blit_shader,
+                        entry_point: "vs_main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &blit_shader,
+                        entry_point: "fs_main",
+                        targets: &[Some(render_state.target_format.into())],
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
                     multiview: None,
-                })
-        };
-        
+                });
+        //linear so a view a pixel off the texture size still looks right
+        let blit_sampler = render_state.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blit Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let trail_vertex_buffer = render_state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail Vertex Buffer"),
+            size: TRAIL_VERTEX_BUFFER_SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         //collecting all the gpu memory and rendering pipelines
         Self {
             camera_uniform_buffer,
             camera_bind_group,
             particles_storage_buffer,
-            particles_storage_buffer_size: PARTICLES_STORAGE_BUFFER_SIZE,
+            particles_storage_buffer_size,
+            draw_order_storage_buffer,
+            draw_order_storage_buffer_size: DRAW_ORDER_STORAGE_BUFFER_SIZE,
             colors_storage_buffer,
             colors_storage_buffer_size: COLORS_STORAGE_BUFFER_SIZE,
+            color_mode_uniform_buffer,
             particles_bind_group_layout,
             particles_bind_group,
             particles_render_pipeline,
+            particles_transparent_render_pipeline,
+            particles_haze_render_pipeline,
+            glyph_meshes,
             border_render_pipeline,
+            grid_vertex_buffer,
+            grid_vertex_buffer_size: GRID_VERTEX_BUFFER_SIZE,
+            grid_render_pipeline,
+            trail_vertex_buffer,
+            trail_vertex_buffer_size: TRAIL_VERTEX_BUFFER_SIZE,
+            trail_render_pipeline,
+            physics: GpuPhysics::new(&render_state.device),
+            sample_count,
+            target_format: render_state.target_format,
+            scene_target: None,
+            blit_bind_group_layout,
+            blit_sampler,
+            blit_render_pipeline,
         }
     }
 
@@ -741,35 +4203,39 @@ impl Renderer {
         &mut self,
         camera_data: &[u8],
         particle_data: &[u8],
+        draw_order_data: &[u8],
         color_data: &[u8],
+        color_mode_data: &[u8],
+        grid_data: &[u8],
+        trail_data: &[u8],
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         _cmd_encoder: &wgpu::CommandEncoder,
     ) -> Vec<wgpu::CommandBuffer> {
         //update camera
         queue.write_buffer(&self.camera_uniform_buffer, 0, camera_data);
+        queue.write_buffer(&self.color_mode_uniform_buffer, 0, color_mode_data);
         
         //track if we need to recreate the bind group
         let mut needs_bind_group_update = false;
         
-        //handle particle buffer resizing with memory alignment to 4 bytes
-        let particle_size_aligned = (particle_data.len() + 3) & !3;
-        if self.particles_storage_buffer_size < particle_size_aligned {
-            //apply growth factor of 1.2 to reduce future reallocations
-            let target_size = ((particle_size_aligned as f32 * 1.2) as usize + 3) & !3;
-            
-            //create new buffer with increased capacity
-            self.particles_storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("Particle Data Buffer"),
+        //handle particle buffer resizing
+        needs_bind_group_update |= self.grow_particles_buffer(device, particle_data.len());
+        
+        //same for the draw order
+        let draw_order_size_aligned = (draw_order_data.len() + 3) & !3;
+        if self.draw_order_storage_buffer_size < draw_order_size_aligned {
+            let target_size = ((draw_order_size_aligned as f32 * 1.2) as usize + 3) & !3;
+            self.draw_order_storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Draw Order Buffer"),
                 size: target_size as wgpu::BufferAddress,
                 usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             });
-            
-            self.particles_storage_buffer_size = target_size;
+            self.draw_order_storage_buffer_size = target_size;
             needs_bind_group_update = true;
         }
-        
+
         //similar process for color buffer resizing
         let color_size_aligned = (color_data.len() + 3) & !3;
         if self.colors_storage_buffer_size < color_size_aligned {
@@ -788,46 +4254,274 @@ impl Renderer {
             needs_bind_group_update = true;
         }
         
+        //growing the grid vertex buffer, no bind group needed since it's a vertex buffer
+        if self.grid_vertex_buffer_size < grid_data.len() {
+            let target_size = ((grid_data.len() as f32 * 1.2) as usize + 3) & !3;
+            self.grid_vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Grid Vertex Buffer"),
+                size: target_size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            self.grid_vertex_buffer_size = target_size;
+        }
+        if !grid_data.is_empty() {
+            queue.write_buffer(&self.grid_vertex_buffer, 0, grid_data);
+        }
+
+        //same for the trail vertex buffer
+        if self.trail_vertex_buffer_size < trail_data.len() {
+            let target_size = ((trail_data.len() as f32 * 1.2) as usize + 3) & !3;
+            self.trail_vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Trail Vertex Buffer"),
+                size: target_size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            self.trail_vertex_buffer_size = target_size;
+        }
+        if !trail_data.is_empty() {
+            queue.write_buffer(&self.trail_vertex_buffer, 0, trail_data);
+        }
+        
         //transfer the actual data to GPU memory
         queue.write_buffer(&self.particles_storage_buffer, 0, particle_data);
+        queue.write_buffer(&self.draw_order_storage_buffer, 0, draw_order_data);
         queue.write_buffer(&self.colors_storage_buffer, 0, color_data);
         
         //regenerate bind group if buffer references changed
         if needs_bind_group_update {
-            self.particles_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Particle System Bind Group"),
-                layout: &self.particles_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: self.particles_storage_buffer.as_entire_binding(),
+            self.rebuild_particles_bind_group(device);
+        }
+        
+        Vec::new()
+    }
+
+    //replacing the particles gpu physics steps, the render path uploads them itself otherwise
+    fn upload_particles(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, particle_data: &[u8]) {
+        if self.grow_particles_buffer(device, particle_data.len()) {
+            self.rebuild_particles_bind_group(device);
+        }
+        queue.write_buffer(&self.particles_storage_buffer, 0, particle_data);
+        self.physics.particles_replaced();
+    }
+
+    //making room for `size` bytes of particle data, true if the buffer was replaced
+    fn grow_particles_buffer(&mut self, device: &wgpu::Device, size: usize) -> bool {
+        //memory alignment to 4 bytes
+        let particle_size_aligned = (size + 3) & !3;
+        if self.particles_storage_buffer_size >= particle_size_aligned {
+            return false;
+        }
+        //apply growth factor of 1.2 to reduce future reallocations
+        let target_size = ((particle_size_aligned as f32 * 1.2) as usize + 3) & !3;
+        
+        //create new buffer with increased capacity, only uploads grow it so nothing needs copying
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Data Buffer"),
+            size: target_size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        self.particles_storage_buffer = buffer;
+        self.particles_storage_buffer_size = target_size;
+        self.physics.invalidate();
+        true
+    }
+
+    //textures for a `size` pixel view at the renderer's msaa level
+    fn create_scene_target(&self, device: &wgpu::Device, size: [u32; 2]) -> SceneTarget {
+        let texture = |label: &str, format: wgpu::TextureFormat, sample_count: u32, usage: wgpu::TextureUsages| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: size[0],
+                        height: size[1],
+                        depth_or_array_layers: 1,
                     },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: self.colors_storage_buffer.as_entire_binding(),
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let resolved = texture(
+            "Scene Texture",
+            self.target_format,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let color = (self.sample_count > 1).then(|| {
+            texture(
+                "Scene Multisampled Texture",
+                self.target_format,
+                self.sample_count,
+                wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+        });
+        let depth = texture(
+            "Scene Depth Texture",
+            wgpu::TextureFormat::Depth32Float,
+            self.sample_count,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+        let blit_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit Bind Group"),
+            layout: &self.blit_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&resolved),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.blit_sampler),
+                },
+            ],
+        });
+        SceneTarget { size, color, resolved, depth, blit_bind_group }
+    }
+
+    //drawing the scene into the scene target (resizing it to `size` first), resolving msaa on the way
+    fn render_scene(
+        &mut self,
+        device: &wgpu::Device,
+        size: [u32; 2],
+        clear_color: wgpu::Color,
+        batches: &[RenderBatch],
+        grid_vertices: u32,
+        trail_vertices: u32,
+        transparent: bool,
+        show_border: bool,
+    ) -> wgpu::CommandBuffer {
+        let size = size.map(|length| length.max(1));
+        if self.scene_target.as_ref().is_none_or(|target| target.size != size) {
+            self.scene_target = Some(self.create_scene_target(device, size));
+        }
+        let target = self.scene_target.as_ref().unwrap();
+        let (view, resolve_target) = match &target.color {
+            Some(color) => (color, Some(&target.resolved)),
+            None => (&target.resolved, None),
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Scene Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Scene Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear_color),
+                        store: true,
                     },
-                ],
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &target.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
             });
+            self.render(batches, grid_vertices, trail_vertices, transparent, show_border, &mut pass);
         }
-        
-        Vec::new()
+        encoder.finish()
+    }
+
+    //showing the last rendered scene in egui's render pass (the callback's viewport)
+    fn blit<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(target) = &self.scene_target {
+            pass.set_pipeline(&self.blit_render_pipeline);
+            pass.set_bind_group(0, &target.blit_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    //pointing the particle shaders at the current storage buffers
+    fn rebuild_particles_bind_group(&mut self, device: &wgpu::Device) {
+        self.particles_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle System Bind Group"),
+            layout: &self.particles_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.particles_storage_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.colors_storage_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.color_mode_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.draw_order_storage_buffer.as_entire_binding(),
+                },
+            ],
+        });
     }
         
 
         
 
         //telling gpu which camera and particle data to use
-        fn render<'a>(&'a self, particle_instances: u32, pass: &mut wgpu::RenderPass<'a>) {
+        fn render<'a>(
+            &'a self,
+            batches: &[RenderBatch],
+            grid_vertices: u32,
+            trail_vertices: u32,
+            transparent: bool,
+            show_border: bool,
+            pass: &mut wgpu::RenderPass<'a>,
+        ) {
             pass.set_bind_group(0, &self.camera_bind_group, &[]);
             pass.set_bind_group(1, &self.particles_bind_group, &[]);
             
-            if particle_instances > 0 {
+            if !batches.is_empty() {
                 // First render the container borders
-                pass.set_pipeline(&self.border_render_pipeline);
-                pass.draw(0..24, 0..1);
+                let border = border_vertices(show_border);
+                if !border.is_empty() {
+                    pass.set_pipeline(&self.border_render_pipeline);
+                    pass.draw(border, 0..1);
+                }
                 
-                pass.set_pipeline(&self.particles_render_pipeline);
-                pass.draw(0..4, 0..particle_instances);
+                //one instance of the batch's glyph mesh per particle (batches come depth writing first)
+                for batch in batches {
+                    let (vertex_buffer, index_buffer, index_count) = &self.glyph_meshes[batch.glyph as usize];
+                    pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    if transparent {
+                        pass.set_pipeline(&self.particles_transparent_render_pipeline);
+                    } else if batch.depth_write {
+                        pass.set_pipeline(&self.particles_render_pipeline);
+                    } else {
+                        pass.set_pipeline(&self.particles_haze_render_pipeline);
+                    }
+                    pass.draw_indexed(0..*index_count, 0, batch.instances.clone());
+                }
+            }
+
+            //debug overlay of occupied grid cells
+            if grid_vertices > 0 {
+                pass.set_pipeline(&self.grid_render_pipeline);
+                pass.set_vertex_buffer(0, self.grid_vertex_buffer.slice(..));
+                pass.draw(0..grid_vertices, 0..1);
+            }
+
+            //trails last so they blend over everything opaque
+            if trail_vertices > 0 {
+                pass.set_pipeline(&self.trail_render_pipeline);
+                pass.set_vertex_buffer(0, self.trail_vertex_buffer.slice(..));
+                pass.draw(0..trail_vertices, 0..1);
             }
         }
 }
@@ -838,8 +4532,7 @@ fn main() {
         eframe::NativeOptions {
             renderer: eframe::Renderer::Wgpu,
             wgpu_options: eframe::egui_wgpu::WgpuConfiguration {
-                present_mode: wgpu::PresentMode::AutoNoVsync,
-                depth_format: Some(wgpu::TextureFormat::Depth32Float), //disable vsync for max speed            
+                present_mode: wgpu::PresentMode::AutoNoVsync,//disable vsync for max speed
                 device_descriptor: wgpu::DeviceDescriptor {
                     features: wgpu::Features::POLYGON_MODE_LINE,
                     ..Default::default()
@@ -847,10 +4540,770 @@ fn main() {
                 ..Default::default()
             },
             vsync: false,
-            depth_buffer: 32,//turned off for faster rendering
+            //no depth buffer or msaa on the window, the scene is drawn into the renderer's own
+            //targets so the msaa level can change while running
             ..Default::default()
         },
         Box::new(|cc| Box::new(SimulationApp::new(cc))),
     )
     .unwrap();
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(id: u32) -> Particle {
+        Particle {
+            position: cgmath::vec3(0.0, 0.0, 0.0),
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            id,
+        }
+    }
+
+    fn scrub_state(id: u32, count: usize) -> ScrubState {
+        ScrubState {
+            particles: vec![particle(id); count],
+            previous_accelerations: vec![cgmath::vec3(id as f32, 0.0, 0.0); count],
+            wrapped: vec![id % 2 == 0; count],
+            double_state: vec![],
+            sim_time: id as f32,
+        }
+    }
+
+    #[test]
+    fn scrub_history_drops_the_oldest_states_over_its_byte_cap() {
+        let state_bytes = scrub_state(0, 10).bytes();
+        let mut history = ScrubHistory::new(3 * state_bytes);
+        for step in 0..5 {
+            history.push(scrub_state(step, 10));
+        }
+        assert_eq!(history.states.len(), 3);
+        assert_eq!(history.bytes, 3 * state_bytes);
+        assert_eq!(history.last_particles().map(|p| p[0].id), Some(4));
+        assert_eq!(history.pop(), Some(scrub_state(4, 10)));
+        //the particles before the popped state
+        assert_eq!(history.last_particles().map(|p| p[0].id), Some(3));
+        assert_eq!(history.bytes, 2 * state_bytes);
+        //a state bigger than the whole cap isn't kept
+        history.push(scrub_state(9, 40));
+        assert!(history.pop().is_none());
+        assert_eq!(history.bytes, 0);
+    }
+
+    #[test]
+    fn scrubbing_back_a_verlet_step_steps_on_identically() {
+        let mut sim = Particles::builder()
+            .integrator(Integrator::VelocityVerlet)
+            .particles(particle_3d::generate_particles_seeded(10.0, 200, 5, 3))
+            .build()
+            .unwrap();
+        sim.update(0.02).unwrap();
+        let before = ScrubState::capture(&sim);
+        let past_particles = sim.past_particles.clone();
+        sim.update(0.02).unwrap();
+        let stepped = ScrubState::capture(&sim);
+
+        sim.update(0.02).unwrap();
+        before.restore(&mut sim, past_particles);
+        sim.update(0.02).unwrap();
+        assert_eq!(ScrubState::capture(&sim), stepped);
+    }
+
+    #[test]
+    fn orbit_pivot_is_the_center_of_mass_or_the_world_center() {
+        let mut sim = Particles::builder().id_count(2).build().unwrap();
+        sim.active_particles.clear();
+        assert_eq!(orbit_pivot(&sim), cgmath::vec3(0.0, 0.0, 0.0));
+        sim.active_particles = vec![particle(0), particle(1)];
+        sim.active_particles[0].position = cgmath::vec3(2.0, 1.0, -3.0);
+        sim.active_particles[1].position = cgmath::vec3(4.0, 1.0, -1.0);
+        assert_eq!(orbit_pivot(&sim), cgmath::vec3(3.0, 1.0, -2.0));
+    }
+
+    #[test]
+    fn orbiting_a_pivot_keeps_the_eye_and_looks_at_the_pivot() {
+        let pivot = cgmath::vec3(3.0, 1.0, -2.0);
+        let orbit = CameraMode::Orbit { target: cgmath::vec3(0.0, 0.0, 0.0), distance: 8.0, azimuth: 30.0, elevation: 20.0 };
+        for mode in [CameraMode::Fps, orbit] {
+            let mut camera = CameraSystem {
+                position: cgmath::vec3(1.0, 4.0, 10.0),
+                up: cgmath::vec3(0.0, 1.0, 0.0),
+                pitch: 0.0,
+                yaw: 0.0,
+                speed: 1.0,
+                level_horizon: true,
+                mode,
+            };
+            let eye = camera.eye();
+            camera.orbit_around(pivot);
+            assert!(matches!(camera.mode, CameraMode::Orbit { target, .. } if target == pivot));
+            assert!((camera.eye() - eye).magnitude() < 1e-4);
+            let (forward, _, _) = camera.calculate_axes();
+            assert!(forward.dot((pivot - eye).normalize()) > 0.9999);
+        }
+    }
+
+    #[test]
+    fn scrubber_steps_once_on_a_press_then_at_its_rate() {
+        let mut scrubber = Scrubber { rate: 10.0, pending: 0.0, last_direction: 0 };
+        //a fresh press steps right away
+        assert_eq!(scrubber.advance(1, 0.01), 1);
+        //holding adds rate * dt, carrying fractions over
+        assert_eq!(scrubber.advance(1, 0.05), 0);
+        assert_eq!(scrubber.advance(1, 0.06), 1);
+        assert_eq!(scrubber.advance(1, 0.35), 3);
+        //turning around starts over with one step right away
+        assert_eq!(scrubber.advance(-1, 0.5), 1);
+        assert_eq!(scrubber.last_direction, -1);
+        //letting go takes no steps and forgets what was pending
+        assert_eq!(scrubber.advance(0, 0.5), 0);
+        assert_eq!(scrubber.pending, 0.0);
+        assert_eq!(scrubber.advance(0, 0.5), 0);
+        assert_eq!(scrubber.advance(-1, 0.0), 1);
+    }
+
+    #[test]
+    fn frustum_planes_keep_what_the_camera_sees() {
+        let view = cgmath::Matrix4::look_to_rh(
+            cgmath::point3(0.0, 0.0, 0.0),
+            cgmath::vec3(0.0, 0.0, -1.0),
+            cgmath::vec3(0.0, 1.0, 0.0),
+        );
+        let projection = cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.1, 100.0);
+        let planes = frustum_planes(projection * view);
+        let inside = |p: cgmath::Vector3<f32>| planes.iter().all(|plane| plane.dot(p.extend(1.0)) >= 0.0);
+        for plane in &planes {
+            assert!((plane.truncate().magnitude() - 1.0).abs() < 1e-5);
+        }
+        //straight ahead and near the edges of a 90 degree view
+        assert!(inside(cgmath::vec3(0.0, 0.0, -5.0)));
+        assert!(inside(cgmath::vec3(4.9, -4.9, -5.0)));
+        //behind, off to each side, in front of the near plane and past the far plane
+        for outside in [
+            cgmath::vec3(0.0, 0.0, 5.0),
+            cgmath::vec3(5.1, 0.0, -5.0),
+            cgmath::vec3(-5.1, 0.0, -5.0),
+            cgmath::vec3(0.0, 5.1, -5.0),
+            cgmath::vec3(0.0, -5.1, -5.0),
+            cgmath::vec3(0.0, 0.0, -0.05),
+            cgmath::vec3(0.0, 0.0, -150.0),
+        ] {
+            assert!(!inside(outside), "{:?}", outside);
+        }
+        //distances are in world units, so a particle's radius can be compared directly
+        let right = planes[1];
+        assert!((right.dot(cgmath::vec4(5.0 + 2.0f32.sqrt(), 0.0, -5.0, 1.0)) + 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn srgb_and_linear_conversions_round_trip() {
+        for i in 0..=1000 {
+            let c = i as f32 / 1000.0;
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-5, "{}", c);
+            assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < 1e-5, "{}", c);
+        }
+        //the ends and a known mid gray stay put
+        assert_eq!((srgb_to_linear(0.0), srgb_to_linear(1.0)), (0.0, 1.0));
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.21404).abs() < 1e-4);
+        //both pieces of the curve meet without a jump
+        assert!((linear_to_srgb(0.0031308) - 0.04045).abs() < 1e-4);
+    }
+
+    #[test]
+    fn trail_styles_fall_back_to_the_global_length_and_type_color() {
+        let colors = [cgmath::vec3(1.0, 0.0, 0.0), cgmath::vec3(0.0, 1.0, 0.0), cgmath::vec3(0.0, 0.0, 1.0)];
+        let mut configs = vec![TypeRenderConfig::default(); 2];
+        configs[1].trail_length = Some(3);
+        configs[1].trail_color = Some([1.0, 1.0, 0.0]);
+        let styles = trail_styles(&colors, &configs, 8);
+        assert_eq!(styles[0], (8, colors[0]));
+        assert_eq!(styles[1], (3, cgmath::vec3(1.0, 1.0, 0.0)));
+        //types without a config use the defaults too
+        assert_eq!(styles[2], (8, colors[2]));
+        //lengths stay within what the history can hold
+        configs[0].trail_length = Some(MAX_TRAIL_LENGTH + 10);
+        assert_eq!(trail_styles(&colors, &configs, 8)[0].0, MAX_TRAIL_LENGTH);
+    }
+
+    #[test]
+    fn each_type_draws_its_own_trail_length_and_fade() {
+        assert_eq!(trail_fade(0, 4), 1.0);
+        assert_eq!(trail_fade(4, 4), 0.0);
+        assert_eq!(trail_fade(1, 4), 0.75);
+
+        //five recorded steps of two particles moving along x
+        let history: std::collections::VecDeque<_> = (0..5)
+            .map(|step| (vec![cgmath::vec3(step as f32, 0.0, 0.0), cgmath::vec3(step as f32, 1.0, 0.0)], vec![false; 2]))
+            .collect();
+        let particles = [particle(0), particle(1)];
+        let styles = [(5, cgmath::vec3(1.0, 0.0, 0.0)), (2, cgmath::vec3(0.0, 0.0, 1.0))];
+        let vertices = trail_vertices(&history, &particles, &styles);
+        let segments: Vec<&[f32]> = vertices.chunks(14).collect();
+        //four segments for the long trail and only the newest one for the short trail
+        assert_eq!(segments.len(), 5);
+        let long: Vec<_> = segments.iter().filter(|segment| segment[1] == 0.0).collect();
+        let short: Vec<_> = segments.iter().filter(|segment| segment[1] == 1.0).collect();
+        assert_eq!(long.len(), 4);
+        assert_eq!((long[0][6], long[0][13]), (0.0, 0.25));
+        assert_eq!((long[3][6], long[3][13]), (0.75, 1.0));
+        assert_eq!(short.len(), 1);
+        assert_eq!((short[0][0], short[0][7]), (3.0, 4.0));
+        assert_eq!((short[0][6], short[0][13]), (0.0, 1.0));
+        assert_eq!(&short[0][3..6], &[0.0, 0.0, 1.0]);
+
+        //a wrap breaks the trail at that step only
+        let mut wrapped = history.clone();
+        wrapped[4].1[0] = true;
+        assert_eq!(trail_vertices(&wrapped, &particles, &styles).len(), 4 * 14);
+    }
+
+    #[test]
+    fn matching_configs_draw_one_batch_in_simulation_order() {
+        let particles = [0, 1, 2, 1, 0].map(particle);
+        let configs = vec![TypeRenderConfig::default(); 3];
+        let (order, batches) = render_batches(&particles, None, &configs);
+        assert_eq!(order, None);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].instances, 0..5);
+    }
+
+    #[test]
+    fn haze_types_are_batched_after_depth_writing_types() {
+        let particles = [0, 1, 0, 1].map(particle);
+        let mut configs = vec![TypeRenderConfig::default(); 2];
+        configs[1].depth_write = false;
+        let (order, batches) = render_batches(&particles, None, &configs);
+        assert_eq!(order, Some(vec![0, 2, 1, 3]));
+        assert_eq!(batches.len(), 2);
+        assert!(batches[0].depth_write);
+        assert!(!batches[1].depth_write);
+        assert_eq!(batches[0].instances, 0..2);
+        assert_eq!(batches[1].instances, 2..4);
+    }
+
+    #[test]
+    fn batching_keeps_the_given_order_inside_each_batch() {
+        let particles = [0, 1, 0, 1].map(particle);
+        let mut configs = vec![TypeRenderConfig::default(); 2];
+        configs[1].depth_write = false;
+        let (order, _) = render_batches(&particles, Some(vec![3, 2, 1, 0]), &configs);
+        assert_eq!(order, Some(vec![2, 0, 3, 1]));
+    }
+
+    #[test]
+    fn gpu_info_describes_the_adapter_and_limits() {
+        let adapter = wgpu::AdapterInfo {
+            name: "Test GPU".to_string(),
+            vendor: 0x10de,
+            device: 0x1234,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            driver: "testdriver".to_string(),
+            driver_info: "1.2.3".to_string(),
+            backend: wgpu::Backend::Vulkan,
+        };
+        assert_eq!(adapter_summary(&adapter), "Test GPU (Vulkan, DiscreteGpu, driver testdriver 1.2.3)");
+        let limits = wgpu::Limits::default();
+        let lines = gpu_info_lines(&adapter, wgpu::TextureFormat::Bgra8UnormSrgb, &limits);
+        assert_eq!(lines[0], "Adapter: Test GPU (Vulkan, DiscreteGpu, driver testdriver 1.2.3)");
+        assert_eq!(lines[1], "Surface Format: Bgra8UnormSrgb");
+        assert_eq!(lines[2], format!("Max Texture Size: {}", limits.max_texture_dimension_2d));
+        assert_eq!(lines.len(), 6);
+    }
+
+    #[test]
+    fn presets_set_their_documented_settings() {
+        let settings = |sample_count, update_rate, smooth_rendering| PresetSettings {
+            sample_count,
+            update_rate,
+            smooth_rendering,
+            frustum_culling: true,
+        };
+        assert_eq!(QualityPreset::Performance.settings(), settings(1, 30.0, false));
+        assert_eq!(QualityPreset::Balanced.settings(), settings(4, 60.0, false));
+        assert_eq!(QualityPreset::Quality.settings(), settings(4, 120.0, true));
+    }
+
+    #[test]
+    fn sample_counts_need_color_depth_and_resolve_support() {
+        use wgpu::TextureFormatFeatureFlags as Flags;
+        let color = Flags::MULTISAMPLE_X4 | Flags::MULTISAMPLE_X8 | Flags::MULTISAMPLE_RESOLVE;
+        assert_eq!(sample_counts(color, Flags::MULTISAMPLE_X4), vec![1, 4]);
+        assert_eq!(sample_counts(color, Flags::MULTISAMPLE_X4 | Flags::MULTISAMPLE_X8), vec![1, 4, 8]);
+        //without resolve only the single sampled scene works
+        assert_eq!(sample_counts(Flags::MULTISAMPLE_X4, Flags::MULTISAMPLE_X4), vec![1]);
+    }
+
+    #[test]
+    fn requested_sample_count_rounds_down_to_a_supported_one() {
+        assert_eq!(clamp_sample_count(8, &[1, 4]), 4);
+        assert_eq!(clamp_sample_count(4, &[1, 4]), 4);
+        assert_eq!(clamp_sample_count(2, &[1, 4]), 1);
+        assert_eq!(clamp_sample_count(16, &[1]), 1);
+    }
+
+    //a device for the gpu tests, None on machines without an adapter (the tests pass there)
+    fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+    }
+
+    #[test]
+    fn gpu_step_matches_cpu_step() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        const TS: f32 = 0.01;
+        let mut cpu = Particles::builder()
+            .integrator(Integrator::SemiImplicitEuler)
+            .precision(Precision::Single)
+            .boundary(BoundaryMode::Walls)
+            .build()
+            .unwrap();
+        cpu.active_particles =
+            particle_3d::generate_particles_seeded_in_box(cpu.world_extents(), 300, cpu.id_count, 7);
+        cpu.collect_stats = true;
+
+        let data = particle_buffer_data(cpu.world_extents(), &cpu.active_particles);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &data,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        });
+        let mut physics = GpuPhysics::new(&device);
+        physics.step(&device, &queue, &buffer, &cpu, TS);
+        assert!(physics.request_readback(&device, &queue, &buffer, cpu.active_particles.len(), 0));
+        let gpu = physics.poll_readback(&device, true).unwrap();
+        cpu.update(TS).unwrap();
+
+        assert_eq!(gpu.particles.len(), cpu.active_particles.len());
+        for (gpu, cpu) in gpu.particles.iter().zip(&cpu.active_particles) {
+            assert_eq!(gpu.id, cpu.id);
+            assert!((gpu.position - cpu.position).magnitude() < 1e-4, "{:?} vs {:?}", gpu.position, cpu.position);
+            assert!((gpu.velocity - cpu.velocity).magnitude() < 1e-3, "{:?} vs {:?}", gpu.velocity, cpu.velocity);
+        }
+        assert!((gpu.energy_absorbed_by_walls - cpu.energy_absorbed_by_walls).abs() < 1e-3);
+        assert_eq!(gpu.step_stats.effective_interactions, cpu.step_stats.effective_interactions);
+        assert!((gpu.step_stats.max_force_magnitude - cpu.step_stats.max_force_magnitude).abs() < 1e-3);
+        assert_eq!(gpu.wrapped, vec![false; cpu.active_particles.len()]);
+    }
+
+    #[test]
+    fn reset_tunables_matches_the_defaults_and_counts_dropped_particles() {
+        //a loaded seven type scene with every tunable moved off its default
+        let particles: Vec<Particle> = (0..14).map(|i| particle(i % 7)).collect();
+        let mut sim = Particles::builder()
+            .world_size(20.0)
+            .id_count(7)
+            .colors(vec![cgmath::vec3(0.5, 0.5, 0.5); 7])
+            .particles(particles)
+            .build()
+            .unwrap();
+        sim.attraction_matrix = vec![0.25; 49].into();
+        sim.attraction_range = (-3.0, 3.0);
+        sim.particle_effect_radius = 3.0;
+        sim.radius_matrix = vec![2.5; 49];
+        sim.hard_radius = 0.2;
+        sim.hard_iterations = 7;
+        sim.coefficient = 0.1;
+        sim.friction_scale = vec![2.0; 7];
+        sim.interaction_force = 3.0;
+        sim.pair_force_scale = vec![0.5; 49];
+        sim.symmetric_forces = !sim.symmetric_forces;
+        sim.min_pull_ratio = 0.45;
+        sim.boundary = BoundaryMode::Open;
+        sim.restitution = 0.3;
+        sim.gravity = GravityMode::Uniform(cgmath::vec3(0.0, -9.81, 0.0));
+        sim.gravity_scale = vec![0.0; 7];
+        sim.masses = vec![2.0; 7];
+        sim.flow_field = Some(FlowField::Vortex { axis: [0.0, 1.0, 0.0], strength: 1.0 });
+        sim.lock_y = !sim.lock_y;
+        sim.dimensions = Dim::Two;
+        sim.max_speed = Some(1.5);
+        sim.cell_size = Some(4.0);
+        sim.force_curve = vec![(0.0, -1.0), (1.0, 0.0)];
+        sim.integrator = Integrator::VelocityVerlet;
+        sim.precision = Precision::Double;
+        sim.deterministic = !sim.deterministic;
+        sim.focus_point = Some(cgmath::vec3(1.0, 2.0, 3.0));
+        sim.focus_strength = 4.0;
+        sim.sim_time = 12.5;
+
+        //the particles of the two types the defaults don't have are gone
+        assert_eq!(reset_tunables(&mut sim), 4);
+        assert!(sim.active_particles.iter().all(|p| p.id < 5));
+        assert_eq!(sim.active_particles.len(), 10);
+        sim.validate().unwrap();
+
+        //every tunable is the default, the world and the particles in it are what they were
+        let reset = sim.to_scene();
+        let mut expected = default_particles().to_scene();
+        expected.world_size = 20.0;
+        expected.particles = reset.particles.clone();
+        expected.sim_time = 12.5;
+        expected.previous_accelerations = reset.previous_accelerations.clone();
+        expected.double_state = reset.double_state.clone();
+        assert_eq!(reset, expected);
+    }
+
+    #[test]
+    fn step_rate_counts_the_steps_inside_its_window() {
+        let ms = std::time::Duration::from_millis;
+        let start = std::time::Instant::now();
+        let mut counter = StepRateCounter::new(ms(1000));
+        assert_eq!(counter.rate(start), 0.0);
+        //ten steps 100 ms apart
+        for i in 0..10 {
+            counter.record(start + ms(100 * i));
+        }
+        assert_eq!(counter.rate(start + ms(900)), 10.0);
+        //half a second later only the steps from 500 ms on are in the window
+        assert_eq!(counter.rate(start + ms(1500)), 5.0);
+        assert_eq!(counter.steps.len(), 5);
+        //paused: nothing ran for longer than the window
+        assert_eq!(counter.rate(start + ms(5000)), 0.0);
+        assert!(counter.steps.is_empty());
+    }
+
+    #[test]
+    fn repaint_delay_is_one_frame_at_the_target_fps() {
+        let close = |fps: f32, seconds: f64| (repaint_delay(fps).as_secs_f64() - seconds).abs() < 1e-6;
+        assert!(close(60.0, 1.0 / 60.0));
+        assert!(close(30.0, 1.0 / 30.0));
+        assert!(close(1000.0, 0.001));
+        //below 1 fps (or a nonsense target) it still repaints once a second
+        assert!(close(0.5, 1.0));
+        assert!(close(0.0, 1.0));
+        assert!(close(-10.0, 1.0));
+    }
+
+    #[test]
+    fn step_stats_average_the_recorded_durations() {
+        let mut stats = StepStats::default();
+        assert_eq!(stats.average_ms(), 0.0);
+        for ms in [2, 4, 9] {
+            stats.record(std::time::Duration::from_millis(ms));
+        }
+        assert_eq!(stats.steps, 3);
+        assert_eq!(stats.total_time, std::time::Duration::from_millis(15));
+        assert!((stats.average_ms() - 5.0).abs() < 1e-9);
+        stats.record(std::time::Duration::from_micros(1000));
+        assert!((stats.average_ms() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn camera_flight_starts_and_ends_on_its_poses() {
+        assert_eq!((ease_in_out(0.0), ease_in_out(0.5), ease_in_out(1.0)), (0.0, 0.5, 1.0));
+        //flat at both ends and clamped outside the flight
+        assert!(ease_in_out(0.05) < 0.05 && ease_in_out(0.95) > 0.95);
+        assert_eq!((ease_in_out(-1.0), ease_in_out(2.0)), (0.0, 1.0));
+
+        let from = CameraPose { position: cgmath::vec3(0.0, 2.0, 8.0), pitch: -10.0, yaw: 350.0 };
+        let to = CameraPose { position: cgmath::vec3(4.0, -2.0, 16.0), pitch: 30.0, yaw: 10.0 };
+        assert_eq!(from.lerp(to, 0.0), from);
+        assert_eq!(from.lerp(to, 1.0), to);
+        //halfway turns the short way through 0 degrees instead of back through 180
+        let halfway = from.lerp(to, 0.5);
+        assert_eq!(halfway.position, cgmath::vec3(2.0, 0.0, 12.0));
+        assert_eq!((halfway.pitch, halfway.yaw), (10.0, 360.0));
+
+        let mut flight = CameraAnimation { from, to, elapsed: 0.0, duration: 2.0 };
+        assert_eq!(flight.pose(), from);
+        assert!(!flight.advance(1.0));
+        assert!(flight.advance(1.5));
+        assert_eq!(flight.pose(), to);
+    }
+
+    #[test]
+    fn world_to_screen_projects_known_points() {
+        //camera 5 in front of the origin with a 90 degree square view
+        let view = cgmath::Matrix4::look_at_rh(
+            cgmath::point3(0.0, 0.0, 5.0),
+            cgmath::point3(0.0, 0.0, 0.0),
+            cgmath::vec3(0.0, 1.0, 0.0),
+        );
+        let view_projection = cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.1, 100.0) * view;
+        let rect = egui::Rect::from_min_size(egui::pos2(10.0, 20.0), egui::vec2(200.0, 200.0));
+        let close = |point: cgmath::Vector3<f32>, expected: egui::Pos2| {
+            let screen = world_to_screen(view_projection, point, rect).expect("on screen");
+            assert!((screen - expected).length() < 1e-3, "{point:?} -> {screen:?}");
+        };
+        //the look at point lands in the middle, half way to the edge of view lands half way out
+        close(cgmath::vec3(0.0, 0.0, 0.0), egui::pos2(110.0, 120.0));
+        close(cgmath::vec3(2.5, 0.0, 0.0), egui::pos2(160.0, 120.0));
+        //up in the world is up on screen
+        close(cgmath::vec3(0.0, 2.5, 0.0), egui::pos2(110.0, 70.0));
+        close(cgmath::vec3(-2.5, -2.5, 0.0), egui::pos2(60.0, 170.0));
+        //behind the camera, outside the view, or past the far plane
+        assert_eq!(world_to_screen(view_projection, cgmath::vec3(0.0, 0.0, 10.0), rect), None);
+        assert_eq!(world_to_screen(view_projection, cgmath::vec3(6.0, 0.0, 0.0), rect), None);
+        assert_eq!(world_to_screen(view_projection, cgmath::vec3(0.0, 0.0, -200.0), rect), None);
+    }
+
+    #[test]
+    fn clamping_pulls_only_a_far_camera_back() {
+        let camera = |position: cgmath::Vector3<f32>, mode: CameraMode| CameraSystem {
+            position,
+            up: cgmath::vec3(0.0, 1.0, 0.0),
+            pitch: 0.0,
+            yaw: 0.0,
+            speed: 1.0,
+            level_horizon: true,
+            mode,
+        };
+        //inside the range nothing moves
+        let mut near = camera(cgmath::vec3(3.0, 4.0, 0.0), CameraMode::Fps);
+        near.clamp_position(50.0);
+        assert_eq!(near.position, cgmath::vec3(3.0, 4.0, 0.0));
+        //far outside it comes back onto the sphere along the same direction
+        let mut far = camera(cgmath::vec3(300.0, 400.0, 0.0), CameraMode::Fps);
+        far.clamp_position(50.0);
+        assert!((far.position - cgmath::vec3(30.0, 40.0, 0.0)).magnitude() < 1e-4, "{:?}", far.position);
+        //orbiting keeps its target and only shortens the distance
+        let target = cgmath::vec3(1.0, 2.0, 3.0);
+        for (distance, clamped) in [(20.0, 20.0), (80.0, 50.0)] {
+            let orbit = CameraMode::Orbit { target, distance, azimuth: 10.0, elevation: 20.0 };
+            let mut orbiting = camera(cgmath::vec3(0.0, 0.0, 0.0), orbit);
+            orbiting.clamp_position(50.0);
+            assert_eq!(orbiting.mode, CameraMode::Orbit { target, distance: clamped, azimuth: 10.0, elevation: 20.0 });
+        }
+    }
+
+    #[test]
+    fn depth_sort_draws_the_farthest_particles_first() {
+        let at = |x: f32, z: f32| Particle { position: cgmath::vec3(x, 0.0, z), ..particle(0) };
+        //looking down -z from the origin, the last particle is behind the camera
+        let particles = [at(0.0, -1.0), at(2.0, -5.0), at(-1.0, -3.0), at(0.5, 2.0)];
+        let order = depth_sorted_indices(&particles, cgmath::vec3(0.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, -1.0));
+        assert_eq!(order, vec![1, 2, 0, 3]);
+
+        //a camera turned to look down +x from x = -10, with the axes the renderer uses
+        let camera = CameraSystem {
+            position: cgmath::vec3(-10.0, 0.0, 0.0),
+            up: cgmath::vec3(0.0, 1.0, 0.0),
+            pitch: 0.0,
+            yaw: 90.0,
+            speed: 1.0,
+            level_horizon: true,
+            mode: CameraMode::Fps,
+        };
+        let (forward, _, _) = camera.calculate_axes();
+        let order = depth_sorted_indices(&particles, camera.eye(), forward);
+        assert_eq!(order, vec![1, 3, 0, 2]);
+        assert!(depth_sorted_indices(&[], camera.eye(), forward).is_empty());
+    }
+
+    #[test]
+    fn level_horizon_keeps_the_right_vector_flat() {
+        //an up vector with some roll in it, which free flying follows
+        let camera = |pitch: f32, yaw: f32, level_horizon: bool| CameraSystem {
+            position: cgmath::vec3(0.0, 0.0, 0.0),
+            up: cgmath::vec3(0.4, 1.0, 0.0).normalize(),
+            pitch,
+            yaw,
+            speed: 1.0,
+            level_horizon,
+            mode: CameraMode::Fps,
+        };
+        for pitch in [-90.0, -60.0, -15.0, 0.0, 30.0, 75.0, 90.0] {
+            for yaw in (0..12).map(|i| i as f32 * 30.0) {
+                let (forward, right, up) = camera(pitch, yaw, true).calculate_axes();
+                assert!(right.y.abs() < 1e-6, "pitch {pitch} yaw {yaw}: {right:?}");
+                assert!((right.magnitude() - 1.0).abs() < 1e-5);
+                //still a proper frame
+                assert!(forward.dot(right).abs() < 1e-5 && up.dot(right).abs() < 1e-5);
+            }
+        }
+        //free flying keeps the roll
+        let (_, right, _) = camera(0.0, 0.0, false).calculate_axes();
+        assert!(right.y.abs() > 0.1, "{right:?}");
+    }
+
+    #[test]
+    fn slab_clip_plane_keeps_the_slab_around_its_center() {
+        //the shader's test: hidden when farther than half the thickness from the plane
+        let visible = |plane: cgmath::Vector4<f32>, p: cgmath::Vector3<f32>| (plane.truncate().dot(p) + plane.w).abs() <= 0.5;
+        for axis in 0..3 {
+            let plane = slab_clip_plane(axis, 1.5);
+            let mut normal = cgmath::vec3(0.0, 0.0, 0.0);
+            normal[axis] = 1.0;
+            assert_eq!(plane, normal.extend(-1.5));
+            let along = |offset: f32| {
+                //the other axes don't matter
+                let mut p = cgmath::vec3(3.0, -7.0, 4.0);
+                p[axis] = offset;
+                p
+            };
+            for (offset, inside) in [(1.5, true), (1.2, true), (1.99, true), (2.1, false), (0.9, false), (-1.5, false)] {
+                assert_eq!(visible(plane, along(offset)), inside, "axis {axis} at {offset}");
+            }
+        }
+    }
+
+    #[test]
+    fn billboard_polygons_wrap_the_disc_tightly() {
+        let area = |corners: usize| {
+            let vertices = billboard_polygon(corners);
+            let (a, b) = (vertices[1], vertices[2]);
+            //the fan is corners copies of the triangle between the center and two corners
+            corners as f32 * 0.5 * (a[0] * b[1] - a[1] * b[0]).abs()
+        };
+        for corners in [4, 6, 8] {
+            let vertices = billboard_polygon(corners);
+            assert_eq!(vertices.len(), corners + 1);
+            assert_eq!(vertices[0], [0.0, 0.0, 0.0]);
+            for (i, corner) in vertices[1..].iter().enumerate() {
+                let next = vertices[1 + (i + 1) % corners];
+                //flat, and every edge just touches the disc of radius 0.5
+                assert_eq!((corner[2], next[2]), (0.0, 0.0));
+                let midpoint = [(corner[0] + next[0]) * 0.5, (corner[1] + next[1]) * 0.5];
+                assert!((midpoint[0].hypot(midpoint[1]) - 0.5).abs() < 1e-5, "{corners}: {midpoint:?}");
+            }
+            let indices = billboard_polygon_indices(corners);
+            assert_eq!(indices.len(), 3 * corners);
+            assert!(indices.chunks(3).all(|triangle| triangle[0] == 0 && triangle.iter().all(|&i| (i as usize) <= corners)));
+        }
+        //more corners cover less outside the disc (a square around it covers 1)
+        assert!((area(4) - 1.0).abs() < 1e-5);
+        assert!(area(8) < area(6) && area(6) < area(4));
+        assert!(area(8) > std::f32::consts::PI * 0.25);
+    }
+
+    #[test]
+    fn storage_buffer_capacity_fits_the_first_upload() {
+        //header plus one element is the minimum, each further element adds its size
+        assert_eq!(storage_buffer_capacity(48, 32, 0), 48);
+        assert_eq!(storage_buffer_capacity(48, 32, 1), 48);
+        assert_eq!(storage_buffer_capacity(48, 32, 100), 48 + 32 * 99);
+        //rounded up to whole 4 byte words
+        assert_eq!(storage_buffer_capacity(10, 3, 3), 16);
+
+        //the particle buffer presized for n particles holds exactly what uploading n writes
+        let min_size = <GpuParticles as ShaderType>::METADATA.min_size().get() as usize;
+        let element_size = <Particle as ShaderSize>::SHADER_SIZE.get() as usize;
+        for count in [1, 2, 1000] {
+            let data = particle_buffer_data(cgmath::vec3(10.0, 10.0, 10.0), &vec![particle(0); count]);
+            assert_eq!(storage_buffer_capacity(min_size, element_size, count), data.len(), "{count}");
+        }
+    }
+
+    #[test]
+    fn type_styles_pack_in_particle_id_order() {
+        let colors = [cgmath::vec3(1.0, 0.0, 0.0), cgmath::vec3(0.0, 1.0, 0.0), cgmath::vec3(0.0, 0.0, 1.0)];
+        let configs = [
+            TypeRenderConfig::default(),
+            TypeRenderConfig { glyph: Glyph::Tetrahedron, size: 0.3, alpha: 0.5, ..TypeRenderConfig::default() },
+        ];
+        //the type without a config gets the defaults
+        let styles = pack_type_styles(&colors, &configs);
+        let packed: Vec<_> = styles.iter().map(|s| (s.color, s.size, s.alpha, s.billboard)).collect();
+        assert_eq!(
+            packed,
+            vec![(colors[0], 0.1, 1.0, 1), (colors[1], 0.3, 0.5, 0), (colors[2], 0.1, 1.0, 1)]
+        );
+
+        //`colors.colors[particle.id]` in the shader: a 16 byte header, then one 32 byte style per
+        //type (vec3 color, size, alpha, billboard, padding)
+        let mut buffer = StorageBuffer::new(vec![]);
+        buffer.write(&GpuColors { alpha: 0.75, length: ArrayLength, particles: &styles }).unwrap();
+        let bytes = buffer.into_inner();
+        let word = |offset: usize| <[u8; 4]>::try_from(&bytes[offset..offset + 4]).unwrap();
+        let float = |offset: usize| f32::from_le_bytes(word(offset));
+        assert_eq!((float(0), u32::from_le_bytes(word(4))), (0.75, 3));
+        assert_eq!(bytes.len(), 16 + 3 * 32);
+        for (id, style) in styles.iter().enumerate() {
+            let base = 16 + 32 * id;
+            let color = cgmath::vec3(float(base), float(base + 4), float(base + 8));
+            assert_eq!((color, float(base + 12), float(base + 16)), (style.color, style.size, style.alpha));
+            assert_eq!(u32::from_le_bytes(word(base + 20)), style.billboard);
+        }
+    }
+
+    #[test]
+    fn fixed_composition_generates_exactly_the_requested_counts() {
+        let extents = cgmath::vec3(10.0, 4.0, 6.0);
+        let counts = [3, 0, 250, 17];
+        let particles = generate_particles_with_composition(extents, &counts);
+        assert_eq!(particles.len(), 270);
+        for (id, &count) in counts.iter().enumerate() {
+            assert_eq!(particles.iter().filter(|p| p.id == id as u32).count(), count, "type {id}");
+        }
+        //at random places inside the box
+        let half = extents * 0.5;
+        assert!(particles.iter().all(|p| p.position.x.abs() <= half.x && p.position.y.abs() <= half.y && p.position.z.abs() <= half.z));
+        assert!(particles.windows(2).any(|pair| pair[0].position != pair[1].position));
+        assert!(generate_particles_with_composition(extents, &[0, 0]).is_empty());
+    }
+
+    #[test]
+    fn glyph_meshes_size_their_buffers_and_draws() {
+        for (glyph, vertex_count, index_count) in [
+            //the quad keeps the old two triangle draw
+            (Glyph::Quad, 4, 6),
+            (Glyph::Hexagon, 7, 18),
+            (Glyph::Octagon, 9, 24),
+            (Glyph::Tetrahedron, 4, 12),
+            (Glyph::Cube, 8, 36),
+        ] {
+            let (vertices, indices, draw_count) = glyph_mesh_data(glyph);
+            assert_eq!(vertices.len(), vertex_count * GLYPH_VERTEX_SIZE, "{}", glyph.name());
+            assert_eq!(indices.len(), index_count * std::mem::size_of::<u16>(), "{}", glyph.name());
+            //the draw covers every index, in whole triangles of vertices that exist
+            assert_eq!(draw_count as usize, index_count, "{}", glyph.name());
+            assert_eq!(draw_count % 3, 0);
+            assert!(glyph.indices().iter().all(|&i| (i as usize) < vertex_count), "{}", glyph.name());
+        }
+        //the first vertex as the vertex buffer layout reads it
+        let (vertices, _, _) = glyph_mesh_data(Glyph::Quad);
+        let x = f32::from_ne_bytes(vertices[0..4].try_into().unwrap());
+        let y = f32::from_ne_bytes(vertices[4..8].try_into().unwrap());
+        assert_eq!((x, y), (-0.5, -0.5));
+    }
+
+    #[test]
+    fn border_drawing_and_boundary_physics_are_independent() {
+        //the border draws the shader's 24 edge endpoints exactly when it's switched on
+        assert_eq!(border_vertices(true), 0..24);
+        assert!(border_vertices(false).is_empty());
+        //and a particle crossing the +x face is handled by the boundary mode alone: bounced back
+        //inside between walls, carried to the far side when wrapping
+        for (boundary, lands_on_far_side) in [(BoundaryMode::Walls, false), (BoundaryMode::Wrap, true)] {
+            let crossing = Particle { position: cgmath::vec3(4.95, 0.0, 0.0), velocity: cgmath::vec3(1.0, 0.0, 0.0), id: 0 };
+            let mut sim = Particles::builder()
+                .world_size(10.0)
+                .boundary(boundary)
+                .particles(vec![crossing])
+                .build()
+                .unwrap();
+            sim.update(0.1).unwrap();
+            let x = sim.active_particles[0].position.x;
+            assert_eq!(x < 0.0, lands_on_far_side, "{boundary:?}: {x}");
+            assert!(sim.contains(sim.active_particles[0].position));
+        }
+    }
+
+    #[test]
+    fn frozen_view_skips_the_paint_work() {
+        let painted = std::cell::Cell::new(0);
+        let paint = || painted.set(painted.get() + 1);
+        assert!(!paint_unless_frozen(true, paint));
+        assert_eq!(painted.get(), 0);
+        assert!(paint_unless_frozen(false, paint));
+        assert_eq!(painted.get(), 1);
+    }
+
+    #[test]
+    fn controls_placement_toggles_and_docks_when_its_window_closes() {
+        let docked = ControlsPlacement::Docked;
+        assert_eq!(docked.toggle_label(), "Detach Controls");
+        let detached = docked.toggled();
+        assert_eq!(detached, ControlsPlacement::Detached);
+        assert_eq!(detached.toggle_label(), "Dock Controls");
+        assert_eq!(detached.toggled(), docked);
+        //frames with the window open keep it detached, closing the window docks the controls
+        assert_eq!(detached.after_window(true), detached);
+        assert_eq!(detached.after_window(false), docked);
+        assert_eq!(docked.after_window(false), docked);
+    }
+}
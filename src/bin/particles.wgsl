@@ -6,27 +6,56 @@ struct Particle {
 
 //holds all particle data
 struct Particles {
-    world_size: f32,
+    world_extents: vec3<f32>, //edge lengths of the world box
     length: u32,
     particles: array<Particle>,
 };
 
+//color and render config of one particle type
+struct TypeStyle {
+    color: vec3<f32>,
+    size: f32, //glyph size in world units
+    alpha: f32, //opacity multiplier for this type
+    billboard: u32, //1 = camera facing disc glyph, 0 = solid mesh
+};
+
 //stores color info
 struct Colors {
+    alpha: f32, //opacity for every particle
     length: u32,
-    colors: array<vec3<f32>>,
+    colors: array<TypeStyle>,
+};
+
+//how particles are colored
+struct ColorMode {
+    mode: u32, //0 = by type, 1 = by speed, 2 = by id
+    min_speed: f32, //speed drawn with slow_color
+    max_speed: f32, //speed drawn with fast_color
+    slow_color: vec3<f32>,
+    fast_color: vec3<f32>,
+};
+
+//order particles are drawn in
+struct DrawOrder {
+    length: u32, //0 draws particles in their own order
+    indices: array<u32>, //particle index drawn by each instance
 };
 
 //camera matrices
 struct Camera {
     view_matrix: mat4x4<f32>,
     projection_matrix: mat4x4<f32>,
+    frustum_planes: array<vec4<f32>, 6>, //inward facing planes for culling
+    cull_enabled: u32, //1 when off-screen particles should be skipped
+    slab_plane: vec4<f32>, //center plane of the visible cross-section
+    slab_half_thickness: f32, //half width of the visible cross-section
+    slab_enabled: u32, //1 when only the cross-section is drawn
 };
 
 //shader inputs
 struct VertexIn {
-    @builtin(vertex_index) vertex_index: u32,
-    @builtin(instance_index) particle_index: u32, // parallel processing
+    @location(0) local_position: vec3<f32>, //glyph mesh vertex
+    @builtin(instance_index) instance: u32, //position in the draw order
 };
 
 //data passed to fragment shader
@@ -35,11 +64,22 @@ struct VertexOut {
     @location(0) world_position: vec3<f32>,
     @location(1) uv: vec2<f32>,
     @location(2) particle_index: u32,
+    @location(3) shade: f32, //fake lighting so solid glyphs show their faces
 };
 
 @group(0) @binding(0) var<uniform> camera: Camera;
 @group(1) @binding(0) var<storage, read> particles: Particles;
 @group(1) @binding(1) var<storage, read> colors: Colors;
+@group(1) @binding(2) var<uniform> color_mode: ColorMode;
+@group(1) @binding(3) var<storage, read> draw_order: DrawOrder;
+
+//cheap integer hash so neighboring indices get unrelated colors
+fn hash_color(index: u32) -> vec3<f32> {
+    var h = index * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    return vec3(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+}
 
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
@@ -48,35 +88,79 @@ fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
         discard; //fast because skips pixel calculation
     }
     
-    let particle_id = particles.particles[in.particle_index].id;
-    let color = colors.colors[particle_id];
+    //hiding particles outside the cross-section slab
+    let particle = particles.particles[in.particle_index];
+    if camera.slab_enabled == 1u
+        && abs(dot(camera.slab_plane.xyz, particle.position) + camera.slab_plane.w) > camera.slab_half_thickness {
+        discard;
+    }
+    
+    let style = colors.colors[particle.id];
     
-    return vec4(color, 1.0);
+    var color = style.color;
+    if color_mode.mode == 1u {
+        let range = max(color_mode.max_speed - color_mode.min_speed, 1e-6);
+        let t = clamp((length(particle.velocity) - color_mode.min_speed) / range, 0.0, 1.0);
+        color = mix(color_mode.slow_color, color_mode.fast_color, t);
+    } else if color_mode.mode == 2u {
+        color = hash_color(in.particle_index);
+    }
+    
+    return vec4(color * in.shade, colors.alpha * style.alpha);
 }
 
 @vertex
 fn vs_main(in: VertexIn) -> VertexOut {
-    let vertex_index = in.vertex_index;
-    let particle_index = in.particle_index; //concurrent processing here
-    
-        //fast bit operations for uv calculation
-    let u = f32((vertex_index >> 0u) & 1u);
-    let v = f32((vertex_index >> 1u) & 1u);
-    let uv = vec2(u, v);
+    //particle index in simulation order, so by id colors don't change with sorting
+    var particle_index = in.instance;
+    if draw_order.length > 0u {
+        particle_index = draw_order.indices[in.instance];
+    }
+    let particle = particles.particles[particle_index];
+    let style = colors.colors[particle.id];
+    let is_billboard = style.billboard == 1u;
     
-    let particle_pos = particles.particles[particle_index].position;
-    let view_space_pos = camera.view_matrix * vec4(particle_pos, 1.0);
+    //billboards are cut into discs in the fragment shader, solid glyphs keep every pixel
+    var uv = vec2(0.5, 0.5);
+    if is_billboard {
+        uv = in.local_position.xy + 0.5;
+    }
     
+    let particle_pos = particle.position;
+
+    var out: VertexOut;
+    out.particle_index = particle_index;
+    out.uv = uv;
+    out.shade = 1.0;
+    if !is_billboard {
+        out.shade = 0.75 + 0.25 * normalize(in.local_position).y;
+    }
+
+    //skipping particles fully outside the frustum by collapsing the quad outside clip space
+    if camera.cull_enabled == 1u {
+        for (var i = 0u; i < 6u; i++) {
+            let plane = camera.frustum_planes[i];
+            if dot(plane.xyz, particle_pos) + plane.w < -style.size {
+                out.position = vec4(2.0, 2.0, 2.0, 1.0);
+                out.world_position = out.position.xyz;
+                return out;
+            }
+        }
+    }
+
+    var final_view_pos: vec4<f32>;
+    if is_billboard {
         //quick billboard calculation without full matrix math
-    let offset = vec4((uv - 0.5) * 0.1, 0.0, 0.0);
-    let final_view_pos = view_space_pos + offset;
+        let view_space_pos = camera.view_matrix * vec4(particle_pos, 1.0);
+        final_view_pos = view_space_pos + vec4(in.local_position.xy * style.size, 0.0, 0.0);
+    } else {
+        //solid glyphs sit in world space so they can be seen from every side
+        final_view_pos = camera.view_matrix * vec4(particle_pos + in.local_position * style.size, 1.0);
+    }
     
     let clip_pos = camera.projection_matrix * final_view_pos;
     let world_pos = clip_pos.xyz / clip_pos.w;
     
-    var out: VertexOut;
-    out.particle_index = particle_index;
-    out.uv = uv;
     out.position = clip_pos;
     out.world_position = world_pos;
     
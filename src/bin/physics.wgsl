@@ -0,0 +1,232 @@
+struct Particle {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+    id: u32,
+};
+
+//simulation settings for one step
+struct Params {
+    world_extents: vec3<f32>, //edge lengths of the world box
+    count: u32, //no. of particles
+    gravity: vec3<f32>, //uniform acceleration or point gravity center
+    id_count: u32, //no. of particle types
+    ts: f32, //step length in seconds
+    interaction_force: f32,
+    effect_radius: f32,
+    min_pull_ratio: f32,
+    friction: f32, //decay rate per second
+    restitution: f32,
+    max_speed: f32, //negative = no speed limit
+    rest_speed: f32, //speed below which particles stop
+    boundary: u32, //0 = wrap, 1 = walls, 2 = open
+    lock_y: u32, //1 = no vertical motion
+    flat: u32, //1 = 2D on the z = 0 plane
+    gravity_mode: u32, //0 = none, 1 = uniform, 2 = towards a point
+    gravity_strength: f32, //point gravity strength
+    gravity_softening: f32, //distance below which point gravity stops growing
+};
+
+//how one type reacts to another
+struct Pair {
+    attraction: f32,
+    radius: f32, //cutoff distance
+    force_scale: f32,
+};
+
+//per type multipliers
+struct Kind {
+    mass: f32,
+    gravity_scale: f32,
+    friction_scale: f32,
+};
+
+//the particle buffer the renderer draws from
+struct Particles {
+    world_extents: vec3<f32>,
+    length: u32,
+    particles: array<Particle>,
+};
+
+//what happened to one particle at the world boundary
+struct StepEvent {
+    wrapped: u32, //1 once the particle wrapped in any step since the last readback
+    absorbed: f32, //kinetic energy the walls took from it in the last step
+};
+
+//pair work of the last step, summed over all particles
+struct StepStats {
+    pair_checks: atomic<u32>,
+    effective_interactions: atomic<u32>,
+    max_force_bits: atomic<u32>, //f32 bits, non-negative floats order the same as their bits
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var<storage, read> pairs: array<Pair>; //laid out like the attraction matrix
+
+@group(0) @binding(2)
+var<storage, read> kinds: array<Kind>;
+
+@group(0) @binding(3)
+var<storage, read> input: Particles; //state before the step
+
+@group(0) @binding(4)
+var<storage, read_write> output: array<Particle>; //state after the step, copied over the input
+
+@group(0) @binding(5)
+var<storage, read_write> events: array<StepEvent>;
+
+@group(0) @binding(6)
+var<storage, read_write> stats: StepStats;
+
+//outside the world box, where open worlds drop particles
+fn outside(position: vec3<f32>) -> bool {
+    return any(abs(position) > params.world_extents * 0.5);
+}
+
+//same built-in force shape as the cpu solver, `distance` in world units
+fn calculate_force(distance: f32, attraction: f32) -> f32 {
+    if distance < params.min_pull_ratio {
+        //very close particles repel each other
+        return distance / params.min_pull_ratio - 1.0;
+    } else if params.min_pull_ratio < distance && distance < 1.0 {
+        //medium distance particles attract or repel based on the attraction matrix
+        return attraction * (1.0 - abs(2.0 * distance - 1.0 - params.min_pull_ratio) / (1.0 - params.min_pull_ratio));
+    }
+    //far particles don't affect each other
+    return 0.0;
+}
+
+//same gravity as the cpu solver (`GravityMode::acceleration`)
+fn gravity(position: vec3<f32>) -> vec3<f32> {
+    if params.gravity_mode == 1u {
+        return params.gravity;
+    } else if params.gravity_mode == 2u {
+        let offset = params.gravity - position;
+        return offset * (params.gravity_strength / (dot(offset, offset) + params.gravity_softening * params.gravity_softening));
+    }
+    return vec3<f32>(0.0);
+}
+
+@compute @workgroup_size(64)
+fn step(@builtin(global_invocation_id) invocation: vec3<u32>) {
+    let index = invocation.x;
+    if index >= params.count {
+        return;
+    }
+    let particle = input.particles[index];
+
+    //particles that left an open world wait for the cpu to remove them on the next readback
+    if params.boundary == 2u && outside(particle.position) {
+        output[index] = particle;
+        events[index].absorbed = 0.0;
+        return;
+    }
+
+    //summing forces from every other particle (brute force, no spatial hash)
+    var total_force = vec3<f32>(0.0);
+    var pair_checks = 0u;
+    var effective_interactions = 0u;
+    var max_force = 0.0;
+    for (var other_index = 0u; other_index < params.count; other_index += 1u) {
+        let other = input.particles[other_index];
+        if params.boundary == 2u && outside(other.position) {
+            continue;
+        }
+        var relative_position = other.position - particle.position;
+        //shortest way around when the world wraps (minimum image)
+        if params.boundary == 0u {
+            relative_position -= params.world_extents * round(relative_position / params.world_extents);
+        }
+        pair_checks += 1u;
+        //types outside id_count have no pair entry and don't interact
+        if particle.id >= params.id_count || other.id >= params.id_count {
+            continue;
+        }
+        let pair = pairs[particle.id * params.id_count + other.id];
+        let sqr_distance = dot(relative_position, relative_position);
+        if sqr_distance > 0.0 && sqr_distance < pair.radius * pair.radius {
+            let distance = sqrt(sqr_distance);
+            let f = calculate_force(distance, pair.attraction) * pair.force_scale;
+            effective_interactions += 1u;
+            max_force = max(max_force, abs(f));
+            total_force += relative_position * (f / distance);
+        }
+    }
+    total_force *= params.interaction_force * params.effect_radius;
+    atomicAdd(&stats.pair_checks, pair_checks);
+    atomicAdd(&stats.effective_interactions, effective_interactions);
+    atomicMax(&stats.max_force_bits, bitcast<u32>(max_force));
+
+    //semi-implicit euler like the cpu default
+    var kind = Kind(1.0, 1.0, 1.0);
+    if particle.id < params.id_count {
+        kind = kinds[particle.id];
+    }
+    var acceleration = total_force / kind.mass + gravity(particle.position) * kind.gravity_scale;
+    if params.lock_y == 1u {
+        acceleration.y = 0.0;
+    }
+    if params.flat == 1u {
+        acceleration.z = 0.0;
+    }
+    var velocity = particle.velocity + acceleration * params.ts;
+
+    //decaying exponentially with friction, stopping particles that are all but at rest
+    velocity *= exp(-params.friction * kind.friction_scale * params.ts);
+    if dot(velocity, velocity) < params.rest_speed * params.rest_speed {
+        velocity = vec3<f32>(0.0);
+    }
+    if params.lock_y == 1u {
+        velocity.y = 0.0;
+    }
+    if params.flat == 1u {
+        velocity.z = 0.0;
+    }
+    if params.max_speed >= 0.0 {
+        let speed = length(velocity);
+        if speed > params.max_speed {
+            velocity *= params.max_speed / speed;
+        }
+    }
+
+    var position = particle.position + velocity * params.ts;
+    if params.flat == 1u {
+        position.z = 0.0;
+    }
+
+    //wrapping or bouncing at the world box, open worlds leave particles outside
+    let half_extents = params.world_extents * 0.5;
+    let restitution = clamp(params.restitution, 0.0, 1.0);
+    let was_outside = outside(position);
+    var absorbed = 0.0;
+    for (var axis = 0; axis < 3; axis += 1) {
+        if position[axis] > half_extents[axis] {
+            if params.boundary == 0u {
+                position[axis] -= params.world_extents[axis];
+            } else if params.boundary == 1u {
+                position[axis] = half_extents[axis];
+                let into_wall = max(velocity[axis], 0.0);
+                velocity[axis] -= into_wall * (1.0 + restitution);
+                absorbed += 0.5 * into_wall * into_wall * (1.0 - restitution * restitution);
+            }
+        } else if position[axis] < -half_extents[axis] {
+            if params.boundary == 0u {
+                position[axis] += params.world_extents[axis];
+            } else if params.boundary == 1u {
+                position[axis] = -half_extents[axis];
+                let into_wall = min(velocity[axis], 0.0);
+                velocity[axis] -= into_wall * (1.0 + restitution);
+                absorbed += 0.5 * into_wall * into_wall * (1.0 - restitution * restitution);
+            }
+        }
+    }
+
+    output[index] = Particle(position, velocity, particle.id);
+    if params.boundary == 0u && was_outside {
+        events[index].wrapped = 1u;
+    }
+    events[index].absorbed = absorbed;
+}
@@ -0,0 +1,36 @@
+//camera matrices
+struct Camera {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+    frustum_planes: array<vec4<f32>, 6>, //inward facing planes for culling
+    cull_enabled: u32, //1 when off-screen particles should be skipped
+    slab_plane: vec4<f32>, //center plane of the visible cross-section
+    slab_half_thickness: f32, //half width of the visible cross-section
+    slab_enabled: u32, //1 when only the cross-section is drawn
+};
+
+//trail segment end coming from the cpu recorded particle history
+struct VertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>, //type color, alpha fades with age
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return in.color;
+}
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+    out.position = camera.projection_matrix * camera.view_matrix * vec4(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
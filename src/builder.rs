@@ -0,0 +1,283 @@
+use crate::{AttractionMatrix, BoundaryMode, Dim, FlowField, GravityMode, HashCache, Integrator, Particle, Particles, Precision, SimError};
+
+//number of particle types in the default setup
+const DEFAULT_TYPES: usize = 5;
+
+//chainable setup for `Particles` so callers only name what they change, every other field
+//starts at the same defaults the viewer uses
+pub struct ParticlesBuilder {
+    particles: Particles,
+}
+
+impl Default for ParticlesBuilder {
+    fn default() -> Self {
+        Self {
+            particles: Particles {
+                world_size: 10.0, //size of the simulation space
+                world_aspect: cgmath::vec3(1.0, 1.0, 1.0),//a cube
+                id_count: DEFAULT_TYPES as u32,//no. of different particle types
+                colors: vec![//colors for different particle types
+                    cgmath::vec3(1.0, 0.0, 0.0), // red
+                    cgmath::vec3(0.0, 1.0, 0.0), // green
+                    cgmath::vec3(0.0, 0.0, 1.0), // blue
+                    cgmath::vec3(1.0, 1.0, 0.0), // yellow
+                    cgmath::vec3(1.0, 0.0, 1.0), // magenta
+                ],
+                attraction_matrix: vec![//how different particles attract/repel each other
+                    0.5, 1.0, -0.5, 0.0, -1.0,//positive values = attraction, negative = repulsion
+                    1.0, 1.0, 1.0, 0.0, -1.0,
+                    0.0, 0.0, 0.5, 1.5, -1.0,
+                    0.0, 0.0, 0.0, 0.0, -1.0,
+                    1.0, 1.0, 1.0, 1.0, 0.5,
+                ]
+                .into(),
+                attraction_range: (-1.0, 1.0),//default unit strength limits for the matrix editor
+                particle_effect_radius: 2.0,//how far particles can affect each other
+                radius_matrix: vec![],//same radius for every pair
+                hard_radius: 0.0,//soft repulsion only
+                hard_iterations: 2,
+                coefficient: 0.97,//friction decay rate per second (0.0 = no friction)
+                friction_scale: vec![1.0; DEFAULT_TYPES],//every type feels the same friction
+                interaction_force: 1.0,//strength of particle interactions
+                pair_force_scale: vec![1.0; DEFAULT_TYPES * DEFAULT_TYPES],//every pair at full strength
+                symmetric_forces: false,//each type reacts to the other as the matrix says
+                min_pull_ratio: 0.3, //when to push instead of pull
+                active_particles: vec![],//no particles until some are added
+                past_particles: vec![],//storage for previous frames
+                boundary: BoundaryMode::Wrap,//whether particles wrap, bounce off walls or leave
+                restitution: 0.0,//walls stop particles dead
+                gravity: GravityMode::None,//no gravity
+                gravity_scale: vec![1.0; DEFAULT_TYPES],//every type feels full gravity
+                masses: vec![1.0; DEFAULT_TYPES],//every type equally heavy
+                flow_field: None,//no swirl
+                max_speed: None,//no speed limit
+                lock_y: false,//full 3d motion
+                dimensions: Dim::Three,//a volume rather than a flat field
+                focus_point: None,//no focus pull
+                focus_strength: 1.0,//pull towards the focus point once there is one
+                deterministic: false,//faster parallel force sums by default
+                precision: Precision::Single,//f32 solver, enough for interactive use
+                integrator: Integrator::SemiImplicitEuler,//the original solver
+                previous_accelerations: vec![],
+                double_state: vec![],//no f64 state until a double precision step
+                on_step: None,//no per step hook
+                post_step: None,//no custom step logic
+                force_curve: vec![],//built-in force shape
+                incremental_hash: true,//skip rehashing particles that stayed in their cell
+                cell_size: None,//cells as big as the effect radius
+                hash_cache: HashCache::default(),
+                sim_time: 0.0,//nothing simulated yet
+                collect_stats: false,//no counting overhead unless asked for
+                step_stats: Default::default(),
+                energy_absorbed_by_walls: 0.0,
+                wrapped: vec![],//no step taken yet
+            },
+        }
+    }
+}
+
+impl Particles {
+    //starting point for building a simulation, see `ParticlesBuilder`
+    pub fn builder() -> ParticlesBuilder {
+        ParticlesBuilder::default()
+    }
+}
+
+impl ParticlesBuilder {
+    //a cube world with edges of `world_size`
+    pub fn world_size(mut self, world_size: f32) -> Self {
+        self.particles.world_size = world_size;
+        self.particles.world_aspect = cgmath::vec3(1.0, 1.0, 1.0);
+        self
+    }
+
+    //a box world with the given edge lengths, world_size becomes the longest edge
+    pub fn world_extents(mut self, extents: cgmath::Vector3<f32>) -> Self {
+        let world_size = extents.x.max(extents.y).max(extents.z);
+        self.particles.world_size = world_size;
+        self.particles.world_aspect = extents / world_size;
+        self
+    }
+
+    //changing the type count resets the per type multipliers (friction, pair force, gravity,
+    //mass) to 1.0, the colors and attraction matrix have to be set to match
+    pub fn id_count(mut self, id_count: u32) -> Self {
+        let types = id_count as usize;
+        self.particles.id_count = id_count;
+        self.particles.friction_scale = vec![1.0; types];
+        self.particles.pair_force_scale = vec![1.0; types * types];
+        self.particles.gravity_scale = vec![1.0; types];
+        self.particles.masses = vec![1.0; types];
+        self.particles.attraction_matrix.resize(types);
+        self
+    }
+
+    pub fn colors(mut self, colors: Vec<cgmath::Vector3<f32>>) -> Self {
+        self.particles.colors = colors;
+        self
+    }
+
+    //row major values (or a ready matrix), `get(a, b)` is how type a reacts to type b
+    pub fn attraction_matrix(mut self, attraction_matrix: impl Into<AttractionMatrix>) -> Self {
+        self.particles.attraction_matrix = attraction_matrix.into();
+        self
+    }
+
+    pub fn attraction_range(mut self, min: f32, max: f32) -> Self {
+        self.particles.attraction_range = (min, max);
+        self
+    }
+
+    pub fn effect_radius(mut self, radius: f32) -> Self {
+        self.particles.particle_effect_radius = radius;
+        self
+    }
+
+    //per pair cutoff distances laid out like the attraction matrix
+    pub fn radius_matrix(mut self, radius_matrix: Vec<f32>) -> Self {
+        self.particles.radius_matrix = radius_matrix;
+        self
+    }
+
+    pub fn hard_radius(mut self, radius: f32, iterations: usize) -> Self {
+        self.particles.hard_radius = radius;
+        self.particles.hard_iterations = iterations;
+        self
+    }
+
+    pub fn friction(mut self, coefficient: f32) -> Self {
+        self.particles.coefficient = coefficient;
+        self
+    }
+
+    pub fn interaction_force(mut self, interaction_force: f32) -> Self {
+        self.particles.interaction_force = interaction_force;
+        self
+    }
+
+    pub fn min_pull_ratio(mut self, min_pull_ratio: f32) -> Self {
+        self.particles.min_pull_ratio = min_pull_ratio;
+        self
+    }
+
+    pub fn boundary(mut self, boundary: BoundaryMode) -> Self {
+        self.particles.boundary = boundary;
+        self
+    }
+
+    pub fn gravity(mut self, gravity: GravityMode) -> Self {
+        self.particles.gravity = gravity;
+        self
+    }
+
+    //uniform gravity, shorthand for `gravity(GravityMode::Uniform(acceleration))`
+    pub fn acceleration(self, acceleration: cgmath::Vector3<f32>) -> Self {
+        self.gravity(GravityMode::Uniform(acceleration))
+    }
+
+    pub fn flow_field(mut self, flow_field: Option<FlowField>) -> Self {
+        self.particles.flow_field = flow_field;
+        self
+    }
+
+    pub fn max_speed(mut self, max_speed: Option<f32>) -> Self {
+        self.particles.max_speed = max_speed;
+        self
+    }
+
+    pub fn lock_y(mut self, lock_y: bool) -> Self {
+        self.particles.lock_y = lock_y;
+        self
+    }
+
+    pub fn dimensions(mut self, dimensions: Dim) -> Self {
+        self.particles.dimensions = dimensions;
+        self
+    }
+
+    pub fn restitution(mut self, restitution: f32) -> Self {
+        self.particles.restitution = restitution;
+        self
+    }
+
+    pub fn symmetric_forces(mut self, symmetric_forces: bool) -> Self {
+        self.particles.symmetric_forces = symmetric_forces;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.particles.deterministic = deterministic;
+        self
+    }
+
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.particles.precision = precision;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: Integrator) -> Self {
+        self.particles.integrator = integrator;
+        self
+    }
+
+    //spatial hash cell edge, None sizes cells to the effect radius
+    pub fn cell_size(mut self, cell_size: Option<f32>) -> Self {
+        self.particles.cell_size = cell_size;
+        self
+    }
+
+    pub fn force_curve(mut self, force_curve: Vec<(f32, f32)>) -> Self {
+        self.particles.force_curve = force_curve;
+        self
+    }
+
+    pub fn particles(mut self, particles: Vec<Particle>) -> Self {
+        self.particles.active_particles = particles;
+        self
+    }
+
+    //checking everything fits together (table sizes, particle types, world size) before handing
+    //out the simulation
+    pub fn build(self) -> Result<Particles, SimError> {
+        self.particles.validate()?;
+        Ok(self.particles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_the_viewer_setup_and_consistent() {
+        let sim = Particles::builder().build().unwrap();
+        assert_eq!((sim.world_size, sim.world_extents()), (10.0, cgmath::vec3(10.0, 10.0, 10.0)));
+        assert_eq!(sim.id_count as usize, DEFAULT_TYPES);
+        assert_eq!((sim.particle_effect_radius, sim.coefficient, sim.min_pull_ratio), (2.0, 0.97, 0.3));
+        assert_eq!((sim.boundary, sim.gravity, sim.dimensions), (BoundaryMode::Wrap, GravityMode::None, Dim::Three));
+        assert_eq!((sim.focus_point, sim.focus_strength), (None, 1.0));
+        assert_eq!((sim.integrator, sim.precision), (Integrator::SemiImplicitEuler, Precision::Single));
+        assert!(sim.active_particles.is_empty() && sim.past_particles.is_empty());
+        //every per type table has a value for every type
+        assert_eq!(sim.colors.len(), DEFAULT_TYPES);
+        assert_eq!(sim.attraction_matrix.id_count(), DEFAULT_TYPES);
+        assert_eq!(sim.pair_force_scale, vec![1.0; DEFAULT_TYPES * DEFAULT_TYPES]);
+        for table in [&sim.friction_scale, &sim.gravity_scale, &sim.masses] {
+            assert_eq!(table, &vec![1.0; DEFAULT_TYPES]);
+        }
+        //and nothing is lost saving and loading them
+        let scene = sim.to_scene();
+        assert_eq!(Particles::from_scene(&scene).unwrap().to_scene(), scene);
+    }
+
+    #[test]
+    fn changing_the_type_count_keeps_the_defaults_consistent() {
+        let colors = vec![cgmath::vec3(1.0, 1.0, 1.0); 3];
+        let sim = Particles::builder().id_count(3).colors(colors).build().unwrap();
+        assert_eq!(sim.attraction_matrix.len(), 9);
+        //the pairs both sizes have keep the default matrix
+        assert_eq!(sim.attraction_matrix.get(0, 2), -0.5);
+        assert_eq!(sim.pair_force_scale.len(), 9);
+        assert_eq!(sim.masses, vec![1.0; 3]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering::Relaxed};
+
+use cgmath::prelude::*;
+use rayon::prelude::*;
+
+use crate::{axis_neighbor_cells, BoundaryMode, Dim, Particles};
+
+//summary of one simulation step, handed to the `on_step` hook
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimDiagnostics {
+    pub kinetic_energy: f32,//sum of 0.5 * m * v^2 over all particles
+    pub wall_collisions: usize,//particles that bounced off a wall this step
+    pub wraps: usize,//particles that wrapped to the other side this step
+    pub escaped: usize,//particles that left an open box this step and were removed
+    pub energy_absorbed_by_walls: f32,//kinetic energy lost to wall bounces this step (unit mass)
+    pub cluster_count: usize,//groups of touching occupied grid cells
+}
+
+//how much pair work the last step did, filled in by `update` while `collect_stats` is on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StepStats {
+    pub pair_checks: usize,//candidate pairs whose distance was tested
+    pub effective_interactions: usize,//pairs that were inside their radius and exerted a force
+    pub max_force_magnitude: f32,//strongest single pair force (before interaction_force scaling)
+}
+
+//thread safe counters behind `StepStats`, shared by the parallel force loop
+#[derive(Default)]
+pub(crate) struct StepCounters {
+    pair_checks: AtomicUsize,
+    effective_interactions: AtomicUsize,
+    max_force_bits: AtomicU32,//f32 bits, non-negative floats order the same as their bits
+}
+
+impl StepCounters {
+    //adding one particle's counts, called once per neighbor scan rather than per pair
+    pub(crate) fn record(&self, pair_checks: usize, effective_interactions: usize, max_force: f32) {
+        self.pair_checks.fetch_add(pair_checks, Relaxed);
+        self.effective_interactions.fetch_add(effective_interactions, Relaxed);
+        self.max_force_bits.fetch_max(max_force.abs().to_bits(), Relaxed);
+    }
+
+    pub(crate) fn finish(self) -> StepStats {
+        StepStats {
+            pair_checks: self.pair_checks.into_inner(),
+            effective_interactions: self.effective_interactions.into_inner(),
+            max_force_magnitude: f32::from_bits(self.max_force_bits.into_inner()),
+        }
+    }
+}
+
+//flags a quasi-steady state once the kinetic energy has changed by less than `threshold`
+//between consecutive frames for `window` frames in a row, e.g. to end batch runs early
+#[derive(Clone, Debug, PartialEq)]
+pub struct SteadyStateDetector {
+    pub threshold: f32,//largest energy change between frames that still counts as calm
+    pub window: usize,//calm frames in a row needed before reporting steady state
+    last_energy: Option<f32>,//energy seen on the previous frame
+    calm_frames: usize,//consecutive calm frames so far
+}
+
+impl SteadyStateDetector {
+    pub fn new(threshold: f32, window: usize) -> Self {
+        Self {
+            threshold,
+            window,
+            last_energy: None,
+            calm_frames: 0,
+        }
+    }
+
+    //feeding one frame's total kinetic energy, returns true while the system is steady
+    pub fn observe(&mut self, kinetic_energy: f32) -> bool {
+        if let Some(last_energy) = self.last_energy {
+            if (kinetic_energy - last_energy).abs() < self.threshold {
+                self.calm_frames += 1;
+            } else {
+                self.calm_frames = 0;
+            }
+        }
+        self.last_energy = Some(kinetic_energy);
+        self.is_steady()
+    }
+
+    //whether the last `window` frames were all calm
+    pub fn is_steady(&self) -> bool {
+        self.calm_frames >= self.window.max(1)
+    }
+
+    //forgetting the history, e.g. after the particles were regenerated
+    pub fn reset(&mut self) {
+        self.last_energy = None;
+        self.calm_frames = 0;
+    }
+}
+
+impl Particles {
+    //total kinetic energy using each type's mass (1.0 for types without one), for telling a
+    //settling parameter set from a runaway one
+    pub fn kinetic_energy(&self) -> f32 {
+        self.active_particles
+            .par_iter()
+            .map(|particle| {
+                let mass = self.masses.get(particle.id as usize).copied().unwrap_or(1.0);
+                0.5 * mass * particle.velocity.magnitude2()
+            })
+            .sum()
+    }
+
+    //sum of mass times velocity over all particles (1.0 for types without a mass), constant
+    //without gravity, walls or friction when forces are symmetric
+    pub fn total_momentum(&self) -> cgmath::Vector3<f32> {
+        self.active_particles
+            .par_iter()
+            .map(|particle| {
+                let mass = self.masses.get(particle.id as usize).copied().unwrap_or(1.0);
+                particle.velocity * mass
+            })
+            .reduce(cgmath::Vector3::zero, |a, b| a + b)
+    }
+
+    //mass weighted mean position (1.0 for types without a mass), none without particles, a
+    //cluster straddling a wrapped edge averages to somewhere between its halves
+    pub fn center_of_mass(&self) -> Option<cgmath::Vector3<f32>> {
+        let (weighted, total_mass) = self
+            .active_particles
+            .par_iter()
+            .map(|particle| {
+                let mass = self.masses.get(particle.id as usize).copied().unwrap_or(1.0);
+                (particle.position * mass, mass)
+            })
+            .reduce(|| (cgmath::Vector3::zero(), 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+        (total_mass > 0.0).then(|| weighted / total_mass)
+    }
+
+    //mean particle speed (0.0 without particles)
+    pub fn average_speed(&self) -> f32 {
+        let count = self.active_particles.len();
+        if count == 0 {
+            return 0.0;
+        }
+        self.active_particles
+            .par_iter()
+            .map(|particle| particle.velocity.magnitude())
+            .sum::<f32>()
+            / count as f32
+    }
+
+    //average number of other particles within `radius` of each particle, a measure of how much
+    //work each particle costs per step (neighbors across wrapped edges count in wrap mode), found
+    //through the same spatial hash and neighbor cells as the force pass
+    pub fn average_neighbor_count(&self, radius: f32) -> f32 {
+        let count = self.active_particles.len();
+        if count == 0 || radius <= 0.0 {
+            return 0.0;
+        }
+
+        let hash = self.spatial_hash(&self.active_particles);
+        //cells to scan around each particle so the whole radius is covered
+        let search_range = self
+            .cell_size()
+            .map(|cell_size| ((radius / cell_size).ceil() as isize).max(1));
+        let cells_per_axis = self.cells_per_axis();
+        let extents = self.world_extents();
+        let wrap = self.boundary == BoundaryMode::Wrap;
+        let axis_neighbors =
+            |axis: usize, c: isize| axis_neighbor_cells(c, search_range[axis], cells_per_axis[axis], wrap);
+
+        let total: usize = self
+            .active_particles
+            .par_iter()
+            .enumerate()
+            .map(|(i, particle)| {
+                let p = particle.position;
+                let cell = hash.cell_of(i);
+                let zs = match self.dimensions {
+                    Dim::Two => vec![cell.z],
+                    Dim::Three => axis_neighbors(2, cell.z),
+                };
+                let (xs, ys) = (axis_neighbors(0, cell.x), axis_neighbors(1, cell.y));
+                let mut neighbors = 0;
+                for &x in &xs {
+                    for &y in &ys {
+                        for &z in &zs {
+                            neighbors += hash
+                                .particles_in_cell(cgmath::vec3(x, y, z))
+                                .filter(|&j| j != i)
+                                .filter(|&j| {
+                                    let mut delta = self.active_particles[j].position - p;
+                                    //taking the shortest way around when the world wraps
+                                    if wrap {
+                                        for axis in 0..3 {
+                                            delta[axis] -= extents[axis] * (delta[axis] / extents[axis]).round();
+                                        }
+                                    }
+                                    delta.magnitude2() < radius * radius
+                                })
+                                .count();
+                        }
+                    }
+                }
+                neighbors
+            })
+            .sum();
+
+        total as f32 / count as f32
+    }
+
+    //spatial hash grid cell holding the most particles and how many it holds, for finding hotspots
+    //ties go to the smallest cell coordinates, an empty simulation gives cell 0 with 0 particles
+    pub fn densest_cell(&self) -> (cgmath::Vector3<isize>, usize) {
+        self.spatial_hash(&self.active_particles)
+            .densest_cell()
+            .unwrap_or((cgmath::vec3(0, 0, 0), 0))
+    }
+
+    //rough cluster count: occupied grid cells that touch (including diagonally) form one cluster
+    pub fn cluster_count(&self) -> usize {
+        let mut remaining: HashSet<_> = self
+            .occupied_cells()
+            .into_iter()
+            .map(|cell| (cell.x, cell.y, cell.z))
+            .collect();
+
+        let mut clusters = 0;
+        while let Some(&start) = remaining.iter().next() {
+            //flood filling everything connected to this cell
+            remaining.remove(&start);
+            let mut queue = VecDeque::from([start]);
+            while let Some((x, y, z)) = queue.pop_front() {
+                for dx in -1..=1 {
+                    for dy in -1..=1 {
+                        for dz in -1..=1 {
+                            let neighbor = (x + dx, y + dy, z + dz);
+                            if remaining.remove(&neighbor) {
+                                queue.push_back(neighbor);
+                            }
+                        }
+                    }
+                }
+            }
+            clusters += 1;
+        }
+        clusters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_particles_seeded, Particle};
+
+    //checking every pair directly
+    fn brute_force_neighbor_count(particles: &Particles, radius: f32) -> f32 {
+        let extents = particles.world_extents();
+        let all = &particles.active_particles;
+        let total: usize = (0..all.len())
+            .map(|i| {
+                (0..all.len())
+                    .filter(|&j| j != i)
+                    .filter(|&j| {
+                        let mut delta = all[j].position - all[i].position;
+                        if particles.boundary == BoundaryMode::Wrap {
+                            for axis in 0..3 {
+                                delta[axis] -= extents[axis] * (delta[axis] / extents[axis]).round();
+                            }
+                        }
+                        delta.magnitude2() < radius * radius
+                    })
+                    .count()
+            })
+            .sum();
+        total as f32 / all.len() as f32
+    }
+
+    #[test]
+    fn neighbor_count_matches_brute_force_for_any_radius() {
+        for boundary in [BoundaryMode::Wrap, BoundaryMode::Walls] {
+            let particles = Particles::builder()
+                .world_size(10.0)
+                .effect_radius(1.0)
+                .boundary(boundary)
+                .particles(generate_particles_seeded(10.0, 400, 3, 11))
+                .build()
+                .unwrap();
+            //smaller than a cell, a few cells and most of the world
+            for radius in [0.4, 2.5, 6.0] {
+                let hashed = particles.average_neighbor_count(radius);
+                let brute_force = brute_force_neighbor_count(&particles, radius);
+                assert_eq!(hashed, brute_force, "{boundary:?} radius {radius}");
+            }
+        }
+    }
+
+    #[test]
+    fn steady_state_fires_once_a_damped_simulation_settles() {
+        let moving = |x: f32| Particle {
+            position: cgmath::vec3(x, 0.0, 0.0),
+            velocity: cgmath::vec3(0.0, 1.0, 0.5),
+            id: 0,
+        };
+        let mut sim = Particles::builder()
+            .friction(5.0)
+            .attraction_matrix(vec![0.0; 25])
+            .particles(vec![moving(-3.0), moving(0.0), moving(3.0)])
+            .build()
+            .unwrap();
+        let mut detector = SteadyStateDetector::new(1e-4, 10);
+        //still losing energy quickly at first
+        for _ in 0..5 {
+            sim.update(0.02).unwrap();
+            assert!(!detector.observe(sim.kinetic_energy()));
+        }
+        let steps = (0..500)
+            .position(|_| {
+                sim.update(0.02).unwrap();
+                detector.observe(sim.kinetic_energy())
+            })
+            .expect("never settled");
+        assert!(sim.kinetic_energy() < 1e-3);
+        assert!(steps >= 10);
+
+        //starting over needs a full calm window again
+        detector.reset();
+        assert!(!detector.is_steady());
+        for _ in 0..10 {
+            sim.update(0.02).unwrap();
+            assert!(!detector.observe(sim.kinetic_energy()));
+        }
+        assert!(detector.observe(sim.kinetic_energy()));
+
+        //a kick breaks the calm
+        sim.active_particles[0].velocity = cgmath::vec3(5.0, 0.0, 0.0);
+        assert!(!detector.observe(sim.kinetic_energy()));
+    }
+
+    #[test]
+    fn symmetric_forces_conserve_momentum_in_a_wrapped_box() {
+        //drift of the total momentum over 200 steps of the default (asymmetric) matrix
+        let drift = |symmetric_forces: bool| {
+            let mut particles = generate_particles_seeded(10.0, 300, 5, 12);
+            for (i, particle) in particles.iter_mut().enumerate() {
+                let (x, y, z) = ((i % 7) as f32 - 3.0, (i % 5) as f32 - 2.0, (i % 3) as f32 - 1.0);
+                particle.velocity = cgmath::vec3(x, y, z) * 0.1;
+            }
+            let mut sim = Particles::builder()
+                .friction(0.0)
+                .symmetric_forces(symmetric_forces)
+                .particles(particles)
+                .build()
+                .unwrap();
+            sim.masses = vec![1.0, 2.0, 0.5, 1.5, 3.0];
+            let start = sim.total_momentum();
+            for _ in 0..200 {
+                sim.update(0.01).unwrap();
+            }
+            (sim.total_momentum() - start).magnitude()
+        };
+        let symmetric = drift(true);
+        assert!(symmetric < 1e-2, "{}", symmetric);
+        //the one sided matrix pushes the whole system along
+        assert!(drift(false) > 10.0 * symmetric.max(1e-3));
+    }
+
+    #[test]
+    fn kinetic_energy_weighs_each_type_by_its_mass() {
+        let mut sim = Particles::builder().id_count(2).build().unwrap();
+        sim.masses = vec![1.0, 3.0];
+        sim.active_particles = vec![
+            Particle { position: cgmath::vec3(0.0, 0.0, 0.0), velocity: cgmath::vec3(2.0, 0.0, 0.0), id: 0 },
+            Particle { position: cgmath::vec3(1.0, 0.0, 0.0), velocity: cgmath::vec3(0.0, 1.0, -1.0), id: 1 },
+        ];
+        //0.5 * 1 * 4 + 0.5 * 3 * 2
+        assert!((sim.kinetic_energy() - 5.0).abs() < 1e-6);
+        //the same energy the step hook reports
+        let reported = std::sync::Arc::new(parking_lot::Mutex::new(None));
+        let hook_reported = reported.clone();
+        sim.on_step = Some(Box::new(move |diagnostics: &SimDiagnostics| {
+            *hook_reported.lock() = Some(diagnostics.kinetic_energy);
+        }));
+        sim.coefficient = 0.0;
+        sim.update(1e-6).unwrap();
+        let expected = sim.kinetic_energy();
+        assert_eq!(*reported.lock(), Some(expected));
+    }
+
+    #[test]
+    fn center_of_mass_leans_towards_heavier_types() {
+        let mut sim = Particles::builder().id_count(2).build().unwrap();
+        sim.active_particles.clear();
+        assert_eq!(sim.center_of_mass(), None);
+        sim.masses = vec![1.0, 3.0];
+        sim.active_particles = vec![
+            Particle { position: cgmath::vec3(-2.0, 0.0, 0.0), velocity: cgmath::Vector3::zero(), id: 0 },
+            Particle { position: cgmath::vec3(2.0, 4.0, 0.0), velocity: cgmath::Vector3::zero(), id: 1 },
+        ];
+        assert_eq!(sim.center_of_mass(), Some(cgmath::vec3(1.0, 3.0, 0.0)));
+    }
+
+    #[test]
+    fn neighbor_count_of_nothing_is_zero() {
+        let particles = Particles::builder().build().unwrap();
+        assert_eq!(particles.average_neighbor_count(1.0), 0.0);
+    }
+}
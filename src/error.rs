@@ -0,0 +1,100 @@
+//everything that can go wrong in the library, returned instead of panicking so an embedding
+//app can show the problem and keep running
+#[derive(Debug, thiserror::Error)]
+pub enum SimError {
+    //the force cutoff doesn't fit inside the world (wrapping would see a particle twice),
+    //world_size is the shortest side of the box
+    #[error("world size {world_size} is smaller than twice the effect radius {effect_radius}")]
+    WorldTooSmall { world_size: f32, effect_radius: f32 },
+    //a per type table doesn't have one entry per type (or type pair)
+    #[error("{table} has {actual} values but {id_count} types need {expected}")]
+    SizeMismatch {
+        table: &'static str,
+        actual: usize,
+        expected: usize,
+        id_count: usize,
+    },
+    //a setting or argument outside its allowed range
+    #[error("invalid {name}: {reason}")]
+    InvalidParameter { name: &'static str, reason: String },
+    //a particle's position or velocity is nan or infinite
+    #[error("particle {index} has a non-finite position or velocity")]
+    NonFiniteState { index: usize },
+    //reading or writing a file failed, including malformed json or toml
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<serde_json::Error> for SimError {
+    fn from(e: serde_json::Error) -> Self {
+        SimError::Io(e.into())
+    }
+}
+
+impl From<toml::de::Error> for SimError {
+    fn from(e: toml::de::Error) -> Self {
+        SimError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl From<toml::ser::Error> for SimError {
+    fn from(e: toml::ser::Error) -> Self {
+        SimError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_particles_seeded, Particle, Particles, SimParams};
+
+    #[test]
+    fn too_small_world_is_world_too_small() {
+        let built = Particles::builder().world_size(3.0).effect_radius(2.0).build();
+        assert!(matches!(built, Err(SimError::WorldTooSmall { .. })));
+        //shrunk after building, caught by the step
+        let mut sim = Particles::builder().build().unwrap();
+        sim.world_size = 1.0;
+        assert!(matches!(sim.update(0.01), Err(SimError::WorldTooSmall { .. })));
+    }
+
+    #[test]
+    fn wrongly_sized_table_is_size_mismatch() {
+        let built = Particles::builder().attraction_matrix(vec![1.0; 3]).build();
+        assert!(matches!(built, Err(SimError::SizeMismatch { actual: 3, .. })));
+        let built = Particles::builder().radius_matrix(vec![1.0; 2]).build();
+        assert!(matches!(built, Err(SimError::SizeMismatch { actual: 2, .. })));
+    }
+
+    #[test]
+    fn out_of_range_argument_is_invalid_parameter() {
+        let mut sim = Particles::builder().build().unwrap();
+        assert!(matches!(sim.scale_world(0.0), Err(SimError::InvalidParameter { .. })));
+        assert!(matches!(
+            sim.spawn_sphere(cgmath::vec3(0.0, 0.0, 0.0), 1.0, 10, 99),
+            Err(SimError::InvalidParameter { name: "particle type", .. })
+        ));
+        assert!(matches!(
+            sim.spawn_sphere(cgmath::vec3(0.0, 0.0, 0.0), f32::NAN, 10, 0),
+            Err(SimError::InvalidParameter { name: "brush radius", .. })
+        ));
+    }
+
+    #[test]
+    fn nan_particle_is_non_finite_state() {
+        let mut particles = generate_particles_seeded(10.0, 10, 5, 1);
+        particles[4] = Particle {
+            position: cgmath::vec3(0.0, f32::NAN, 0.0),
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            id: 0,
+        };
+        let built = Particles::builder().particles(particles).build();
+        assert!(matches!(built, Err(SimError::NonFiniteState { index: 4 })));
+    }
+
+    #[test]
+    fn unreadable_input_is_io() {
+        assert!(matches!(Particles::load_from_reader(&b"not json"[..]), Err(SimError::Io(_))));
+        assert!(matches!(SimParams::load("no/such/preset.toml"), Err(SimError::Io(_))));
+    }
+}
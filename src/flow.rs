@@ -0,0 +1,95 @@
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+//analytic force field applied to every particle on top of the particle interactions
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum FlowField {
+    //swirl around a line through the origin along `axis`, stronger further from the axis
+    Vortex { axis: [f32; 3], strength: f32 },
+    //push along `direction` that grows with the position along `gradient`
+    //(opposite sides of the origin flow in opposite directions)
+    Shear { direction: [f32; 3], gradient: [f32; 3], strength: f32 },
+}
+
+impl FlowField {
+    //acceleration the field gives a particle at `position`
+    pub fn force(&self, position: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        match *self {
+            FlowField::Vortex { axis, strength } => {
+                let axis = cgmath::Vector3::from(axis);
+                if axis.magnitude2() == 0.0 {
+                    return cgmath::Vector3::zero();
+                }
+                axis.normalize().cross(position) * strength
+            }
+            FlowField::Shear { direction, gradient, strength } => {
+                let direction = cgmath::Vector3::from(direction);
+                let gradient = cgmath::Vector3::from(gradient);
+                if direction.magnitude2() == 0.0 || gradient.magnitude2() == 0.0 {
+                    return cgmath::Vector3::zero();
+                }
+                direction.normalize() * gradient.normalize().dot(position) * strength
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_particles_seeded, Particles};
+
+    //angular momentum (unit mass) of all particles about the y axis through the origin
+    fn spin_about_y(particles: &Particles) -> f32 {
+        particles
+            .active_particles
+            .iter()
+            .map(|p| p.position.cross(p.velocity).y)
+            .sum()
+    }
+
+    #[test]
+    fn vortex_only_turns_particles_around_its_axis() {
+        let vortex = FlowField::Vortex { axis: [0.0, 2.0, 0.0], strength: 0.5 };
+        for position in [cgmath::vec3(1.0, 0.0, 0.0), cgmath::vec3(-2.0, 3.0, 1.0), cgmath::vec3(0.5, -1.0, -4.0)] {
+            let force = vortex.force(position);
+            //no push along the axis or towards it, and a torque of strength * r^2 about it
+            assert_eq!(force.y, 0.0);
+            assert!(force.dot(cgmath::vec3(position.x, 0.0, position.z)).abs() < 1e-6);
+            let torque = position.cross(force).y;
+            assert!((torque - 0.5 * (position.x.powi(2) + position.z.powi(2))).abs() < 1e-5);
+        }
+        //nothing on the axis itself, or without an axis
+        assert_eq!(vortex.force(cgmath::vec3(0.0, 4.0, 0.0)), cgmath::Vector3::zero());
+        let no_axis = FlowField::Vortex { axis: [0.0; 3], strength: 0.5 };
+        assert_eq!(no_axis.force(cgmath::vec3(1.0, 0.0, 0.0)), cgmath::Vector3::zero());
+    }
+
+    #[test]
+    fn vortex_spins_a_resting_simulation_up() {
+        let mut sim = Particles::builder()
+            .world_size(20.0)
+            .friction(0.0)
+            .attraction_matrix(vec![0.0; 25])
+            .flow_field(Some(FlowField::Vortex { axis: [0.0, 1.0, 0.0], strength: 0.5 }))
+            .particles(generate_particles_seeded(10.0, 50, 5, 4))
+            .build()
+            .unwrap();
+        assert_eq!(spin_about_y(&sim), 0.0);
+        let mut spin = 0.0;
+        for _ in 0..20 {
+            sim.update(0.01).unwrap();
+            let now = spin_about_y(&sim);
+            assert!(now > spin, "{} after {}", now, spin);
+            spin = now;
+        }
+        //a negative strength turns the other way
+        if let Some(FlowField::Vortex { strength, .. }) = &mut sim.flow_field {
+            *strength = -0.5;
+        }
+        for _ in 0..20 {
+            sim.update(0.01).unwrap();
+        }
+        assert!(spin_about_y(&sim) < spin);
+    }
+}
@@ -0,0 +1,82 @@
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+//where gravity pulls particles, scaled per type by `gravity_scale`
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum GravityMode {
+    //no gravity
+    None,
+    //the same acceleration everywhere
+    Uniform(cgmath::Vector3<f32>),
+    //pull towards a fixed point, fading with distance (orbits, solar system setups)
+    Point { center: cgmath::Vector3<f32>, strength: f32 },
+}
+
+impl GravityMode {
+    //distance below which point gravity stops growing, keeps particles passing through the center
+    //from being flung away
+    pub const SOFTENING: f32 = 0.1;
+
+    //acceleration gravity gives a particle at `position`
+    pub fn acceleration(&self, position: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        match *self {
+            GravityMode::None => cgmath::Vector3::zero(),
+            GravityMode::Uniform(acceleration) => acceleration,
+            GravityMode::Point { center, strength } => {
+                let offset = center - position;
+                offset * (strength / (offset.magnitude2() + Self::SOFTENING * Self::SOFTENING))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Particles;
+
+    #[test]
+    fn point_gravity_pulls_towards_the_center_and_fades_with_distance() {
+        let center = cgmath::vec3(1.0, -2.0, 0.5);
+        let gravity = GravityMode::Point { center, strength: 3.0 };
+        let near = gravity.acceleration(center + cgmath::vec3(1.0, 0.0, 0.0));
+        let far = gravity.acceleration(center + cgmath::vec3(0.0, 0.0, -4.0));
+        //towards the center from either side
+        assert!(near.normalize().dot(cgmath::vec3(-1.0, 0.0, 0.0)) > 0.9999);
+        assert!(far.normalize().dot(cgmath::vec3(0.0, 0.0, 1.0)) > 0.9999);
+        //strength * r / (r^2 + softening^2)
+        let softening = GravityMode::SOFTENING * GravityMode::SOFTENING;
+        assert!((near.magnitude() - 3.0 / (1.0 + softening)).abs() < 1e-5);
+        assert!((far.magnitude() - 3.0 * 4.0 / (16.0 + softening)).abs() < 1e-5);
+        assert!(far.magnitude() < near.magnitude());
+        //nothing at the center itself, where the softening keeps it finite around it
+        assert_eq!(gravity.acceleration(center), cgmath::Vector3::zero());
+        let close = gravity.acceleration(center + cgmath::vec3(0.01, 0.0, 0.0));
+        assert!(close.magnitude().is_finite() && close.magnitude() < 3.0 / GravityMode::SOFTENING);
+    }
+
+    #[test]
+    fn point_gravity_draws_a_resting_particle_in() {
+        let center = cgmath::vec3(2.0, 0.0, 0.0);
+        let mut sim = Particles::builder()
+            .friction(0.0)
+            .gravity(GravityMode::Point { center, strength: 1.0 })
+            .particles(vec![crate::Particle {
+                position: cgmath::vec3(-2.0, 1.0, 0.0),
+                velocity: cgmath::Vector3::zero(),
+                id: 0,
+            }])
+            .build()
+            .unwrap();
+        let mut distance = (sim.active_particles[0].position - center).magnitude();
+        for _ in 0..50 {
+            sim.update(0.02).unwrap();
+            let now = (sim.active_particles[0].position - center).magnitude();
+            assert!(now < distance);
+            distance = now;
+        }
+        //falling straight in, never sideways
+        let offset = sim.active_particles[0].position - center;
+        assert!(offset.normalize().dot(cgmath::vec3(-4.0, 1.0, 0.0).normalize()) > 0.9999);
+    }
+}
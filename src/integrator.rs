@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+//how positions and velocities are advanced from the accelerations each step
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    //position moves with the old velocity, then the velocity is updated (least stable)
+    ExplicitEuler,
+    //velocity is updated first and the position moves with the new velocity (the classic solver)
+    SemiImplicitEuler,
+    //velocity uses the average of last and this step's acceleration, position gets the
+    //second order term, exact for constant acceleration (the stored velocity is the one at the
+    //start of the step)
+    VelocityVerlet,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GravityMode, Particle, Particles};
+
+    //how far a particle dropped from rest under uniform gravity ends up from 0.5 * a * t^2
+    fn fall_error(integrator: Integrator) -> f32 {
+        let (g, ts, steps) = (9.81, 0.05, 20);
+        let mut sim = Particles::builder()
+            .world_size(1000.0)
+            .friction(0.0)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -g, 0.0)))
+            .integrator(integrator)
+            .particles(vec![Particle {
+                position: cgmath::vec3(0.0, 0.0, 0.0),
+                velocity: cgmath::vec3(0.0, 0.0, 0.0),
+                id: 0,
+            }])
+            .build()
+            .unwrap();
+        for _ in 0..steps {
+            sim.update(ts).unwrap();
+        }
+        let t = ts * steps as f32;
+        (sim.active_particles[0].position.y - -0.5 * g * t * t).abs()
+    }
+
+    #[test]
+    fn verlet_follows_the_analytic_fall() {
+        let verlet = fall_error(Integrator::VelocityVerlet);
+        assert!(verlet < 1e-3, "verlet is {} off", verlet);
+        for euler in [Integrator::ExplicitEuler, Integrator::SemiImplicitEuler] {
+            let error = fall_error(euler);
+            assert!(verlet * 100.0 < error, "verlet {} vs {:?} {}", verlet, euler, error);
+        }
+    }
+}
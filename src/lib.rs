@@ -1,15 +1,104 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-    sync::atomic::{AtomicUsize, Ordering::Relaxed},
-};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
 
 use cgmath::prelude::*;
 use encase::ShaderType;
 use rayon::prelude::*;
 
+mod attraction;
+mod builder;
+mod diagnostics;
+mod error;
+mod integrator;
+mod flow;
+mod gravity;
+mod precision;
+mod preset;
+mod scene;
+mod separation;
+mod spatial_hash;
+mod spawn;
+mod stir;
+pub use attraction::AttractionMatrix;
+pub use builder::ParticlesBuilder;
+pub use diagnostics::{SimDiagnostics, SteadyStateDetector, StepStats};
+use diagnostics::StepCounters;
+pub use error::SimError;
+pub use flow::FlowField;
+pub use gravity::GravityMode;
+pub use integrator::Integrator;
+pub use precision::Precision;
+use precision::SolverFloat;
+pub use preset::SimParams;
+pub use scene::{DebugBundle, Scene, SceneParticle};
+pub use spatial_hash::SpatialHash;
+pub use spawn::{
+    generate_particles_seeded, generate_particles_seeded_in_box, random_particle, random_particle_in_box,
+    spawn_particles, SpawnRegion, SpeciesSpawn,
+};
+
+//smallest radius used for the grid and force cutoff, a radius of 0 acts as "no interactions"
+pub const MIN_EFFECT_RADIUS: f32 = 1e-4;
+
+//speed below which friction stops a particle outright instead of decaying it forever
+pub const REST_SPEED: f32 = 1e-6;
+
+//most substeps `update_adaptive` splits one step into
+pub const MAX_SUBSTEPS: usize = 64;
+
+//largest cutoff of any pair given the per pair radii (empty = `default` for every pair)
+fn largest_radius(radius_matrix: &[f32], default: f32) -> f32 {
+    if radius_matrix.is_empty() {
+        default
+    } else {
+        radius_matrix.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+//distinct neighbor cell coordinates along one axis within `search_range` cells of `c`, wrapped
+//around the grid when the world wraps (on a small grid every cell is a neighbor and must only be
+//visited once)
+fn axis_neighbor_cells(c: isize, search_range: isize, cells_per_axis: isize, wrap: bool) -> Vec<isize> {
+    if wrap && 2 * search_range + 1 >= cells_per_axis {
+        (0..cells_per_axis).collect()
+    } else {
+        (c - search_range..=c + search_range)
+            .filter_map(|c| {
+                if wrap {
+                    Some(c.rem_euclid(cells_per_axis))
+                } else {
+                    Some(c).filter(|&c| (0..cells_per_axis).contains(&c))
+                }
+            })
+            .collect()
+    }
+}
+
+//what happens to particles that reach the edge of the world
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BoundaryMode {
+    Wrap,//come back in on the opposite side
+    Walls,//stop at the wall
+    Open,//leave the simulation and get removed
+}
+
+//how many axes particles move along
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Dim {
+    Two,//flat field in the xy plane, z is pinned to 0
+    Three,//full volume
+}
+
+//point `distance` in front of a camera at `position` looking along `forward`
+pub fn focus_point(
+    position: cgmath::Vector3<f32>,
+    forward: cgmath::Vector3<f32>,
+    distance: f32,
+) -> cgmath::Vector3<f32> {
+    position + forward.normalize() * distance
+}
+
 //single particle with position, velocity, and identity
-#[derive(Clone, Copy, ShaderType, Debug)]
+#[derive(Clone, Copy, ShaderType, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Particle {
     pub position: cgmath::Vector3<f32>,//where particle is in 3D space
     pub velocity: cgmath::Vector3<f32>,//how fast and which direction it's moving
@@ -19,255 +108,2162 @@ pub struct Particle {
 //entire particle system and its properties
 pub struct Particles {
     pub world_size: f32,//size of the simulation box
+    //box shape as a fraction of world_size per axis, (1, 1, 1) is a cube and e.g. (0.25, 1, 0.25)
+    //a tall thin tank world_size high
+    pub world_aspect: cgmath::Vector3<f32>,
     pub active_particles: Vec<Particle>,//current state of all particles
     pub past_particles: Vec<Particle>,//previous state (needed for calculations)
     pub id_count: u32,//total number of particle types
-    pub attraction_matrix: Vec<f32>,//how much different particle types attract/repel each other
+    pub attraction_matrix: AttractionMatrix,//how much different particle types attract/repel each other
+    //allowed (min, max) for attraction values, default (-1.0, 1.0)
+    //forces scale linearly so values beyond unit strength work, but large values need
+    //a lower time step or more friction to stay stable
+    pub attraction_range: (f32, f32),
     pub colors: Vec<cgmath::Vector3<f32>>,//color for each particle type
-    pub coefficient: f32,//how quickly particles slow down
+    pub coefficient: f32,//friction decay rate per second, speed falls by exp(-coefficient * t) (0 = no friction)
+    pub friction_scale: Vec<f32>,//per type multiplier on the friction coefficient (sticky > 1 > slippery)
     pub interaction_force: f32,//how strong the forces between particles are
-    pub min_pull_ratio: f32,//minimum distance where attraction happens
-    pub particle_effect_radius: f32,//how far particles can affect each other
-    pub walls: bool, //whether particles bounce off walls or wrap around
-    pub acceleration: cgmath::Vector3<f32>, //direction and strength of gravity
+    pub pair_force_scale: Vec<f32>,//per type pair multiplier on the force, same layout as attraction_matrix
+    //using the average of a->b and b->a for attraction and pair force scale so every pair pulls
+    //both ways equally and momentum is conserved (also needs a symmetric radius_matrix)
+    pub symmetric_forces: bool,
+    //units: every distance here is in world units, the same units as world_size
+    pub min_pull_ratio: f32,//distance below which the built-in shape repels (world units, not a fraction of the radius)
+    pub particle_effect_radius: f32,//neighbor cutoff distance, also multiplies the summed force
+    //per pair cutoff distance laid out like attraction_matrix, empty uses particle_effect_radius
+    //for every pair (the grid is sized for the largest entry)
+    pub radius_matrix: Vec<f32>,
+    pub hard_radius: f32,//particles are never left closer than this after a step (0 = off)
+    pub hard_iterations: usize,//relaxation passes used to enforce hard_radius
+    pub boundary: BoundaryMode, //whether particles bounce off walls, wrap around or leave
+    pub restitution: f32,//share of the speed into a wall kept after bouncing off it (0 = stop, 1 = elastic)
+    pub gravity: GravityMode, //uniform gravity, pull towards a point or none
+    pub gravity_scale: Vec<f32>,//per type multiplier on gravity (0 floats, negative rises)
+    pub masses: Vec<f32>,//per type mass dividing the interaction force, missing types weigh 1.0
+    pub flow_field: Option<FlowField>,//swirl or shear applied to every particle
+    pub max_speed: Option<f32>,//velocity magnitude cap stopping blowups from tunneling through the world
+    pub lock_y: bool,//keeping motion in the xz plane ("tabletop" 2.5D), rendering stays 3D
+    pub dimensions: Dim,//2D pins every particle to z = 0 and skips the z neighbor cells
+    pub focus_point: Option<cgmath::Vector3<f32>>,//point every particle is pulled towards (e.g. where the camera looks)
+    pub focus_strength: f32,//acceleration towards the focus point
+    pub deterministic: bool,//same results every run regardless of threads (slower)
+    pub precision: Precision,//float type the cpu solver sums forces and integrates in
+    pub integrator: Integrator,//time stepping scheme
+    //acceleration of every particle in the last step, used by velocity verlet
+    //(ignored when it doesn't match the particle count)
+    pub previous_accelerations: Vec<cgmath::Vector3<f32>>,
+    //f64 position and velocity of every particle carried between double precision steps,
+    //active_particles holds them rounded to f32 for drawing and upload (an entry that no longer
+    //rounds to its particle was edited since and restarts from the f32 values)
+    pub double_state: Vec<[cgmath::Vector3<f64>; 2]>,
+    //custom force shape as (normalized distance, force) control points sorted by distance,
+    //linearly interpolated and scaled by the attraction value, empty uses the built-in shape
+    //(normalized distance = world distance / particle_effect_radius, so 1.0 is the cutoff)
+    pub force_curve: Vec<(f32, f32)>,
+    pub incremental_hash: bool,//re-bucketing only the particles that changed cell since the last step
+    //spatial hash cell edge (world units) overriding the effect radius, smaller cells skip more
+    //far pairs for short range setups at the cost of scanning more cells (None = effect radius)
+    pub cell_size: Option<f32>,
+    pub hash_cache: HashCache,//spatial hash of the last update, patched by the next one
+    pub sim_time: f32,//total simulated time, the sum of every ts passed to update
+    pub collect_stats: bool,//counting pair checks and interactions every step (costs a little)
+    pub step_stats: StepStats,//pair work of the last step, only updated while collect_stats is on
+    pub energy_absorbed_by_walls: f32,//kinetic energy the walls took in the last step (unit mass)
+    //per particle, whether it wrapped to the other side of the world in the last step, so drawing
+    //code can skip the segment from its old to its new position (all false unless wrapping, stale
+    //when particles were added or removed since)
+    pub wrapped: Vec<bool>,
+    //called once after every update with summary metrics, e.g. to drive audio
+    pub on_step: Option<Box<dyn FnMut(&SimDiagnostics) + Send + Sync>>,
+    //called at the end of every update with the simulation and time step for custom behaviors
+    //(injecting forces, teleporting particles, logging) without touching the solver
+    pub post_step: Option<Box<dyn FnMut(&mut Particles, f32) + Send + Sync>>,
+}
+
+//last step's spatial hash, kept so the next step only has to move the particles that changed cell
+#[derive(Default)]
+pub struct HashCache {
+    hash: SpatialHash,//empty until an update with incremental_hash on
+}
+
+//iterator stepping the simulation by a fixed time step on every call to next, ending after the
+//first failed step
+pub struct Frames<'a> {
+    particles: &'a mut Particles,//simulation being stepped
+    ts: f32,//time step per frame
+    failed: bool,//a step returned an error, so there are no more frames
 }
 
+impl Iterator for Frames<'_> {
+    type Item = Result<Vec<Particle>, SimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        //frames outlive the next step, so each one is its own copy
+        let frame = self.particles.update(self.ts).map(<[Particle]>::to_vec);
+        self.failed = frame.is_err();
+        Some(frame)
+    }
+}
+
+impl std::iter::FusedIterator for Frames<'_> {}
+
 impl Particles {
-    //checking out which grid cell a particle is in (for faster neighbor finding)
-    fn cell_coord(&self, v: cgmath::Vector3<f32>) -> cgmath::Vector3<isize> {
+    //stepping the simulation as an iterator, e.g. `for frame in sim.frames(0.016).take(100)`,
+    //every frame is the result of one `update` and an error is the last frame
+    pub fn frames(&mut self, ts: f32) -> Frames<'_> {
+        Frames { particles: self, ts, failed: false }
+    }
+
+    //largest cutoff of any pair, kept above zero so grid math never divides by zero
+    fn effect_radius(&self) -> f32 {
+        largest_radius(&self.radius_matrix, self.particle_effect_radius).max(MIN_EFFECT_RADIUS)
+    }
+
+    //attraction of type a towards type b as the solver uses it, averaged with b towards a when
+    //forces are symmetric
+    pub fn pair_attraction(&self, a: u32, b: u32) -> f32 {
+        let (a, b) = (a as usize, b as usize);
+        if self.symmetric_forces {
+            0.5 * (self.attraction_matrix.get(a, b) + self.attraction_matrix.get(b, a))
+        } else {
+            self.attraction_matrix.get(a, b)
+        }
+    }
+
+    //force multiplier of a type pair as the solver uses it, averaged like `pair_attraction`
+    pub fn pair_scale(&self, a: u32, b: u32) -> f32 {
+        let scale = |a: u32, b: u32| {
+            self.pair_force_scale
+                .get((a * self.id_count + b) as usize)
+                .copied()
+                .unwrap_or(1.0)
+        };
+        if self.symmetric_forces {
+            0.5 * (scale(a, b) + scale(b, a))
+        } else {
+            scale(a, b)
+        }
+    }
+
+    //cutoff distance for one (type, type) pair index
+    fn pair_radius(&self, pair: usize) -> f32 {
+        self.radius_matrix
+            .get(pair)
+            .copied()
+            .unwrap_or(self.particle_effect_radius)
+            .max(MIN_EFFECT_RADIUS)
+    }
+
+    //simulated time so far (not wall-clock), the x-axis for physics plots
+    pub fn sim_time(&self) -> f32 {
+        self.sim_time
+    }
+
+    //edge lengths of the world box along each axis
+    pub fn world_extents(&self) -> cgmath::Vector3<f32> {
+        self.world_aspect * self.world_size
+    }
+
+    //number of grid cells along each axis, cells are at least `cell_size` (by default the largest
+    //effect radius) wide, the neighbor scan covers as many rings as the effect radius needs
+    fn cells_per_axis(&self) -> cgmath::Vector3<isize> {
+        let cell_size = self.cell_size.unwrap_or(self.effect_radius()).max(MIN_EFFECT_RADIUS);
+        self.world_extents()
+            .map(|extent| ((extent / cell_size).floor() as isize).max(1))
+    }
+
+    //edge lengths of a spatial hash grid cell, each axis of the world split into equal cells
+    fn cell_size(&self) -> cgmath::Vector3<f32> {
+        let cells_per_axis = self.cells_per_axis();
+        let extents = self.world_extents();
         cgmath::vec3(
-            (v.x / self.particle_effect_radius) as isize,
-            (v.y / self.particle_effect_radius) as isize,
-            (v.z / self.particle_effect_radius) as isize,
+            extents.x / cells_per_axis.x as f32,
+            extents.y / cells_per_axis.y as f32,
+            extents.z / cells_per_axis.z as f32,
         )
     }
 
-    //converting a 3D grid cell into a single number for the hash table
-    fn hash_cell(cell: cgmath::Vector3<isize>) -> usize {
-        let mut hasher = DefaultHasher::new();
-        cell.x.hash(&mut hasher);
-        cell.y.hash(&mut hasher);
-        cell.z.hash(&mut hasher);
-        hasher.finish() as usize
+    //how many cells in each direction the neighbor search has to scan to cover the effect radius
+    fn cell_search_range(&self) -> cgmath::Vector3<isize> {
+        let effect_radius = self.effect_radius();
+        self.cell_size()
+            .map(|cell_size| ((effect_radius / cell_size).ceil() as isize).max(1))
+    }
+
+    //checking out which grid cell a particle is in (for faster neighbor finding), cells count
+    //from the world's min corner and anything outside the box goes to the nearest edge cell
+    fn cell_coord(&self, v: cgmath::Vector3<f32>) -> cgmath::Vector3<isize> {
+        let cell_size = self.cell_size();
+        let cells_per_axis = self.cells_per_axis();
+        let half_extents = self.world_extents() * 0.5;
+        let axis_cell = |axis: usize, c: f32| {
+            (((c + half_extents[axis]) / cell_size[axis]).floor() as isize).clamp(0, cells_per_axis[axis] - 1)
+        };
+        let mut cell = cgmath::vec3(axis_cell(0, v.x), axis_cell(1, v.y), axis_cell(2, v.z));
+        //a flat field lives in the one layer of cells holding z = 0
+        if self.dimensions == Dim::Two {
+            cell.z = axis_cell(2, 0.0);
+        }
+        cell
+    }
+
+    //sampling the custom force curve at a normalized distance (0 = touching, 1 = effect radius)
+    //values outside the control points hold the nearest end point
+    pub fn sample_force_curve(&self, normalized_distance: f32) -> f32 {
+        let curve = &self.force_curve;
+        match curve.iter().position(|&(x, _)| x > normalized_distance) {
+            None => curve.last().map_or(0.0, |&(_, y)| y),
+            Some(0) => curve[0].1,
+            Some(i) => {
+                let (x0, y0) = curve[i - 1];
+                let (x1, y1) = curve[i];
+                y0 + (y1 - y0) * (normalized_distance - x0) / (x1 - x0)
+            }
+        }
     }
 
     //checking how strongly particles interact based on distance and attraction value
-    fn calculate_force(&self, distance: f32, attraction: f32) -> f32 {
-        if distance < self.min_pull_ratio {
+    //`distance` is in world units: the built-in shape repels below min_pull_ratio, peaks halfway between
+    //min_pull_ratio and 1.0 and is zero from 1.0 on, whatever the effect radius (radii below 1.0 cut it off early),
+    //while a custom force curve is sampled at distance / effect radius
+    //(evaluated in the solver's float type, the custom curve's control points are f32 either way)
+    fn calculate_force<S: SolverFloat>(&self, distance: S, attraction: f32, radius: f32) -> S {
+        if !self.force_curve.is_empty() {
+            //custom curve is defined over the pair's effect radius and scaled per pair
+            let normalized_distance = (distance / S::from_f32(radius)).as_f32();
+            return S::from_f32(attraction * self.sample_force_curve(normalized_distance));
+        }
+        let (one, min_pull_ratio) = (S::one(), S::from_f32(self.min_pull_ratio));
+        if distance < min_pull_ratio {
             //very close particles repel each other
-            distance / self.min_pull_ratio - 1.0
-        } else if self.min_pull_ratio < distance && distance < 1.0 {
+            distance / min_pull_ratio - one
+        } else if min_pull_ratio < distance && distance < one {
             //medium distance particles attract or repel based on the attraction matrix
-            attraction * (1.0 - (2.0 * distance - 1.0 - self.min_pull_ratio)
-                .abs() / (1.0 - self.min_pull_ratio))
+            S::from_f32(attraction) * (one - (S::from_f32(2.0) * distance - one - min_pull_ratio)
+                .abs() / (one - min_pull_ratio))
         } else {
             //far particles don't affect each other
-            0.0
+            S::zero()
         }
     }
 
     //handling what happens when particles hit the world boundaries
-    fn handle_wall_collision(&self, particle: &mut Particle) {
-        let half_world = self.world_size * 0.5;
+    //returns true if the particle was outside the box on any axis (bounced, wrapped or escaped),
+    //and the kinetic energy (unit mass) the walls took by stopping its motion into them
+    fn handle_wall_collision(&self, particle: &mut Particle) -> (bool, f32) {
+        let extents = self.world_extents();
+        let half_extents = extents * 0.5;
+        let outside = !self.contains(particle.position);
+        let mut absorbed = 0.0;
+        //reflecting the part of the velocity going into the wall scaled by the restitution,
+        //returns the kinetic energy the wall took (all of that part at 0, none at 1)
+        let restitution = self.restitution.clamp(0.0, 1.0);
+        //(`wall` is the wall's side along the axis, 1.0 for the max wall and -1.0 for the min wall)
+        let bounce = |velocity: &mut f32, wall: f32| {
+            let into_wall = if wall > 0.0 { velocity.max(0.0) } else { velocity.min(0.0) };
+            *velocity -= into_wall * (1.0 + restitution);
+            0.5 * into_wall * into_wall * (1.0 - restitution * restitution)
+        };
         
         //x-axis wall handling
-        if particle.position.x > half_world {
-            if self.walls {
-                //bounce off wall
-                particle.position.x = half_world;
-                particle.velocity.x = particle.velocity.x.min(0.0);
-            } else {
+        if particle.position.x > half_extents.x {
+            match self.boundary {
+                BoundaryMode::Walls => {
+                    //bounce off wall
+                    particle.position.x = half_extents.x;
+                    absorbed += bounce(&mut particle.velocity.x, 1.0);
+                }
                 //wrap around to other side
-                particle.position.x -= self.world_size;
+                BoundaryMode::Wrap => particle.position.x -= extents.x,
+                //leave it outside, it gets removed after the step
+                BoundaryMode::Open => {}
             }
-        } else if particle.position.x < -half_world {
-            if self.walls {
-                //bounce off wall
-                particle.position.x = -half_world;
-                particle.velocity.x = particle.velocity.x.max(0.0);
-            } else {
+        } else if particle.position.x < -half_extents.x {
+            match self.boundary {
+                BoundaryMode::Walls => {
+                    //bounce off wall
+                    particle.position.x = -half_extents.x;
+                    absorbed += bounce(&mut particle.velocity.x, -1.0);
+                }
                 //wrap around to other side
-                particle.position.x += self.world_size;
+                BoundaryMode::Wrap => particle.position.x += extents.x,
+                BoundaryMode::Open => {}
             }
         }
 
         //y-axis wall handling 
-        if particle.position.y > half_world {
-            if self.walls {
-                particle.position.y = half_world;
-                particle.velocity.y = particle.velocity.y.min(0.0);
-            } else {
-                particle.position.y -= self.world_size;
+        if particle.position.y > half_extents.y {
+            match self.boundary {
+                BoundaryMode::Walls => {
+                    particle.position.y = half_extents.y;
+                    absorbed += bounce(&mut particle.velocity.y, 1.0);
+                }
+                BoundaryMode::Wrap => particle.position.y -= extents.y,
+                BoundaryMode::Open => {}
             }
-        } else if particle.position.y < -half_world {
-            if self.walls {
-                particle.position.y = -half_world;
-                particle.velocity.y = particle.velocity.y.max(0.0);
-            } else {
-                particle.position.y += self.world_size;
+        } else if particle.position.y < -half_extents.y {
+            match self.boundary {
+                BoundaryMode::Walls => {
+                    particle.position.y = -half_extents.y;
+                    absorbed += bounce(&mut particle.velocity.y, -1.0);
+                }
+                BoundaryMode::Wrap => particle.position.y += extents.y,
+                BoundaryMode::Open => {}
             }
         }
 
         //z-axis wall handling 
-        if particle.position.z > half_world {
-            if self.walls {
-                particle.position.z = half_world;
-                particle.velocity.z = particle.velocity.z.min(0.0);
-            } else {
-                particle.position.z -= self.world_size;
+        if particle.position.z > half_extents.z {
+            match self.boundary {
+                BoundaryMode::Walls => {
+                    particle.position.z = half_extents.z;
+                    absorbed += bounce(&mut particle.velocity.z, 1.0);
+                }
+                BoundaryMode::Wrap => particle.position.z -= extents.z,
+                BoundaryMode::Open => {}
             }
-        } else if particle.position.z < -half_world {
-            if self.walls {
-                particle.position.z = -half_world;
-                particle.velocity.z = particle.velocity.z.max(0.0);
-            } else {
-                particle.position.z += self.world_size;
+        } else if particle.position.z < -half_extents.z {
+            match self.boundary {
+                BoundaryMode::Walls => {
+                    particle.position.z = -half_extents.z;
+                    absorbed += bounce(&mut particle.velocity.z, -1.0);
+                }
+                BoundaryMode::Wrap => particle.position.z += extents.z,
+                BoundaryMode::Open => {}
             }
         }
+
+        (outside, absorbed)
     }
 
-    //updating all particles for one time step
-    pub fn update(&mut self, ts: f32) -> Vec<Particle> {
-        //making sure the world is big enough for our particle effects
-        assert!(self.world_size >= 2.0 * self.particle_effect_radius);
+    //radial pull towards the focus point with constant strength (zero when unset or already there)
+    pub fn focus_force(&self, position: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        match self.focus_point {
+            Some(focus) => {
+                let to_focus = focus - position;
+                let distance = to_focus.magnitude();
+                if distance > 1e-6 {
+                    to_focus / distance * self.focus_strength
+                } else {
+                    cgmath::Vector3::zero()
+                }
+            }
+            None => cgmath::Vector3::zero(),
+        }
+    }
 
-        //setting up a spatial hash table to quickly find nearby particles
-        let hash_table_length = self.active_particles.len();
-        let hash_table: Vec<_> = std::iter::repeat_with(|| AtomicUsize::new(0))
-            .take(hash_table_length + 1)
-            .collect();
+    //zooming the whole system in or out: world size, particle positions and velocities and every
+    //length setting are multiplied by `factor` (min_pull_ratio stays, the built-in force shape works
+    //in fixed world units), so world_size >= 2 * particle_effect_radius keeps holding
+    pub fn scale_world(&mut self, factor: f32) -> Result<(), SimError> {
+        if !(factor > 0.0 && factor.is_finite()) {
+            return Err(SimError::InvalidParameter {
+                name: "world scale factor",
+                reason: format!("{} is not a positive number", factor),
+            });
+        }
+        //the aspect is relative to world_size, so the box keeps its shape
+        self.world_size *= factor;
+        self.particle_effect_radius *= factor;
+        for radius in &mut self.radius_matrix {
+            *radius *= factor;
+        }
+        if let Some(max_speed) = self.max_speed.as_mut() {
+            *max_speed *= factor;
+        }
+        self.hard_radius *= factor;
+        for particle in self.active_particles.iter_mut().chain(self.past_particles.iter_mut()) {
+            particle.position *= factor;
+            particle.velocity *= factor;
+        }
+        for acceleration in &mut self.previous_accelerations {
+            *acceleration *= factor;
+        }
+        if let Some(focus_point) = self.focus_point.as_mut() {
+            *focus_point *= factor;
+        }
+        //every particle's grid cell changed
+        self.hash_cache = HashCache::default();
+        Ok(())
+    }
 
-        //parallely counting how many particles are in each grid cell
-        self.active_particles.par_iter().for_each(|sphere| {
-            let index = Self::hash_cell(self.cell_coord(sphere.position)) % hash_table_length;
-            hash_table[index].fetch_add(1, Relaxed);
-        });
+    //checking that the per type tables match id_count (an attraction matrix with the wrong length
+    //is easy to get when editing it by hand) and that every particle has a known type and
+    //a finite state
+    pub fn validate(&self) -> Result<(), SimError> {
+        let id_count = self.id_count as usize;
+        scene::check_type_tables(
+            id_count,
+            self.attraction_matrix.len(),
+            self.pair_force_scale.len(),
+            self.colors.len(),
+            self.gravity_scale.len(),
+            self.friction_scale.len(),
+        )?;
+        if let Some(particle) = self.active_particles.iter().find(|p| p.id >= self.id_count) {
+            return Err(SimError::InvalidParameter {
+                name: "particle type",
+                reason: format!("{} is out of range for {} types", particle.id, id_count),
+            });
+        }
+        scene::check_masses(&self.masses)?;
+        if let Some(index) = self.active_particles.iter().position(|p| {
+            !(p.position.x.is_finite()
+                && p.position.y.is_finite()
+                && p.position.z.is_finite()
+                && p.velocity.x.is_finite()
+                && p.velocity.y.is_finite()
+                && p.velocity.z.is_finite())
+        }) {
+            return Err(SimError::NonFiniteState { index });
+        }
+        scene::check_radius_matrix(self.radius_matrix.len(), id_count)?;
+        scene::check_world_size(self.world_extents(), self.effect_radius())
+    }
 
-        //converting counts to running totals to create index ranges
-        for i in 1..hash_table.len() {
-            hash_table[i].fetch_add(hash_table[i - 1].load(Relaxed), Relaxed);
+    //moving every particle onto the z = 0 plane with no z velocity when the simulation is 2D,
+    //e.g. right after generating particles (does nothing in 3D)
+    pub fn apply_dimensions(&mut self) {
+        if self.dimensions == Dim::Two {
+            self.active_particles.par_iter_mut().for_each(|particle| {
+                particle.position.z = 0.0;
+                particle.velocity.z = 0.0;
+            });
         }
+    }
+
+    //checking whether a point is inside the world box
+    pub fn contains(&self, position: cgmath::Vector3<f32>) -> bool {
+        let half_extents = self.world_extents() * 0.5;
+        position.x.abs() <= half_extents.x
+            && position.y.abs() <= half_extents.y
+            && position.z.abs() <= half_extents.z
+    }
 
-        //creating array to store which particle is in which position
-        let particle_indices: Vec<_> = std::iter::repeat_with(|| AtomicUsize::new(0))
-            .take(self.active_particles.len())
+    //finding every grid cell that holds at least one particle
+    pub fn occupied_cells(&self) -> Vec<cgmath::Vector3<isize>> {
+        let cells: std::collections::HashSet<_> = self
+            .active_particles
+            .par_iter()
+            .map(|particle| {
+                let cell = self.cell_coord(particle.position);
+                (cell.x, cell.y, cell.z)
+            })
             .collect();
+        cells
+            .into_iter()
+            .map(|(x, y, z)| cgmath::vec3(x, y, z))
+            .collect()
+    }
 
-        //filling the particle indices array parallely
+    //getting the min and max corner of a grid cell in world space
+    pub fn cell_bounds(&self, cell: cgmath::Vector3<isize>) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let cell_size = self.cell_size();
+        let half_extents = self.world_extents() * 0.5;
+        let min = cgmath::vec3(
+            cell.x as f32 * cell_size.x - half_extents.x,
+            cell.y as f32 * cell_size.y - half_extents.y,
+            cell.z as f32 * cell_size.z - half_extents.z,
+        );
+        (min, min + cell_size)
+    }
+
+    //building line list vertices (12 edges, 24 vertices) for a wireframe box around a grid cell
+    pub fn cell_box_vertices(&self, cell: cgmath::Vector3<isize>) -> Vec<cgmath::Vector3<f32>> {
+        let (min, max) = self.cell_bounds(cell);
+        let corner = |i: usize| {
+            cgmath::vec3(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        //each edge joins two corners that differ in exactly one axis
+        let mut vertices = Vec::with_capacity(24);
+        for i in 0..8 {
+            for axis_bit in [1, 2, 4] {
+                if i & axis_bit == 0 {
+                    vertices.push(corner(i));
+                    vertices.push(corner(i | axis_bit));
+                }
+            }
+        }
+        vertices
+    }
+
+    //particles of one type, in their order in the simulation
+    pub fn iter_type(&self, id: u32) -> impl Iterator<Item = &Particle> {
+        self.active_particles.iter().filter(move |particle| particle.id == id)
+    }
+
+    //counting how many particles of each type are in the simulation (parallel tally), one entry
+    //per type
+    pub fn count_by_type(&self) -> Vec<usize> {
+        let id_count = self.id_count as usize;
         self.active_particles
+            .par_iter()
+            .fold(
+                || vec![0usize; id_count],
+                |mut counts, particle| {
+                    //ignoring particles whose type is outside the known types
+                    if let Some(count) = counts.get_mut(particle.id as usize) {
+                        *count += 1;
+                    }
+                    counts
+                },
+            )
+            .reduce(
+                || vec![0usize; id_count],
+                |mut a, b| {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        *a += b;
+                    }
+                    a
+                },
+            )
+    }
+
+    //blending previous and current particle states for smooth rendering between physics steps
+    //alpha = 0.0 gives the previous step, alpha = 1.0 gives the current one
+    pub fn interpolated(&self, alpha: f32) -> Vec<Particle> {
+        //falling back to current state if there is no matching previous state (e.g. count changed)
+        if self.past_particles.len() != self.active_particles.len() {
+            return self.active_particles.clone();
+        }
+
+        let alpha = alpha.clamp(0.0, 1.0);
+        let extents = self.world_extents();
+        self.past_particles
+            .par_iter()
+            .zip(self.active_particles.par_iter())
+            .map(|(past, current)| {
+                let mut delta = current.position - past.position;
+                //taking the short way around when a particle wrapped to the other side
+                if self.boundary == BoundaryMode::Wrap {
+                    for axis in 0..3 {
+                        if delta[axis] > extents[axis] * 0.5 {
+                            delta[axis] -= extents[axis];
+                        } else if delta[axis] < -extents[axis] * 0.5 {
+                            delta[axis] += extents[axis];
+                        }
+                    }
+                }
+
+                let mut particle = *current;
+                particle.position = past.position + delta * alpha;
+                //keeping the blended position inside the world
+                if self.boundary == BoundaryMode::Wrap {
+                    for axis in 0..3 {
+                        if particle.position[axis] > extents[axis] * 0.5 {
+                            particle.position[axis] -= extents[axis];
+                        } else if particle.position[axis] < -extents[axis] * 0.5 {
+                            particle.position[axis] += extents[axis];
+                        }
+                    }
+                }
+                particle
+            })
+            .collect()
+    }
+
+    //grouping the particles by grid cell for this step, patching last step's hash when
+    //incremental_hash is on (see `SpatialHash::rebucket`)
+    fn step_hash(&mut self) -> SpatialHash {
+        let cells: Vec<_> = self
+            .active_particles
+            .par_iter()
+            .map(|particle| self.cell_coord(particle.position))
+            .collect();
+        let hash = if self.incremental_hash {
+            std::mem::take(&mut self.hash_cache.hash).rebucket(cells, self.deterministic)
+        } else {
+            SpatialHash::from_cells(cells, self.deterministic)
+        };
+        hash.with_search_range(self.cell_search_range())
+    }
+
+    //net interaction force on each particle (the velocity change per unit time, without gravity
+    //or friction) given the particles grouped by grid cell and their positions in S (the f32
+    //particles only give the types, distances and forces are worked out in S)
+    fn forces_with_hash<S: SolverFloat>(
+        &self,
+        particles: &[Particle],
+        positions: &[cgmath::Vector3<S>],
+        hash: &SpatialHash,
+        counters: Option<&StepCounters>,
+    ) -> Vec<cgmath::Vector3<S>> {
+        //cells to scan around each particle so the whole effect radius is covered
+        let search_range = self.cell_search_range();
+        let cells_per_axis = self.cells_per_axis();
+        let extents = self.world_extents();
+        let wrap = self.boundary == BoundaryMode::Wrap;
+        //cutoff of every type pair (and its square for the distance test) computed once per step
+        //instead of for every pair
+        let pair_radius: Vec<f32> = (0..(self.id_count * self.id_count) as usize)
+            .map(|pair| self.pair_radius(pair))
+            .collect();
+        let pair_radius_sqr: Vec<S> = pair_radius.iter().map(|&radius| S::from_f32(radius).powi(2)).collect();
+        //same for the attraction and force scale of every pair (averaged when symmetric)
+        let pairs = 0..self.id_count * self.id_count;
+        let pair_attraction: Vec<f32> = pairs
+            .clone()
+            .map(|pair| self.pair_attraction(pair / self.id_count, pair % self.id_count))
+            .collect();
+        let pair_scale: Vec<f32> = pairs
+            .map(|pair| self.pair_scale(pair / self.id_count, pair % self.id_count))
+            .collect();
+
+        let axis_neighbors =
+            |axis: usize, c: isize| axis_neighbor_cells(c, search_range[axis], cells_per_axis[axis], wrap);
+
+        particles
             .par_iter()
             .enumerate()
-            .for_each(|(i, sphere)| {
-                let index = Self::hash_cell(self.cell_coord(sphere.position)) % hash_table_length;
-                let index = hash_table[index].fetch_sub(1, Relaxed);
-                particle_indices[index - 1].store(i, Relaxed);
-            });
+            .map(|(particle_index, &updated_particle)| {
+                let cell = hash.cell_of(particle_index);
+                //pair work for the stats, reported once at the end of the scan
+                let mut pair_checks = 0;
+                let mut effective_interactions = 0;
+                let mut max_force = 0.0f32;
+
+                //calculating total force on this particle from all nearby particles, in a fixed
+                //cell order so deterministic mode only has to sort within cells
+                let mut total_force = cgmath::Vector3::<S>::zero();
+                let zs = match self.dimensions {
+                    Dim::Two => vec![cell.z],
+                    Dim::Three => axis_neighbors(2, cell.z),
+                };
+                let (xs, ys) = (axis_neighbors(0, cell.x), axis_neighbors(1, cell.y));
+                for &x in &xs {
+                    for &y in &ys {
+                        for &z in &zs {
+                            let cell = cgmath::vec3(x, y, z);
+
+                            //looking up particles in this cell using our hash table
+                            for other_index in hash.particles_in_cell(cell) {
+                                let other_particle = &particles[other_index];
+
+                                //calculating distance to the other particle, taking the shortest
+                                //way around when the world wraps (minimum image)
+                                let mut relative_position = positions[other_index] - positions[particle_index];
+                                if wrap {
+                                    for axis in 0..3 {
+                                        let extent = S::from_f32(extents[axis]);
+                                        relative_position[axis] -= extent * (relative_position[axis] / extent).round();
+                                    }
+                                }
+                                let sqr_distance = relative_position.magnitude2();
+                                pair_checks += 1;
+                                //types outside id_count (e.g. from a hand edited scene) have no
+                                //entry in the pair tables and don't interact with anything
+                                let pair = (updated_particle.id < self.id_count
+                                    && other_particle.id < self.id_count)
+                                    .then(|| (updated_particle.id * self.id_count + other_particle.id) as usize);
+                                let (Some(&attraction), Some(&radius), Some(&radius_sqr), Some(&scale)) = (
+                                    pair.and_then(|pair| pair_attraction.get(pair)),
+                                    pair.and_then(|pair| pair_radius.get(pair)),
+                                    pair.and_then(|pair| pair_radius_sqr.get(pair)),
+                                    pair.and_then(|pair| pair_scale.get(pair)),
+                                ) else {
+                                    continue;
+                                };
+
+                                //if it is close enough to affect each other and not the same particle
+                                if sqr_distance > S::zero() && sqr_distance < radius_sqr {
+                                    let distance = sqr_distance.sqrt();
+                                    //get force from attraction matrix based on particle types
+                                    let f = self.calculate_force(distance, attraction, radius)
+                                        * S::from_f32(scale);
+                                    effective_interactions += 1;
+                                    max_force = max_force.max(f.as_f32().abs());
+                                    //adding force vector to accumulated force
+                                    total_force += relative_position * (f / distance);
+                                    //debug builds stop at the first pair that blows up
+                                    debug_assert!(
+                                        total_force.x.is_finite()
+                                            && total_force.y.is_finite()
+                                            && total_force.z.is_finite(),
+                                        "non-finite force on particle {} at {:?} from particle {} at {:?} \
+                                         (attraction {}, pair force {:?})",
+                                        particle_index,
+                                        updated_particle.position,
+                                        other_index,
+                                        other_particle.position,
+                                        attraction,
+                                        f,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+                if let Some(counters) = counters {
+                    counters.record(pair_checks, effective_interactions, max_force);
+                }
+
+                total_force
+                    * S::from_f32(self.interaction_force)
+                    * S::from_f32(self.particle_effect_radius)
+            })
+            .collect()
+    }
+
+    //net interaction force on each of the given particles, reusing the spatial hash and force
+    //kernel but without integrating, for building custom integrators
+    pub fn compute_forces(&self, particles: &[Particle]) -> Vec<cgmath::Vector3<f32>> {
+        let positions: Vec<_> = particles.iter().map(|particle| particle.position).collect();
+        self.forces_with_hash(particles, &positions, &self.spatial_hash(particles), None)
+    }
+
+    //grouping the given particles by grid cell from scratch (`update` patches last step's hash)
+    fn spatial_hash(&self, particles: &[Particle]) -> SpatialHash {
+        let cells: Vec<_> = particles
+            .par_iter()
+            .map(|particle| self.cell_coord(particle.position))
+            .collect();
+        SpatialHash::from_cells(cells, self.deterministic).with_search_range(self.cell_search_range())
+    }
+
+    //every particle inside the axis aligned box from `min` to `max` (inclusive), in index order,
+    //only looking at the grid cells the box overlaps
+    pub fn particles_in_aabb(&self, min: cgmath::Vector3<f32>, max: cgmath::Vector3<f32>) -> Vec<&Particle> {
+        let hash = self.spatial_hash(&self.active_particles);
+        //positions outside the world land in edge cells, the same as the particles out there
+        let (min_cell, max_cell) = (self.cell_coord(min), self.cell_coord(max));
+        let mut found = Vec::new();
+        for x in min_cell.x..=max_cell.x {
+            for y in min_cell.y..=max_cell.y {
+                for z in min_cell.z..=max_cell.z {
+                    found.extend(hash.particles_in_cell(cgmath::vec3(x, y, z)).filter(|&index| {
+                        let p = self.active_particles[index].position;
+                        (min.x..=max.x).contains(&p.x)
+                            && (min.y..=max.y).contains(&p.y)
+                            && (min.z..=max.z).contains(&p.z)
+                    }));
+                }
+            }
+        }
+        found.sort_unstable();
+        found.into_iter().map(|index| &self.active_particles[index]).collect()
+    }
+
+    //calculating the interaction force on every particle and moving it, with the solver in S
+    fn step_particles<S: SolverFloat>(
+        &mut self,
+        hash: &SpatialHash,
+        ts: f32,
+        boundary_events: &AtomicUsize,
+        energy_absorbed: &parking_lot::Mutex<f32>,
+    ) {
+        let counters = self.collect_stats.then(StepCounters::default);
+        let states = self.solver_states::<S>();
+        let positions: Vec<_> = states.iter().map(|&[position, _]| position).collect();
+        let forces = self.forces_with_hash(&self.active_particles, &positions, hash, counters.as_ref());
+        if let Some(counters) = counters {
+            self.step_stats = counters.finish();
+        }
 
         //swaping current and previous particle arrays and prepare for update
         std::mem::swap(&mut self.active_particles, &mut self.past_particles);
         self.active_particles.clear();
+
+        //last step's accelerations only line up if no particles were added or removed since
+        let previous_accelerations = std::mem::take(&mut self.previous_accelerations);
+        let has_previous = previous_accelerations.len() == self.past_particles.len();
         
+        let wrapped: Vec<_> = std::iter::repeat_with(|| AtomicBool::new(false))
+            .take(self.past_particles.len())
+            .collect();
+
         //processing each particle in parallel
-        self.active_particles = self.past_particles
+        let stepped: Vec<_> = self.past_particles
             .par_iter()
-            .map(|&particle| {
-                let mut updated_particle = particle;
-                
-                //parallel calculating total force on this particle from all nearby particles
-                let total_force = (-1..=1)
-                    .into_par_iter()
-                    .flat_map(|x_offset| {
-                        (-1..=1).into_par_iter().flat_map(move |y_offset| {
-                            (-1..=1).into_par_iter().map(move |z_offset| {
-                                (x_offset, y_offset, z_offset)
-                            })
-                        })
-                    })
-                    .fold(
-                        || cgmath::Vector3::zero(), 
-                        |mut acc, (x_offset, y_offset, z_offset)| {
-                            //handling particles that might be on the other side of boundary
-                            let offset = cgmath::vec3(x_offset as _, y_offset as _, z_offset as _)
-                                * self.world_size;
-                            let cell = self.cell_coord(updated_particle.position + offset);
-
-                            //checking all neighboring cells for nearby particles
-                            for x_cell_offset in -1..=1 {
-                                for y_cell_offset in -1..=1 {
-                                    for z_cell_offset in -1..=1 {
-                                        let cell = cell
-                                            + cgmath::vec3(x_cell_offset, y_cell_offset, z_cell_offset);
-
-                                        //looking up particles in this cell using our hash table
-                                        let index = Self::hash_cell(cell) % hash_table_length;
-                                        for index in &particle_indices[hash_table[index]
-                                            .load(Relaxed)
-                                            ..hash_table[index + 1].load(Relaxed)]
-                                        {
-                                            let other_particle =
-                                                &self.past_particles[index.load(Relaxed)];
-
-                                            //calculating distance to the other particle
-                                            let relative_position = other_particle.position
-                                                - (updated_particle.position + offset);
-                                            let sqr_distance = relative_position.magnitude2();
-                                            
-                                            //if it is close enough to affect each other and not the same particle
-                                            if sqr_distance > 0.0
-                                                && sqr_distance
-                                                    < self.particle_effect_radius
-                                                        * self.particle_effect_radius
-                                            {
-                                                let distance = sqr_distance.sqrt();
-                                                //get force from attraction matrix based on particle types
-                                                let f = self.calculate_force(
-                                                    distance,
-                                                    self.attraction_matrix[(updated_particle.id
-                                                        * self.id_count
-                                                        + other_particle.id)
-                                                        as usize],
-                                                );
-                                                //adding force vector to accumulated force
-                                                acc += relative_position / distance * f;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            acc
-                        }
-                    )
-                    .reduce(
-                        || cgmath::Vector3::zero(), 
-                        |a, b| a + b
-                    );
-
-                //updating velocity based on calculated forces
-                updated_particle.velocity +=
-                    total_force * self.interaction_force * self.particle_effect_radius * ts;
-                //applying gravity
-                updated_particle.velocity += self.acceleration * ts;
-
-                //applying friction to slow particles down
-                let velocity_change = updated_particle.velocity * self.coefficient * ts;
-                if velocity_change.magnitude2() > updated_particle.velocity.magnitude2() {
-                    //stopping completely if friction would reverse direction
-                    updated_particle.velocity = cgmath::vec3(0.0, 0.0, 0.0);
-                } else {
-                    //otherwise just slow down
-                    updated_particle.velocity -= velocity_change;
-                }
+            .zip(states.par_iter().zip(forces.par_iter()))
+            .enumerate()
+            .map(|(index, (&particle, (&state, &force)))| {
+                let previous_acceleration = previous_accelerations.get(index).filter(|_| has_previous);
+                let (mut updated_particle, mut state, acceleration) =
+                    self.integrate(particle, state, force, previous_acceleration.copied(), ts);
+                //debug builds catch blowups that come from integration rather than a single pair
+                debug_assert!(
+                    updated_particle.position.x.is_finite()
+                        && updated_particle.position.y.is_finite()
+                        && updated_particle.position.z.is_finite()
+                        && updated_particle.velocity.x.is_finite()
+                        && updated_particle.velocity.y.is_finite()
+                        && updated_particle.velocity.z.is_finite(),
+                    "non-finite state after integrating particle {} at {:?} with velocity {:?} \
+                     (force {:?}, result {:?})",
+                    index,
+                    particle.position,
+                    particle.velocity,
+                    force.map(S::as_f32),
+                    updated_particle,
+                );
 
-                //updating position based on velocity
-                updated_particle.position += updated_particle.velocity * ts;
                 //handling collisions with world boundaries
-                self.handle_wall_collision(&mut updated_particle);
+                let (outside, absorbed) = self.handle_wall_collision(&mut updated_particle);
+                if outside {
+                    boundary_events.fetch_add(1, Relaxed);
+                    if self.boundary == BoundaryMode::Wrap {
+                        wrapped[index].store(true, Relaxed);
+                    }
+                    //bounces and wraps happen on the f32 particle, the state goes on from there
+                    state = [updated_particle.position, updated_particle.velocity].map(|v| v.map(S::from_f32));
+                }
+                if absorbed > 0.0 {
+                    *energy_absorbed.lock() += absorbed;
+                }
 
-                updated_particle
+                (updated_particle, state, acceleration)
             })
             .collect();
+        self.active_particles = stepped.iter().map(|&(particle, _, _)| particle).collect();
+        self.previous_accelerations = stepped.iter().map(|&(_, _, acceleration)| acceleration).collect();
+        //only double precision has more to keep than the f32 particles
+        self.double_state = if self.precision == Precision::Double {
+            stepped.iter().map(|&(_, state, _)| state.map(|v| v.map(S::as_f64))).collect()
+        } else {
+            Vec::new()
+        };
+        self.wrapped = wrapped.into_iter().map(AtomicBool::into_inner).collect();
+    }
+
+    //position and velocity of every particle to step from in S: the f64 state kept from the last
+    //double precision step where it still rounds to the particle, the f32 particle otherwise
+    fn solver_states<S: SolverFloat>(&self) -> Vec<[cgmath::Vector3<S>; 2]> {
+        let rounded = |v: cgmath::Vector3<f64>| v.map(|c| c as f32);
+        self.active_particles
+            .par_iter()
+            .enumerate()
+            .map(|(index, particle)| match self.double_state.get(index) {
+                Some(&[position, velocity])
+                    if rounded(position) == particle.position && rounded(velocity) == particle.velocity =>
+                {
+                    [position.map(S::from_f64), velocity.map(S::from_f64)]
+                }
+                _ => [particle.position.map(S::from_f32), particle.velocity.map(S::from_f32)],
+            })
+            .collect()
+    }
+
+    //advancing one particle from its position and velocity in S by ts given its interaction
+    //force with the chosen integrator, returns the particle rounded to f32, the new state in S
+    //and the particle's acceleration this step (everything except friction)
+    fn integrate<S: SolverFloat>(
+        &self,
+        particle: Particle,
+        [mut position, mut velocity]: [cgmath::Vector3<S>; 2],
+        force: cgmath::Vector3<S>,
+        previous_acceleration: Option<cgmath::Vector3<f32>>,
+        ts: f32,
+    ) -> (Particle, [cgmath::Vector3<S>; 2], cgmath::Vector3<f32>) {
+        let ts = S::from_f32(ts);
+
+        //accelerating based on calculated forces, heavier types respond less
+        let mass = self.masses.get(particle.id as usize).copied().unwrap_or(1.0);
+        let mut acceleration = force / S::from_f32(mass);
+        //applying gravity scaled for this particle's type
+        let gravity_scale = self
+            .gravity_scale
+            .get(particle.id as usize)
+            .copied()
+            .unwrap_or(1.0);
+        acceleration += self.gravity.acceleration(particle.position).map(S::from_f32) * S::from_f32(gravity_scale);
+        //pushing particles along the flow field
+        if let Some(flow_field) = &self.flow_field {
+            acceleration += flow_field.force(particle.position).map(S::from_f32);
+        }
+        //pulling towards the focus point if there is one
+        acceleration += self.focus_force(particle.position).map(S::from_f32);
+        //flattening motion onto the xz plane, whatever forces pointed up or down (and before
+        //explicit euler moves with the velocity, which may still have speed from before the lock)
+        if self.lock_y {
+            acceleration.y = S::zero();
+            velocity.y = S::zero();
+        }
+        if self.dimensions == Dim::Two {
+            acceleration.z = S::zero();
+            velocity.z = S::zero();
+        }
+
+        //moving with the velocity from before the step, the other schemes move after friction
+        if self.integrator == Integrator::ExplicitEuler {
+            position += velocity * ts;
+        }
+        velocity += match (self.integrator, previous_acceleration) {
+            //averaging with the acceleration the position was moved with last step
+            (Integrator::VelocityVerlet, Some(previous)) => {
+                (previous.map(S::from_f32) + acceleration) * S::from_f32(0.5) * ts
+            }
+            //the first verlet step starts from the current velocity as is
+            (Integrator::VelocityVerlet, None) => cgmath::Vector3::zero(),
+            _ => acceleration * ts,
+        };
+
+        //applying friction scaled for this particle's type to slow it down
+        let friction_scale = self
+            .friction_scale
+            .get(particle.id as usize)
+            .copied()
+            .unwrap_or(1.0);
+        //decaying exponentially so the damping over a given time doesn't depend on how it's split
+        //into steps (the old `velocity -= velocity * k * ts` only matched this for tiny steps)
+        let decay_rate = S::from_f32(self.coefficient) * S::from_f32(friction_scale);
+        velocity *= (-decay_rate * ts).exp();
+        if velocity.magnitude2() < S::from_f32(REST_SPEED * REST_SPEED) {
+            //stopping completely once the particle has all but come to rest
+            velocity = cgmath::Vector3::zero();
+        }
+
+        //verlet's previous acceleration may still point out of the plane from before the lock
+        if self.lock_y {
+            velocity.y = S::zero();
+        }
+        if self.dimensions == Dim::Two {
+            velocity.z = S::zero();
+        }
+
+        //capping the speed so a close encounter can't fling a particle across the world
+        if let Some(max_speed) = self.max_speed {
+            let max_speed = S::from_f32(max_speed.max(0.0));
+            let speed_sqr = velocity.magnitude2();
+            if speed_sqr > max_speed * max_speed {
+                velocity *= max_speed / speed_sqr.sqrt();
+            }
+        }
+
+        //updating position based on velocity
+        match self.integrator {
+            Integrator::ExplicitEuler => {}
+            Integrator::SemiImplicitEuler => position += velocity * ts,
+            Integrator::VelocityVerlet => {
+                position += velocity * ts + acceleration * (S::from_f32(0.5) * ts * ts)
+            }
+        }
+        //keeping a flat field exactly flat, even for particles added off the plane
+        if self.dimensions == Dim::Two {
+            position.z = S::zero();
+        }
+
+        let updated_particle = Particle {
+            position: position.map(S::as_f32),
+            velocity: velocity.map(S::as_f32),
+            id: particle.id,
+        };
+        (updated_particle, [position, velocity], acceleration.map(S::as_f32))
+    }
+
+    //updating all particles for one time step, the simulation is left untouched if it can't run
+    pub fn update(&mut self, ts: f32) -> Result<&[Particle], SimError> {
+        //making sure the world is big enough for our particle effects
+        scene::check_world_size(self.world_extents(), self.effect_radius())?;
+        //a partly filled radius matrix would leave some pairs without a cutoff
+        scene::check_radius_matrix(self.radius_matrix.len(), self.id_count as usize)?;
+        //a zero or negative mass would blow the forces up or flip them
+        scene::check_masses(&self.masses)?;
+        //a matrix sized for another type count silently leaves some pairs neutral
+        debug_assert_eq!(
+            self.attraction_matrix.len(),
+            (self.id_count * self.id_count) as usize,
+            "attraction matrix must have id_count * id_count values"
+        );
+
+        //working out which slot each particle belongs in
+        let hash = self.step_hash();
+
+        //counting particles that bounced or wrapped this step
+        let boundary_events = AtomicUsize::new(0);
+        //kinetic energy lost in wall bounces this step (only locked when a bounce loses energy)
+        let energy_absorbed = parking_lot::Mutex::new(0.0f32);
+
+        //moving every particle with the solver in the chosen precision
+        match self.precision {
+            Precision::Single => {
+                self.step_particles::<f32>(&hash, ts, &boundary_events, &energy_absorbed)
+            }
+            Precision::Double => {
+                self.step_particles::<f64>(&hash, ts, &boundary_events, &energy_absorbed)
+            }
+        }
+
+        //keeping the hash for the next step to patch
+        if self.incremental_hash {
+            self.hash_cache.hash = hash;
+        }
+
+        //pushing apart overlapping particles, counting the pushes through a wall like the step's
+        let (separation_events, separation_absorbed) = self.enforce_min_separation();
+        boundary_events.fetch_add(separation_events, Relaxed);
+        *energy_absorbed.lock() += separation_absorbed;
+
+        //dropping particles that left an open box (next step rebuilds the hash for the new count)
+        if self.boundary == BoundaryMode::Open {
+            let inside: Vec<bool> = self
+                .active_particles
+                .iter()
+                .map(|particle| self.contains(particle.position))
+                .collect();
+            let mut keep = inside.iter();
+            self.active_particles.retain(|_| *keep.next().unwrap());
+            //keeping the verlet accelerations lined up with the particles that are left
+            let mut keep = inside.iter();
+            self.previous_accelerations.retain(|_| *keep.next().unwrap());
+            let mut keep = inside.iter();
+            self.wrapped.retain(|_| *keep.next().unwrap());
+            let mut keep = inside.iter();
+            self.double_state.retain(|_| *keep.next().unwrap());
+        }
+
+        self.sim_time += ts;
+        self.energy_absorbed_by_walls = energy_absorbed.into_inner();
+
+        //reporting this step's summary to the hook (only computed if someone listens)
+        if self.on_step.is_some() {
+            let boundary_events = boundary_events.load(Relaxed);
+            let diagnostics = SimDiagnostics {
+                kinetic_energy: self.kinetic_energy(),
+                wall_collisions: if self.boundary == BoundaryMode::Walls { boundary_events } else { 0 },
+                wraps: if self.boundary == BoundaryMode::Wrap { boundary_events } else { 0 },
+                escaped: if self.boundary == BoundaryMode::Open { boundary_events } else { 0 },
+                energy_absorbed_by_walls: self.energy_absorbed_by_walls,
+                cluster_count: self.cluster_count(),
+            };
+            if let Some(on_step) = self.on_step.as_mut() {
+                on_step(&diagnostics);
+            }
+        }
+
+        //running the custom step logic, taken out while it runs so it can borrow the simulation
+        if let Some(mut post_step) = self.post_step.take() {
+            post_step(self, ts);
+            //keeping a hook the callback may have installed in its place
+            if self.post_step.is_none() {
+                self.post_step = Some(post_step);
+            }
+        }
+
+        //handing out the updated particles without copying them
+        Ok(&self.active_particles)
+    }
+
+    //advancing by ts in equal `update` substeps, as many as the fastest particle needs to move less
+    //than the smallest pair radius per substep (so it can't skip past a neighbor), at most MAX_SUBSTEPS,
+    //returns how many substeps ran, speeds are measured once at the start of the step, and a
+    //substep that can't run stops there (the substeps before it are kept)
+    pub fn update_adaptive(&mut self, ts: f32) -> Result<usize, SimError> {
+        let max_speed = self
+            .active_particles
+            .par_iter()
+            .map(|particle| particle.velocity.magnitude())
+            .reduce(|| 0.0, f32::max);
+        let radius = (0..(self.id_count * self.id_count) as usize)
+            .map(|pair| self.pair_radius(pair))
+            .fold(self.effect_radius(), f32::min);
+        let substeps = ((max_speed * ts / radius).floor() as usize).saturating_add(1).min(MAX_SUBSTEPS);
+        let sub_ts = ts / substeps as f32;
+
+        //a wrap in any substep counts as a wrap in the whole step
+        let mut wrapped = vec![false; self.active_particles.len()];
+        for _ in 0..substeps {
+            self.update(sub_ts)?;
+            if self.wrapped.len() == wrapped.len() {
+                for (any, &now) in wrapped.iter_mut().zip(&self.wrapped) {
+                    *any |= now;
+                }
+            } else {
+                //particles left an open box, the earlier flags no longer line up
+                wrapped = self.wrapped.clone();
+            }
+        }
+        self.wrapped = wrapped;
+        Ok(substeps)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(x: f32, y: f32, z: f32, id: u32) -> Particle {
+        Particle {
+            position: cgmath::vec3(x, y, z),
+            velocity: cgmath::Vector3::zero(),
+            id,
+        }
+    }
+
+    //two types in a cube of 10 where every pair attracts at full strength
+    fn two_types() -> ParticlesBuilder {
+        Particles::builder()
+            .world_size(10.0)
+            .id_count(2)
+            .colors(vec![cgmath::vec3(1.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, 1.0)])
+            .attraction_matrix(vec![1.0; 4])
+    }
+
+    //three types in a cube of 10 where every pair attracts at full strength
+    fn three_types() -> ParticlesBuilder {
+        Particles::builder()
+            .world_size(10.0)
+            .id_count(3)
+            .colors(vec![cgmath::vec3(1.0, 1.0, 1.0); 3])
+            .attraction_matrix(vec![1.0; 9])
+    }
+
+    //force on a particle of type a from one of type b 0.6 away, inside the attracting band
+    fn pair_force(sim: &Particles, a: u32, b: u32) -> cgmath::Vector3<f32> {
+        sim.compute_forces(&[particle(0.0, 0.0, 0.0, a), particle(0.6, 0.0, 0.0, b)])[0]
+    }
+
+    //highest point a particle dropped from y = 4 onto the floor of a walled cube of 10 reaches
+    //after its first bounce
+    fn rebound_height(restitution: f32) -> f32 {
+        let mut sim = two_types()
+            .boundary(BoundaryMode::Walls)
+            .restitution(restitution)
+            .friction(0.0)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -9.81, 0.0)))
+            .particles(vec![particle(0.0, 4.0, 0.0, 0)])
+            .build()
+            .unwrap();
+        let mut bounced = false;
+        let mut highest = f32::MIN;
+        for _ in 0..8000 {
+            sim.update(0.0005).unwrap();
+            let particle = sim.active_particles[0];
+            if !bounced {
+                bounced = particle.velocity.y > 0.0;
+            } else if particle.velocity.y <= 0.0 {
+                break;
+            }
+            if bounced {
+                highest = highest.max(particle.position.y);
+            }
+        }
+        highest
+    }
+
+    #[test]
+    fn rebound_height_follows_the_restitution_squared() {
+        //a 9 high drop comes back up restitution^2 of the way
+        for restitution in [0.3, 0.5, 0.8, 1.0] {
+            let expected = -5.0 + 9.0 * restitution * restitution;
+            let height = rebound_height(restitution);
+            assert!((height - expected).abs() < 0.1, "{} reached {} instead of {}", restitution, height, expected);
+        }
+        //a dead wall keeps it on the floor
+        let mut sim = two_types()
+            .boundary(BoundaryMode::Walls)
+            .restitution(0.0)
+            .particles(vec![particle(0.0, -4.99, 0.0, 0)])
+            .build()
+            .unwrap();
+        sim.active_particles[0].velocity = cgmath::vec3(0.0, -10.0, 0.0);
+        sim.update(0.01).unwrap();
+        assert_eq!(sim.active_particles[0].position.y, -5.0);
+        assert_eq!(sim.active_particles[0].velocity.y, 0.0);
+    }
+
+    #[test]
+    fn walls_report_the_kinetic_energy_they_absorb() {
+        for restitution in [0.0, 0.5, 1.0] {
+            let mut sim = two_types()
+                .boundary(BoundaryMode::Walls)
+                .restitution(restitution)
+                .friction(0.0)
+                .particles(vec![particle(4.95, 0.0, 0.0, 0), particle(0.0, 0.0, 0.0, 1)])
+                .build()
+                .unwrap();
+            //hitting the wall at 3 along x while sliding along z, which the wall keeps
+            sim.active_particles[0].velocity = cgmath::vec3(3.0, 0.0, 1.0);
+            sim.update(0.1).unwrap();
+            let expected = 0.5 * 3.0 * 3.0 * (1.0 - restitution * restitution);
+            assert!((sim.energy_absorbed_by_walls - expected).abs() < 1e-5, "{}", restitution);
+            let velocity = sim.active_particles[0].velocity;
+            assert!((velocity.x + 3.0 * restitution).abs() < 1e-5);
+            assert_eq!(velocity.z, 1.0);
+            //a step without a bounce takes nothing
+            sim.active_particles[0].position = cgmath::vec3(0.0, 3.0, 0.0);
+            sim.update(0.01).unwrap();
+            assert_eq!(sim.energy_absorbed_by_walls, 0.0);
+        }
+    }
+
+    #[test]
+    fn box_world_wraps_each_axis_at_its_own_extent() {
+        let mut sim = two_types()
+            .world_extents(cgmath::vec3(20.0, 5.0, 5.0))
+            .friction(0.0)
+            .particles(vec![
+                particle(9.9, 0.0, 0.0, 0),
+                particle(0.0, 2.4, 0.0, 0),
+                particle(0.0, 0.0, -2.4, 0),
+                particle(4.0, 0.0, 2.0, 0),
+            ])
+            .build()
+            .unwrap();
+        assert_eq!(sim.world_extents(), cgmath::vec3(20.0, 5.0, 5.0));
+        sim.attraction_matrix = vec![0.0; 4].into();
+        sim.active_particles[0].velocity = cgmath::vec3(2.0, 0.0, 0.0);
+        sim.active_particles[1].velocity = cgmath::vec3(0.0, 2.0, 0.0);
+        sim.active_particles[2].velocity = cgmath::vec3(0.0, 0.0, -2.0);
+        //moving 3 along x, which would be past a 5 wide wall but is inside the long axis
+        sim.active_particles[3].velocity = cgmath::vec3(30.0, 0.0, 0.0);
+        sim.update(0.1).unwrap();
+
+        let positions: Vec<_> = sim.active_particles.iter().map(|p| p.position).collect();
+        let expected = [
+            cgmath::vec3(-9.9, 0.0, 0.0),
+            cgmath::vec3(0.0, -2.4, 0.0),
+            cgmath::vec3(0.0, 0.0, 2.4),
+            cgmath::vec3(7.0, 0.0, 2.0),
+        ];
+        for (position, expected) in positions.iter().zip(expected) {
+            assert!((position - expected).magnitude() < 1e-4, "{:?} instead of {:?}", position, expected);
+        }
+        assert_eq!(sim.wrapped, vec![true, true, true, false]);
+    }
+
+    #[test]
+    fn interpolation_takes_the_short_way_across_a_wrap() {
+        let mut sim = two_types()
+            .particles(vec![particle(-4.8, 0.0, 0.0, 0), particle(1.0, 0.0, 0.0, 1)])
+            .build()
+            .unwrap();
+        //the first particle wrapped from 4.6 past the right wall, the second moved normally
+        sim.past_particles = vec![particle(4.6, 1.0, 0.0, 0), particle(0.0, 0.0, 0.0, 1)];
+
+        let halfway = sim.interpolated(0.5);
+        //0.4 along the way through the wall lands right on it, not back through the middle
+        assert!((halfway[0].position - cgmath::vec3(4.9, 0.5, 0.0)).magnitude() < 1e-5, "{:?}", halfway[0].position);
+        assert!((halfway[1].position - cgmath::vec3(0.5, 0.0, 0.0)).magnitude() < 1e-5);
+        //further along it's back inside the world, on the other side
+        let later = sim.interpolated(0.75);
+        assert!((later[0].position - cgmath::vec3(-4.95, 0.25, 0.0)).magnitude() < 1e-5, "{:?}", later[0].position);
+        //and the ends are the stored states
+        assert_eq!(sim.interpolated(0.0)[0].position, sim.past_particles[0].position);
+        assert!((sim.interpolated(1.0)[0].position - sim.active_particles[0].position).magnitude() < 1e-5);
+
+        //walls don't wrap, so the same jump is blended straight through
+        sim.boundary = BoundaryMode::Walls;
+        assert!((sim.interpolated(0.5)[0].position - cgmath::vec3(-0.1, 0.5, 0.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn aabb_query_matches_brute_force() {
+        let extents = cgmath::vec3(12.0, 6.0, 8.0);
+        let sim = Particles::builder()
+            .world_extents(extents)
+            .cell_size(Some(0.7))
+            .particles(generate_particles_seeded_in_box(extents, 2000, 5, 14))
+            .build()
+            .unwrap();
+        let boxes = [
+            (cgmath::vec3(-1.0, -1.0, -1.0), cgmath::vec3(1.0, 1.0, 1.0)),
+            (cgmath::vec3(-6.0, -3.0, -4.0), cgmath::vec3(-4.5, 3.0, 0.2)),
+            //reaching past the world on every side
+            (cgmath::vec3(-50.0, -50.0, -50.0), cgmath::vec3(50.0, 50.0, 50.0)),
+            //a flat slab and an inside out box
+            (cgmath::vec3(-6.0, 0.0, -4.0), cgmath::vec3(6.0, 0.05, 4.0)),
+            (cgmath::vec3(1.0, 1.0, 1.0), cgmath::vec3(-1.0, -1.0, -1.0)),
+        ];
+        for (min, max) in boxes {
+            let found: Vec<*const Particle> =
+                sim.particles_in_aabb(min, max).into_iter().map(|p| p as *const Particle).collect();
+            let expected: Vec<*const Particle> = sim
+                .active_particles
+                .iter()
+                .filter(|p| (0..3).all(|axis| min[axis] <= p.position[axis] && p.position[axis] <= max[axis]))
+                .map(|p| p as *const Particle)
+                .collect();
+            assert_eq!(found, expected, "{:?} to {:?}", min, max);
+        }
+        assert_eq!(sim.particles_in_aabb(boxes[2].0, boxes[2].1).len(), 2000);
+    }
+
+    #[test]
+    fn two_dimensions_keep_every_particle_on_the_z_plane() {
+        let mut sim = Particles::builder()
+            .dimensions(Dim::Two)
+            .gravity(GravityMode::Point { center: cgmath::vec3(0.0, 0.0, 3.0), strength: 2.0 })
+            .flow_field(Some(FlowField::Vortex { axis: [1.0, 0.0, 0.0], strength: 0.5 }))
+            .particles(generate_particles_seeded(10.0, 400, 5, 2))
+            .build()
+            .unwrap();
+        //generated particles fill the volume until flattened
+        assert!(sim.active_particles.iter().any(|p| p.position.z != 0.0));
+        sim.active_particles[0].velocity = cgmath::vec3(1.0, 1.0, 1.0);
+        sim.apply_dimensions();
+        assert_eq!(sim.active_particles[0].velocity, cgmath::vec3(1.0, 1.0, 0.0));
+        //forces pulling out of the plane (here towards z = 3 and around the x axis) are dropped
+        for _ in 0..50 {
+            sim.update(0.02).unwrap();
+            assert!(sim.active_particles.iter().all(|p| p.position.z == 0.0 && p.velocity.z == 0.0));
+        }
+        assert!(sim.active_particles.iter().any(|p| p.velocity.x != 0.0 || p.velocity.y != 0.0));
+
+        //in 3d flattening does nothing
+        let mut volume = Particles::builder().particles(generate_particles_seeded(10.0, 50, 5, 2)).build().unwrap();
+        let before: Vec<_> = volume.active_particles.iter().map(|p| p.position).collect();
+        volume.apply_dimensions();
+        assert!(volume.active_particles.iter().zip(&before).all(|(p, &b)| p.position == b));
+    }
+
+    #[test]
+    fn locked_y_keeps_every_height_across_steps() {
+        for integrator in [Integrator::ExplicitEuler, Integrator::SemiImplicitEuler, Integrator::VelocityVerlet] {
+            let mut sim = Particles::builder()
+                .lock_y(true)
+                .integrator(integrator)
+                .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -9.81, 0.0)))
+                .particles(generate_particles_seeded(10.0, 400, 5, 3))
+                .build()
+                .unwrap();
+            //including vertical speed left over from before the lock
+            sim.active_particles[0].velocity = cgmath::vec3(0.5, 2.0, -0.5);
+            let heights: Vec<f32> = sim.active_particles.iter().map(|p| p.position.y).collect();
+            for _ in 0..50 {
+                sim.update(0.02).unwrap();
+                let flat = sim.active_particles.iter().zip(&heights).all(|(p, &y)| p.position.y == y && p.velocity.y == 0.0);
+                assert!(flat, "{:?}", integrator);
+            }
+            //while still moving in the xz plane
+            assert!(sim.active_particles.iter().any(|p| p.velocity.x != 0.0 || p.velocity.z != 0.0));
+        }
+    }
+
+    #[test]
+    fn friction_decays_the_same_however_the_time_is_split() {
+        let coasting = |friction: f32| {
+            let mut sim = two_types().friction(friction).particles(vec![particle(0.0, 0.0, 0.0, 0)]).build().unwrap();
+            sim.active_particles[0].velocity = cgmath::vec3(3.0, -1.0, 2.0);
+            sim.friction_scale = vec![1.5, 1.0];
+            sim
+        };
+        for friction in [0.5, 0.97, 4.0] {
+            let mut one = coasting(friction);
+            one.update(0.1).unwrap();
+            let mut two = coasting(friction);
+            two.update(0.05).unwrap();
+            two.update(0.05).unwrap();
+            let mut ten = coasting(friction);
+            for _ in 0..10 {
+                ten.update(0.01).unwrap();
+            }
+            //exp(-k * scale * t) of the starting velocity
+            let expected = cgmath::vec3(3.0, -1.0, 2.0) * (-friction * 1.5 * 0.1f32).exp();
+            for sim in [&one, &two, &ten] {
+                assert!((sim.active_particles[0].velocity - expected).magnitude() < 1e-5, "{}", friction);
+            }
+        }
+    }
+
+    #[test]
+    fn max_speed_caps_the_speed_but_keeps_the_direction() {
+        let mut sim = two_types()
+            .max_speed(Some(2.0))
+            .friction(0.0)
+            .particles(vec![particle(0.0, 0.0, 0.0, 0), particle(3.0, 3.0, 3.0, 1)])
+            .build()
+            .unwrap();
+        sim.active_particles[0].velocity = cgmath::vec3(30.0, 40.0, 0.0);
+        sim.active_particles[1].velocity = cgmath::vec3(0.0, 1.0, 0.0);
+        sim.update(0.01).unwrap();
+        let fast = sim.active_particles[0].velocity;
+        assert!((fast.magnitude() - 2.0).abs() < 1e-5);
+        assert!((fast.normalize() - cgmath::vec3(0.6, 0.8, 0.0)).magnitude() < 1e-5);
+        //moved at the capped speed, not the original one
+        assert!((sim.active_particles[0].position - cgmath::vec3(0.012, 0.016, 0.0)).magnitude() < 1e-5);
+        //slower particles are left alone
+        assert_eq!(sim.active_particles[1].velocity, cgmath::vec3(0.0, 1.0, 0.0));
+
+        //without a cap the speed is kept
+        sim.max_speed = None;
+        sim.active_particles[0].velocity = cgmath::vec3(30.0, 40.0, 0.0);
+        sim.update(0.01).unwrap();
+        assert!((sim.active_particles[0].velocity.magnitude() - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn heavier_types_accelerate_less_from_the_same_force() {
+        let mut sim = two_types()
+            .friction(0.0)
+            .symmetric_forces(true)
+            .particles(vec![particle(4.5, 5.0, 5.0, 0), particle(5.5, 5.0, 5.0, 1)])
+            .build()
+            .unwrap();
+        sim.masses = vec![1.0, 4.0];
+        sim.update(0.01).unwrap();
+        let (light, heavy) = (sim.active_particles[0].velocity, sim.active_particles[1].velocity);
+        //pulled towards each other, the heavy one a quarter as fast
+        assert!(light.x > 0.0 && heavy.x < 0.0);
+        assert!((light.x + 4.0 * heavy.x).abs() < 1e-5 * light.x, "{:?} {:?}", light, heavy);
+        //so the momentum stays zero
+        assert!((light * 1.0 + heavy * 4.0).magnitude() < 1e-5);
+        //gravity pulls every mass the same
+        let mut falling = two_types()
+            .friction(0.0)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -2.0, 0.0)))
+            .particles(vec![particle(0.0, 0.0, 0.0, 0), particle(4.0, 0.0, 0.0, 1)])
+            .build()
+            .unwrap();
+        falling.masses = vec![1.0, 4.0];
+        falling.update(0.01).unwrap();
+        assert_eq!(falling.active_particles[0].velocity, falling.active_particles[1].velocity);
+    }
+
+    #[test]
+    fn only_particles_that_crossed_are_flagged_as_wrapped() {
+        let moving = || {
+            let mut particles = vec![particle(4.95, 0.0, 0.0, 0), particle(0.0, -4.9, 3.0, 1), particle(-3.0, 3.0, -3.0, 0)];
+            particles[0].velocity = cgmath::vec3(1.0, 0.0, 0.0);
+            //reaching the bottom wall in the fourth step
+            particles[1].velocity = cgmath::vec3(0.0, -0.3, 0.0);
+            particles[2].velocity = cgmath::vec3(0.0, 0.5, 0.0);
+            particles
+        };
+        let mut sim = two_types().friction(0.0).particles(moving()).build().unwrap();
+        sim.attraction_matrix = vec![0.0; 4].into();
+        assert!(sim.wrapped.is_empty());
+        sim.update(0.1).unwrap();
+        assert_eq!(sim.wrapped, vec![true, false, false]);
+        //the flags only cover the last step
+        sim.update(0.1).unwrap();
+        assert_eq!(sim.wrapped, vec![false, false, false]);
+        sim.update(0.1).unwrap();
+        assert_eq!(sim.wrapped, vec![false, false, false]);
+        sim.update(0.1).unwrap();
+        assert_eq!(sim.wrapped, vec![false, true, false]);
+
+        //a bounce isn't a wrap
+        let mut walled = two_types().boundary(BoundaryMode::Walls).particles(moving()).build().unwrap();
+        walled.attraction_matrix = vec![0.0; 4].into();
+        walled.update(0.1).unwrap();
+        assert_eq!(walled.wrapped, vec![false; 3]);
+
+        //and adaptive substeps flag a wrap in any of their substeps
+        let mut adaptive = two_types().friction(0.0).effect_radius(0.1).particles(moving()).build().unwrap();
+        adaptive.attraction_matrix = vec![0.0; 4].into();
+        adaptive.active_particles[0].velocity.x = 20.0;
+        let substeps = adaptive.update_adaptive(0.1).unwrap();
+        assert!(substeps > 1);
+        assert_eq!(adaptive.wrapped, vec![true, false, false]);
+    }
+
+    #[test]
+    fn count_by_type_tallies_a_known_particle_set() {
+        let particles = vec![
+            particle(0.0, 0.0, 0.0, 0),
+            particle(1.0, 0.0, 0.0, 2),
+            particle(2.0, 0.0, 0.0, 2),
+            particle(3.0, 0.0, 0.0, 0),
+            particle(4.0, 0.0, 0.0, 2),
+        ];
+        let mut sim = Particles::builder()
+            .id_count(4)
+            .colors(vec![cgmath::vec3(1.0, 1.0, 1.0); 4])
+            .particles(particles)
+            .build()
+            .unwrap();
+        //one entry per type, including the type nobody has
+        assert_eq!(sim.count_by_type(), vec![2, 0, 3, 0]);
+        sim.active_particles.clear();
+        assert_eq!(sim.count_by_type(), vec![0; 4]);
+    }
+
+    #[test]
+    fn seeded_generation_gives_the_expected_type_histogram() {
+        let sim = Particles::builder().particles(generate_particles_seeded(10.0, 1000, 5, 7)).build().unwrap();
+        let counts = sim.count_by_type();
+        assert_eq!(counts.len(), sim.id_count as usize);
+        //the parallel tally agrees with filtering one type at a time
+        let expected: Vec<usize> = (0..sim.id_count).map(|id| sim.iter_type(id).count()).collect();
+        assert_eq!(counts, expected);
+        assert_eq!(counts.iter().sum::<usize>(), 1000);
+        assert!(sim.iter_type(3).all(|p| p.id == 3));
+        //the same seed gives the same histogram, and every type gets a fair share of 1000
+        let again = Particles::builder().particles(generate_particles_seeded(10.0, 1000, 5, 7)).build().unwrap();
+        assert_eq!(again.count_by_type(), counts);
+        assert!(counts.iter().all(|&count| (120..=280).contains(&count)), "{counts:?}");
+    }
+
+    #[test]
+    fn open_boundary_removal_keeps_the_per_particle_state_aligned() {
+        //particle 1 leaves through the +x face in the first step, the others fall in place and
+        //type 1 falls three times as fast, so a shifted acceleration would show
+        let open = |escaping_speed: f32| {
+            let mut particles = vec![particle(0.0, 0.0, 0.0, 0), particle(4.95, 0.0, 0.0, 0), particle(-2.0, 0.0, 0.0, 1)];
+            particles[1].velocity.x = escaping_speed;
+            let mut sim = two_types()
+                .boundary(BoundaryMode::Open)
+                .integrator(Integrator::VelocityVerlet)
+                .precision(Precision::Double)
+                .friction(0.0)
+                .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -1.0, 0.0)))
+                .particles(particles)
+                .build()
+                .unwrap();
+            sim.attraction_matrix = vec![0.0; 4].into();
+            sim.gravity_scale = vec![1.0, 3.0];
+            sim
+        };
+        let mut sim = open(1.0);
+        sim.update(0.1).unwrap();
+        assert_eq!(sim.active_particles.len(), 2);
+        assert_eq!(sim.active_particles.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(sim.previous_accelerations, vec![cgmath::vec3(0.0, -1.0, 0.0), cgmath::vec3(0.0, -3.0, 0.0)]);
+        assert_eq!(sim.wrapped, vec![false; 2]);
+        assert_eq!(sim.double_state.len(), 2);
+        for (state, particle) in sim.double_state.iter().zip(&sim.active_particles) {
+            assert_eq!(state[0].map(|c| c as f32), particle.position);
+        }
+
+        //the survivors go on exactly like they do in a box nobody leaves
+        let mut kept = open(0.0);
+        kept.update(0.1).unwrap();
+        for _ in 0..5 {
+            sim.update(0.1).unwrap();
+            kept.update(0.1).unwrap();
+        }
+        for (particle, twin) in sim.active_particles.iter().zip([kept.active_particles[0], kept.active_particles[2]]) {
+            assert_eq!((particle.position, particle.velocity), (twin.position, twin.velocity));
+        }
+    }
+
+    #[test]
+    fn on_step_reports_each_update_once() {
+        let reports = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let listen = |sim: &mut Particles| {
+            let hook_reports = reports.clone();
+            sim.on_step = Some(Box::new(move |diagnostics: &SimDiagnostics| hook_reports.lock().push(*diagnostics)));
+        };
+        let mut crossing = vec![particle(4.95, 0.0, 0.0, 0), particle(-3.0, 0.0, 0.0, 1)];
+        crossing[0].velocity = cgmath::vec3(1.0, 0.0, 0.0);
+
+        let mut sim = two_types().friction(0.0).particles(crossing.clone()).build().unwrap();
+        sim.attraction_matrix = vec![0.0; 4].into();
+        listen(&mut sim);
+        for step in 1..=3 {
+            sim.update(0.1).unwrap();
+            let reports = reports.lock();
+            assert_eq!(reports.len(), step);
+            let last = reports[step - 1];
+            assert_eq!(last.kinetic_energy, sim.kinetic_energy());
+            assert_eq!(last.cluster_count, sim.cluster_count());
+            //only the first step carries the particle across the edge
+            assert_eq!((last.wraps, last.wall_collisions, last.escaped), (usize::from(step == 1), 0, 0));
+        }
+
+        //the same crossing is a bounce between walls
+        reports.lock().clear();
+        let mut walled = two_types().boundary(BoundaryMode::Walls).particles(crossing).build().unwrap();
+        walled.attraction_matrix = vec![0.0; 4].into();
+        listen(&mut walled);
+        walled.update(0.1).unwrap();
+        let reports = reports.lock();
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].wraps, reports[0].wall_collisions), (0, 1));
+        assert_eq!(reports[0].energy_absorbed_by_walls, walled.energy_absorbed_by_walls);
+    }
+
+    #[test]
+    fn post_step_hook_that_stops_particles_takes_effect_every_step() {
+        let mut particles = vec![particle(0.0, 0.0, 0.0, 0), particle(1.0, 0.0, 0.0, 1)];
+        particles[0].velocity = cgmath::vec3(2.0, 0.0, 0.0);
+        let mut sim = two_types().friction(0.0).particles(particles).build().unwrap();
+        let calls = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let hook_calls = calls.clone();
+        sim.post_step = Some(Box::new(move |particles: &mut Particles, ts: f32| {
+            hook_calls.lock().push(ts);
+            for particle in &mut particles.active_particles {
+                particle.velocity = cgmath::Vector3::zero();
+            }
+        }));
+        for step in 1..=3 {
+            let before: Vec<_> = sim.active_particles.iter().map(|p| p.position).collect();
+            let ts = 0.01 * step as f32;
+            let after = sim.update(ts).unwrap();
+            //the returned particles already went through the hook
+            assert!(after.iter().all(|p| p.velocity == cgmath::Vector3::zero()));
+            assert_eq!(calls.lock().len(), step);
+            assert_eq!(calls.lock()[step - 1], ts);
+            //after the first step everything starts at rest, so a particle only moves by what one
+            //step's force adds (the first step still carries particle 0's speed of 2)
+            if step > 1 {
+                for (particle, before) in sim.active_particles.iter().zip(&before) {
+                    assert!((particle.position - before).magnitude() < ts, "{:?}", particle.position);
+                }
+            }
+        }
+        //the hook stays installed
+        assert!(sim.post_step.is_some());
+    }
+
+    #[test]
+    fn doubled_attraction_doubles_only_that_pairs_force() {
+        let mut sim = three_types().attraction_range(-2.0, 2.0).build().unwrap();
+        let unit: Vec<_> = (0..9).map(|pair| pair_force(&sim, pair / 3, pair % 3)).collect();
+        assert!(unit.iter().all(|force| force.x > 0.0), "{unit:?}");
+        sim.attraction_matrix.set(0, 1, 2.0);
+        for pair in 0..9 {
+            let force = pair_force(&sim, pair / 3, pair % 3);
+            let expected = if pair == 1 { unit[pair as usize] * 2.0 } else { unit[pair as usize] };
+            assert!((force - expected).magnitude() < 1e-6, "{pair}: {force:?}");
+        }
+    }
+
+    #[test]
+    fn doubled_pair_force_scale_doubles_only_that_pairs_force() {
+        let mut sim = three_types().build().unwrap();
+        let unit: Vec<_> = (0..9).map(|pair| pair_force(&sim, pair / 3, pair % 3)).collect();
+        //type 0 pulled twice as hard towards type 2 (not the other way around)
+        sim.pair_force_scale[2] = 2.0;
+        for pair in 0..9 {
+            let force = pair_force(&sim, pair / 3, pair % 3);
+            let expected = if pair == 2 { unit[pair as usize] * 2.0 } else { unit[pair as usize] };
+            assert!((force - expected).magnitude() < 1e-6, "{pair}: {force:?}");
+        }
+    }
+
+    #[test]
+    fn doubled_gravity_scale_doubles_only_that_types_fall() {
+        //one particle of each type, far enough apart not to interact
+        let mut sim = three_types()
+            .friction(0.0)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -1.0, 0.0)))
+            .particles(vec![particle(-3.0, 0.0, 0.0, 0), particle(0.0, 0.0, 0.0, 1), particle(3.0, 0.0, 0.0, 2)])
+            .build()
+            .unwrap();
+        sim.gravity_scale = vec![1.0, 2.0, 1.0];
+        for _ in 0..10 {
+            sim.update(0.1).unwrap();
+        }
+        let [a, b, c] = [0, 1, 2].map(|index| sim.active_particles[index]);
+        assert!((a.velocity.y + 1.0).abs() < 1e-5, "{:?}", a.velocity);
+        assert!((b.velocity.y - 2.0 * a.velocity.y).abs() < 1e-5, "{:?}", b.velocity);
+        assert!((b.position.y - 2.0 * a.position.y).abs() < 1e-5, "{:?}", b.position);
+        assert_eq!((c.position.y, c.velocity.y), (a.position.y, a.velocity.y));
+    }
+
+    #[test]
+    fn doubled_friction_scale_doubles_only_that_types_decay_rate() {
+        let moving = |id: u32, x: f32| {
+            let mut particle = particle(x, 0.0, 0.0, id);
+            particle.velocity = cgmath::vec3(0.0, 0.0, 2.0);
+            particle
+        };
+        let mut sim = three_types()
+            .friction(0.5)
+            .particles(vec![moving(0, -3.0), moving(1, 0.0), moving(2, 3.0)])
+            .build()
+            .unwrap();
+        sim.friction_scale = vec![1.0, 2.0, 1.0];
+        sim.update(0.1).unwrap();
+        //speed falls by exp(-0.5 * scale * 0.1)
+        let speeds: Vec<_> = sim.active_particles.iter().map(|p| p.velocity.z).collect();
+        assert!((speeds[0] - 2.0 * (-0.05f32).exp()).abs() < 1e-5, "{speeds:?}");
+        assert!((speeds[1] - 2.0 * (-0.1f32).exp()).abs() < 1e-5, "{speeds:?}");
+        assert_eq!(speeds[2], speeds[0]);
+    }
+
+    #[test]
+    fn zero_effect_radius_steps_without_interactions_or_nans() {
+        let mut sim = Particles::builder().particles(generate_particles_seeded(10.0, 300, 5, 4)).build().unwrap();
+        //what dragging the slider to 0 does to a running simulation
+        sim.particle_effect_radius = 0.0;
+        sim.collect_stats = true;
+        for _ in 0..5 {
+            let particles = sim.update(0.02).unwrap();
+            assert!(particles.iter().all(|p| {
+                [p.position, p.velocity].iter().all(|v| v.x.is_finite() && v.y.is_finite() && v.z.is_finite())
+            }));
+            assert_eq!(sim.step_stats.effective_interactions, 0);
+        }
+        let forces = sim.compute_forces(&sim.active_particles);
+        assert!(forces.iter().all(|force| *force == cgmath::Vector3::zero()));
+    }
+
+    //the check only exists in debug builds
+    #[cfg(debug_assertions)]
+    #[test]
+    fn overflowing_attraction_names_the_offending_pair() {
+        let mut sim = two_types().build().unwrap();
+        sim.attraction_matrix = vec![0.0, 0.0, f32::MAX, 0.0].into();
+        //type 1 pulled towards type 0 at the peak of the band, where the force is the full value
+        let particles = [particle(0.0, 0.0, 0.0, 0), particle(0.65, 0.0, 0.0, 1)];
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| sim.compute_forces(&particles)))
+            .expect_err("overflow went unnoticed");
+        let message = panic
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_default();
+        assert!(message.starts_with("non-finite force on particle 1 at"), "{message}");
+        assert!(message.contains("from particle 0 at"), "{message}");
+        assert!(message.contains(&format!("attraction {}", f32::MAX)), "{message}");
+    }
+
+    #[test]
+    fn sim_time_is_the_sum_of_every_time_step() {
+        let mut sim = Particles::builder().particles(generate_particles_seeded(10.0, 100, 5, 6)).build().unwrap();
+        let steps = [0.01, 0.05, 0.002, 0.1, 0.033];
+        for ts in steps {
+            sim.update(ts).unwrap();
+        }
+        //adaptive steps add their ts once however they split it
+        sim.update_adaptive(0.2).unwrap();
+        let total = steps.iter().sum::<f32>() + 0.2;
+        assert!((sim.sim_time() - total).abs() < 1e-6, "{}", sim.sim_time());
+        //starting over starts the clock over
+        sim.reseed(6);
+        assert_eq!(sim.sim_time(), 0.0);
+    }
+
+    #[test]
+    fn scaling_the_world_up_and_back_restores_it() {
+        let mut particles = generate_particles_seeded(10.0, 200, 2, 8);
+        for (i, particle) in particles.iter_mut().enumerate() {
+            particle.velocity = cgmath::vec3(i as f32 % 3.0 - 1.0, 0.5, -0.25);
+        }
+        let mut sim = two_types()
+            .radius_matrix(vec![1.0, 2.0, 2.0, 1.5])
+            .max_speed(Some(3.0))
+            .particles(particles)
+            .build()
+            .unwrap();
+        sim.focus_point = Some(cgmath::vec3(1.0, -2.0, 0.5));
+        let original = sim.active_particles.clone();
+
+        sim.scale_world(2.0).unwrap();
+        assert_eq!(sim.world_size, 20.0);
+        assert_eq!(sim.radius_matrix, vec![2.0, 4.0, 4.0, 3.0]);
+        //still a valid world with every particle inside it
+        sim.validate().unwrap();
+        assert!(sim.active_particles.iter().all(|p| sim.contains(p.position)));
+
+        sim.scale_world(0.5).unwrap();
+        assert_eq!((sim.world_size, sim.particle_effect_radius, sim.max_speed), (10.0, 2.0, Some(3.0)));
+        assert_eq!(sim.radius_matrix, vec![1.0, 2.0, 2.0, 1.5]);
+        assert_eq!(sim.focus_point, Some(cgmath::vec3(1.0, -2.0, 0.5)));
+        for (particle, original) in sim.active_particles.iter().zip(&original) {
+            assert!((particle.position - original.position).magnitude() < 1e-5);
+            assert!((particle.velocity - original.velocity).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn force_curve_hits_its_control_points_and_interpolates_between_them() {
+        let mut sim = two_types().build().unwrap();
+        assert_eq!(sim.sample_force_curve(0.5), 0.0);
+        sim.force_curve = vec![(0.0, -1.0), (0.3, 0.0), (0.6, 1.0), (1.0, 0.0)];
+        for &(x, y) in &sim.force_curve {
+            assert_eq!(sim.sample_force_curve(x), y, "{x}");
+        }
+        for (x, y) in [(0.15, -0.5), (0.45, 0.5), (0.5, 2.0 / 3.0), (0.8, 0.5), (0.9, 0.25)] {
+            assert!((sim.sample_force_curve(x) - y).abs() < 1e-5, "{x}: {}", sim.sample_force_curve(x));
+        }
+        //holding the end points outside the curve
+        assert_eq!((sim.sample_force_curve(-0.5), sim.sample_force_curve(1.5)), (-1.0, 0.0));
+
+        //the solver samples it at distance / effect radius, scaled by the pair's attraction (and
+        //like every force by the effect radius of 2)
+        sim.attraction_matrix = vec![0.5; 4].into();
+        let force = sim.compute_forces(&[particle(0.0, 0.0, 0.0, 0), particle(0.9, 0.0, 0.0, 1)])[0];
+        assert!((force - cgmath::vec3(0.5, 0.0, 0.0)).magnitude() < 1e-5, "{force:?}");
+    }
+
+    #[test]
+    fn cell_boxes_trace_the_twelve_edges_of_their_cell() {
+        //cells of 2 x 2 x 2, 8 x 4 x 2 of them
+        let sim = two_types().world_extents(cgmath::vec3(16.0, 8.0, 4.0)).build().unwrap();
+        for (cell, min, max) in [
+            (cgmath::vec3(0, 0, 0), cgmath::vec3(-8.0, -4.0, -2.0), cgmath::vec3(-6.0, -2.0, 0.0)),
+            (cgmath::vec3(7, 1, 0), cgmath::vec3(6.0, -2.0, -2.0), cgmath::vec3(8.0, 0.0, 0.0)),
+            (cgmath::vec3(7, 3, 1), cgmath::vec3(6.0, 2.0, 0.0), cgmath::vec3(8.0, 4.0, 2.0)),
+        ] {
+            assert_eq!(sim.cell_bounds(cell), (min, max));
+            let vertices = sim.cell_box_vertices(cell);
+            assert_eq!(vertices.len(), 24);
+            //every vertex is a corner of the cell
+            for v in &vertices {
+                assert!((0..3).all(|axis| v[axis] == min[axis] || v[axis] == max[axis]), "{v:?}");
+            }
+            //12 different edges, each along one axis and as long as the cell
+            let mut edges = Vec::new();
+            for line in vertices.chunks(2) {
+                let (a, b) = (line[0], line[1]);
+                let differing: Vec<_> = (0..3).filter(|&axis| a[axis] != b[axis]).collect();
+                assert_eq!(differing.len(), 1, "{a:?} {b:?}");
+                assert_eq!((a - b).magnitude(), 2.0);
+                assert!(!edges.contains(&(a, b)) && !edges.contains(&(b, a)));
+                edges.push((a, b));
+            }
+        }
+    }
+
+    #[test]
+    fn compute_forces_matches_the_forces_update_applies() {
+        let mut sim = Particles::builder()
+            .friction(0.0)
+            .particles(generate_particles_seeded(10.0, 400, 5, 21))
+            .build()
+            .unwrap();
+        sim.masses = vec![1.0, 2.0, 0.5, 1.5, 3.0];
+        let forces = sim.compute_forces(&sim.active_particles);
+        assert!(forces.iter().any(|force| force.magnitude() > 0.1));
+        let ts = 0.01;
+        sim.update(ts).unwrap();
+        //starting at rest without friction, a step's acceleration is the force over the mass and
+        //the velocity is that over one step
+        for ((particle, acceleration), force) in sim.active_particles.iter().zip(&sim.previous_accelerations).zip(&forces) {
+            let expected = force / sim.masses[particle.id as usize];
+            assert!((acceleration - expected).magnitude() < 1e-4, "{acceleration:?} {expected:?}");
+            assert!((particle.velocity - expected * ts).magnitude() < 1e-5, "{:?}", particle.velocity);
+        }
+    }
+
+    #[test]
+    fn frames_step_once_per_frame() {
+        let mut sim = two_types()
+            .particles(vec![particle(4.5, 5.0, 5.0, 0), particle(5.5, 5.0, 5.0, 1)])
+            .build()
+            .unwrap();
+        let frames: Vec<_> = sim.frames(0.01).take(10).collect::<Result<_, _>>().unwrap();
+        assert_eq!(frames.len(), 10);
+        assert!((sim.sim_time - 0.1).abs() < 1e-6);
+        //every frame is a new step of the attracting pair, and the last one is where the sim is
+        assert!(frames.windows(2).all(|pair| pair[0][0].position != pair[1][0].position));
+        let gap = |frame: &[Particle]| (frame[1].position - frame[0].position).magnitude();
+        assert!(gap(&frames[9]) < 1.0);
+        assert_eq!(frames[9][0].position, sim.active_particles[0].position);
+    }
+
+    #[test]
+    fn frames_end_after_the_first_error() {
+        let mut sim = two_types().particles(vec![particle(5.0, 5.0, 5.0, 0)]).build().unwrap();
+        sim.masses[0] = 0.0;
+        let mut frames = sim.frames(0.01);
+        assert!(matches!(frames.next(), Some(Err(_))));
+        assert!(frames.next().is_none());
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn focus_point_lies_along_the_view_direction() {
+        let point = focus_point(cgmath::vec3(1.0, 2.0, 3.0), cgmath::vec3(0.0, 0.0, -4.0), 5.0);
+        assert!((point - cgmath::vec3(1.0, 2.0, -2.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn focus_force_pulls_towards_the_point_with_constant_strength() {
+        let mut sim = two_types().build().unwrap();
+        assert_eq!(sim.focus_force(cgmath::vec3(1.0, 0.0, 0.0)), cgmath::Vector3::zero());
+
+        sim.focus_point = Some(cgmath::vec3(0.0, 1.0, 0.0));
+        sim.focus_strength = 2.5;
+        for position in [cgmath::vec3(0.0, -3.0, 0.0), cgmath::vec3(4.0, 1.0, 0.0), cgmath::vec3(0.5, 1.5, -0.5)] {
+            let force = sim.focus_force(position);
+            assert!((force.magnitude() - 2.5).abs() < 1e-5);
+            let towards = (cgmath::vec3(0.0, 1.0, 0.0) - position).normalize();
+            assert!(force.normalize().dot(towards) > 0.9999);
+        }
+        //nothing at the point itself
+        assert_eq!(sim.focus_force(cgmath::vec3(0.0, 1.0, 0.0)), cgmath::Vector3::zero());
+    }
+
+    #[test]
+    fn adaptive_substeps_follow_the_smallest_pair_radius() {
+        //moving 2 per step against a default radius of 1 and one pair's radius of 0.25
+        let moving = |radius_matrix: Vec<f32>| {
+            let mut sim = two_types().effect_radius(1.0).radius_matrix(radius_matrix).build().unwrap();
+            let mut p = particle(0.0, 0.0, 0.0, 0);
+            p.velocity = cgmath::vec3(2.0, 0.0, 0.0);
+            sim.active_particles = vec![p];
+            sim.update_adaptive(1.0).unwrap()
+        };
+        assert_eq!(moving(vec![]), 3);
+        assert_eq!(moving(vec![1.0, 1.0, 1.0, 0.25]), 9);
+    }
+
+    #[test]
+    fn out_of_range_type_does_not_interact() {
+        let mut sim = two_types().build().unwrap();
+        //validation would reject the type, so it is slipped in after building
+        sim.active_particles = vec![particle(0.0, 0.0, 0.0, 0), particle(0.5, 0.0, 0.0, 7)];
+
+        let particles = sim.update(0.01).unwrap();
+        assert_eq!(particles[0].velocity, cgmath::Vector3::zero());
+        assert_eq!(particles[1].velocity, cgmath::Vector3::zero());
+    }
+
+    //positions and velocities as bits, so comparisons catch any rounding difference
+    fn state_bits(particles: &[Particle]) -> Vec<[u32; 6]> {
+        particles
+            .iter()
+            .map(|p| {
+                [
+                    p.position.x.to_bits(),
+                    p.position.y.to_bits(),
+                    p.position.z.to_bits(),
+                    p.velocity.x.to_bits(),
+                    p.velocity.y.to_bits(),
+                    p.velocity.z.to_bits(),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clusters_in_colliding_cells_do_not_interact() {
+        //unit cells in a world of 10, two pairs of particles in cells sharing a slot of the
+        //4 slot table while being too far apart to be neighbors
+        let cell_a = cgmath::vec3(2isize, 2, 2);
+        let wrapped_distance = |a: isize, b: isize| (a - b).rem_euclid(10).min((b - a).rem_euclid(10));
+        let cell_b = (0..1000)
+            .map(|i| cgmath::vec3(i / 100, i / 10 % 10, i % 10))
+            .find(|&cell: &cgmath::Vector3<isize>| {
+                (0..3).any(|axis| wrapped_distance(cell[axis], cell_a[axis]) >= 3)
+                    && spatial_hash::hash_cell(cell) % 4 == spatial_hash::hash_cell(cell_a) % 4
+            })
+            .unwrap();
+        let cluster = |cell: cgmath::Vector3<isize>| {
+            let center = cell.map(|c| c as f32 - 4.5);
+            vec![particle(center.x - 0.1, center.y, center.z, 0), particle(center.x + 0.1, center.y, center.z, 1)]
+        };
+        let step = |particles: Vec<Particle>| {
+            let mut sim = two_types().effect_radius(1.0).particles(particles).build().unwrap();
+            sim.collect_stats = true;
+            sim.update(0.01).unwrap();
+            (state_bits(&sim.active_particles), sim.step_stats.pair_checks)
+        };
+
+        let (alone_a, checks_a) = step(cluster(cell_a));
+        let (alone_b, checks_b) = step(cluster(cell_b));
+        let (together, checks) = step([cluster(cell_a), cluster(cell_b)].concat());
+        assert_eq!(together, [alone_a, alone_b].concat());
+        //the other cluster's particles weren't even looked at
+        assert_eq!(checks, checks_a + checks_b);
+    }
+
+    #[test]
+    fn pair_across_the_wrap_interacts_exactly_once() {
+        //on a 10 cell grid and on a 2 cell grid where every cell is a neighbor of every other
+        for radius in [1.0, 4.0] {
+            let sim = two_types().effect_radius(radius).build().unwrap();
+            let across = sim.compute_forces(&[particle(4.9, 0.0, 0.0, 0), particle(-4.9, 0.0, 0.0, 1)]);
+            let inside = sim.compute_forces(&[particle(-0.1, 0.0, 0.0, 0), particle(0.1, 0.0, 0.0, 1)]);
+            assert!(inside[0].x != 0.0);
+            //(the wrapped distance is off in the last bits, a double count would be twice the force)
+            for (a, b) in across.iter().zip(&inside) {
+                assert!((a - b).magnitude() < 1e-3 * b.magnitude(), "{:?} vs {:?}", a, b);
+            }
+
+            let mut sim = two_types()
+                .effect_radius(radius)
+                .particles(vec![particle(4.9, 0.0, 0.0, 0), particle(-4.9, 0.0, 0.0, 1)])
+                .build()
+                .unwrap();
+            sim.collect_stats = true;
+            sim.update(0.01).unwrap();
+            assert_eq!(sim.step_stats.effective_interactions, 2);
+        }
+    }
+
+    //interaction force on every particle from every other one, no grid
+    fn brute_force_forces(sim: &Particles, particles: &[Particle]) -> Vec<cgmath::Vector3<f32>> {
+        let extents = sim.world_extents();
+        particles
+            .iter()
+            .map(|p| {
+                let mut total = cgmath::Vector3::zero();
+                for other in particles {
+                    let mut relative = other.position - p.position;
+                    for axis in 0..3 {
+                        relative[axis] -= extents[axis] * (relative[axis] / extents[axis]).round();
+                    }
+                    let distance = relative.magnitude();
+                    let radius = sim.pair_radius((p.id * sim.id_count + other.id) as usize);
+                    if distance > 0.0 && distance < radius {
+                        let f = sim.calculate_force(distance, sim.pair_attraction(p.id, other.id), radius)
+                            * sim.pair_scale(p.id, other.id);
+                        total += relative * (f / distance);
+                    }
+                }
+                total * sim.interaction_force * sim.particle_effect_radius
+            })
+            .collect()
+    }
 
-        //returning the updated particles
-        self.active_particles.clone()
+    fn assert_forces_close(actual: &[cgmath::Vector3<f32>], expected: &[cgmath::Vector3<f32>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, b) in actual.iter().zip(expected) {
+            assert!((a - b).magnitude() <= 1e-4 * (1.0 + b.magnitude()), "{:?} vs {:?}", a, b);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn precomputed_pair_radii_give_the_forces_of_the_per_pair_square_root() {
+        let particles = generate_particles_seeded(10.0, 400, 2, 9);
+        for force_curve in [vec![], vec![(0.0, -1.0), (0.4, 0.8), (1.0, 0.0)]] {
+            let sim = two_types()
+                .attraction_matrix(vec![0.6, -0.4, 1.0, 0.2])
+                .radius_matrix(vec![1.3, 0.7, 0.7, 2.1])
+                .force_curve(force_curve)
+                .particles(particles.clone())
+                .build()
+                .unwrap();
+            //the kernel before precomputing: the cutoff recovered from its square for every pair
+            let extents = sim.world_extents();
+            let old: Vec<_> = particles
+                .iter()
+                .map(|p| {
+                    let mut total = cgmath::Vector3::zero();
+                    for other in &particles {
+                        let mut relative = other.position - p.position;
+                        for axis in 0..3 {
+                            relative[axis] -= extents[axis] * (relative[axis] / extents[axis]).round();
+                        }
+                        let radius_sqr = sim.pair_radius((p.id * sim.id_count + other.id) as usize).powi(2);
+                        let sqr_distance = relative.magnitude2();
+                        if sqr_distance > 0.0 && sqr_distance < radius_sqr {
+                            let distance = sqr_distance.sqrt();
+                            let attraction = sim.pair_attraction(p.id, other.id);
+                            let f = sim.calculate_force(distance, attraction, radius_sqr.sqrt())
+                                * sim.pair_scale(p.id, other.id);
+                            total += relative * (f / distance);
+                        }
+                    }
+                    total * sim.interaction_force * sim.particle_effect_radius
+                })
+                .collect();
+            assert_forces_close(&sim.compute_forces(&particles), &old);
+        }
+    }
+
+    #[test]
+    fn radius_spanning_several_cells_matches_brute_force() {
+        let particles = generate_particles_seeded(10.0, 500, 5, 5);
+        //a radius of 2 over cells of 0.5 has to scan 4 cells each way
+        let sim = Particles::builder()
+            .effect_radius(2.0)
+            .cell_size(Some(0.5))
+            .particles(particles.clone())
+            .build()
+            .unwrap();
+        assert_eq!(sim.cell_search_range(), cgmath::vec3(4, 4, 4));
+        assert_forces_close(&sim.compute_forces(&particles), &brute_force_forces(&sim, &particles));
+    }
+
+    #[test]
+    fn forces_do_not_depend_on_cell_size() {
+        let particles = generate_particles_seeded(10.0, 500, 5, 9);
+        let forces = |cell_size: Option<f32>| {
+            let sim = Particles::builder()
+                .effect_radius(1.5)
+                .cell_size(cell_size)
+                .particles(particles.clone())
+                .build()
+                .unwrap();
+            sim.compute_forces(&particles)
+        };
+        let default = forces(None);
+        for cell_size in [0.4, 0.75, 1.0, 2.5, 6.0] {
+            assert_forces_close(&forces(Some(cell_size)), &default);
+        }
+    }
+
+    #[test]
+    fn double_precision_drifts_less_over_a_long_fall() {
+        //free falls under uniform gravity, which verlet follows exactly without rounding, compared
+        //by energy (unit mass) to the analytic state after many small steps (the stored verlet
+        //velocity is the one from the start of the last step)
+        let (g, ts, steps) = (9.81f32, 0.0002f32, 50_000);
+        let energy = |position: cgmath::Vector3<f64>, velocity: cgmath::Vector3<f64>| {
+            0.5 * velocity.magnitude2() + g as f64 * position.y
+        };
+        let exact_energy = |start: &Particle| {
+            let (t, a) = (ts as f64 * steps as f64, cgmath::vec3(0.0, -g as f64, 0.0));
+            let (position, velocity) = (start.position.cast::<f64>().unwrap(), start.velocity.cast::<f64>().unwrap());
+            energy(position + velocity * t + a * (0.5 * t * t), velocity + a * (t - ts as f64))
+        };
+        let fall = |precision: Precision| {
+            let start: Vec<_> = [(-3.0, 0.0, 1.0), (0.0, 2.5, 0.0), (5.0, -1.0, -2.0)]
+                .into_iter()
+                .map(|(x, vy, vz)| Particle {
+                    position: cgmath::vec3(x, 100.0, 0.0),
+                    velocity: cgmath::vec3(0.3, vy, vz),
+                    id: 0,
+                })
+                .collect();
+            let mut sim = Particles::builder()
+                .world_size(2000.0)
+                .friction(0.0)
+                .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -g, 0.0)))
+                .integrator(Integrator::VelocityVerlet)
+                .precision(precision)
+                .particles(start.clone())
+                .build()
+                .unwrap();
+            for _ in 0..steps {
+                sim.update(ts).unwrap();
+            }
+            let drift: f64 = start
+                .iter()
+                .zip(&sim.active_particles)
+                .map(|(start, p)| {
+                    let stepped = energy(p.position.cast().unwrap(), p.velocity.cast().unwrap());
+                    (stepped - exact_energy(start)).abs()
+                })
+                .sum();
+            (drift, sim.active_particles)
+        };
+        let (single_drift, single) = fall(Precision::Single);
+        let (double_drift, double) = fall(Precision::Double);
+        //the trajectories part ways
+        assert!(single.iter().zip(&double).any(|(a, b)| a.position != b.position));
+        assert!(double_drift < 5e-3, "double precision drifted by {}", double_drift);
+        assert!(double_drift * 4.0 < single_drift, "double {} vs single {}", double_drift, single_drift);
+    }
+
+    #[test]
+    fn deterministic_runs_are_bitwise_identical() {
+        //the same steps on differently sized thread pools, so slots fill in different orders
+        let run = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let mut sim = Particles::builder()
+                    .deterministic(true)
+                    .particles(generate_particles_seeded(10.0, 1000, 5, 11))
+                    .build()
+                    .unwrap();
+                for _ in 0..30 {
+                    sim.update(0.02).unwrap();
+                }
+                state_bits(&sim.active_particles)
+            })
+        };
+        let first = run(8);
+        assert_eq!(first, run(8));
+        assert_eq!(first, run(3));
+        assert_eq!(first, run(1));
+    }
+
+    #[test]
+    fn incremental_hash_matches_full_rebuild() {
+        let run = |incremental_hash: bool| {
+            let mut sim = Particles::builder()
+                .deterministic(true)
+                .particles(generate_particles_seeded(10.0, 400, 5, 3))
+                .build()
+                .unwrap();
+            sim.incremental_hash = incremental_hash;
+            for _ in 0..20 {
+                sim.update(0.02).unwrap();
+            }
+            state_bits(&sim.active_particles)
+        };
+        assert_eq!(run(true), run(false));
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+//float type the cpu solver sums forces and integrates in, particles are always stored as f32
+//(which is what gets drawn and uploaded to the gpu), double precision also keeps its own f64
+//copy of every particle between steps
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    Single,//f32 everywhere, fastest
+    Double,//f64 force sums and integration for long running or stiff simulations
+}
+
+//float types the solver can run in
+//(`as_*` rather than `to_*` so they don't clash with num's `ToPrimitive` the float bound brings in)
+pub(crate) trait SolverFloat: cgmath::BaseFloat + Send + Sync {
+    fn from_f32(value: f32) -> Self;
+    fn from_f64(value: f64) -> Self;
+    fn as_f32(self) -> f32;
+    fn as_f64(self) -> f64;
+}
+
+impl SolverFloat for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn as_f32(self) -> f32 {
+        self
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl SolverFloat for f64 {
+    fn from_f32(value: f32) -> Self {
+        value as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn as_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AttractionMatrix, BoundaryMode, GravityMode, Particles, SimError};
+
+//the tunables worth keeping between runs, without particle positions (see `Scene` for a full
+//snapshot), saved as toml so presets are easy to edit by hand
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SimParams {
+    pub attraction_matrix: AttractionMatrix,
+    pub colors: Vec<[f32; 3]>,
+    pub coefficient: f32,//friction decay rate per second
+    pub interaction_force: f32,
+    pub min_pull_ratio: f32,
+    pub effect_radius: f32,
+    pub boundary: BoundaryMode,//wrap, walls or open
+    pub gravity: GravityMode,
+    pub update_rate: f32,//physics steps per second, kept by the app rather than `Particles`
+}
+
+impl SimParams {
+    //reading a preset from a toml file
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SimError> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    //writing a preset to a toml file
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), SimError> {
+        let text = toml::to_string_pretty(self)?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+impl Particles {
+    //capturing the current tunables as a preset
+    pub fn to_params(&self, update_rate: f32) -> SimParams {
+        SimParams {
+            attraction_matrix: self.attraction_matrix.clone(),
+            colors: self.colors.iter().map(|&c| c.into()).collect(),
+            coefficient: self.coefficient,
+            interaction_force: self.interaction_force,
+            min_pull_ratio: self.min_pull_ratio,
+            effect_radius: self.particle_effect_radius,
+            boundary: self.boundary,
+            gravity: self.gravity,
+            update_rate,
+        }
+    }
+
+    //using a preset's tunables, keeping the particles and type count, a preset made for a different
+    //number of types has its matrix and colors cut down or padded (new pairs neutral, new types
+    //keep their current color)
+    pub fn apply_params(&mut self, params: &SimParams) {
+        let id_count = self.id_count as usize;
+        let mut attraction_matrix = params.attraction_matrix.clone();
+        if attraction_matrix.id_count() != id_count || attraction_matrix.len() != id_count * id_count {
+            attraction_matrix.resize(id_count);
+        }
+        self.attraction_matrix = attraction_matrix;
+        self.colors = (0..id_count)
+            .map(|id| {
+                params
+                    .colors
+                    .get(id)
+                    .map(|&c| c.into())
+                    .or_else(|| self.colors.get(id).copied())
+                    .unwrap_or(cgmath::vec3(1.0, 1.0, 1.0))
+            })
+            .collect();
+        self.coefficient = params.coefficient;
+        self.interaction_force = params.interaction_force;
+        self.min_pull_ratio = params.min_pull_ratio;
+        self.particle_effect_radius = params.effect_radius;
+        self.boundary = params.boundary;
+        self.gravity = params.gravity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_file_round_trips_exactly() {
+        let sim = Particles::builder()
+            .friction(0.55)
+            .boundary(BoundaryMode::Walls)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -9.81, 0.0)))
+            .build()
+            .unwrap();
+        let params = sim.to_params(75.0);
+        let path = std::env::temp_dir().join(format!("preset_round_trip_{}.toml", std::process::id()));
+        params.save(&path).unwrap();
+        let loaded = SimParams::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), params);
+    }
+}
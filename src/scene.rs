@@ -0,0 +1,525 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{BoundaryMode, Dim, FlowField, GravityMode, Integrator, Particle, Particles, Precision, SimError};
+
+//single particle as stored in a scene file
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SceneParticle {
+    pub position: [f32; 3],//exact starting position
+    pub velocity: [f32; 3],//exact starting velocity
+    pub id: u32,//particle type
+}
+
+//everything needed to rebuild a simulation exactly, including particle positions
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Scene {
+    pub world_size: f32,
+    pub world_aspect: [f32; 3],
+    pub particles: Vec<SceneParticle>,
+    pub id_count: u32,
+    pub attraction_matrix: Vec<f32>,
+    pub attraction_range: (f32, f32),
+    pub colors: Vec<[f32; 3]>,
+    pub coefficient: f32,
+    pub friction_scale: Vec<f32>,
+    pub interaction_force: f32,
+    pub pair_force_scale: Vec<f32>,
+    pub symmetric_forces: bool,
+    pub min_pull_ratio: f32,
+    pub particle_effect_radius: f32,
+    pub radius_matrix: Vec<f32>,
+    pub hard_radius: f32,
+    pub hard_iterations: usize,
+    pub boundary: BoundaryMode,
+    pub restitution: f32,
+    pub gravity: GravityMode,
+    pub gravity_scale: Vec<f32>,
+    pub masses: Vec<f32>,
+    pub flow_field: Option<FlowField>,
+    pub max_speed: Option<f32>,
+    pub lock_y: bool,
+    pub dimensions: Dim,
+    pub deterministic: bool,
+    pub precision: Precision,
+    pub integrator: Integrator,
+    pub force_curve: Vec<(f32, f32)>,
+    pub cell_size: Option<f32>,
+    #[serde(default)]
+    pub focus_point: Option<[f32; 3]>,//where the focus pull was aimed when saved
+    #[serde(default = "default_focus_strength")]
+    pub focus_strength: f32,
+    //solver state carried from step to step, so a loaded simulation steps on exactly like the one
+    //that was saved (hand written scenes leave them out and start fresh)
+    #[serde(default)]
+    pub sim_time: f32,
+    #[serde(default)]
+    pub previous_accelerations: Vec<[f32; 3]>,//velocity verlet history
+    #[serde(default)]
+    pub double_state: Vec<[[f64; 3]; 2]>,//f64 positions and velocities of double precision
+}
+
+//same as the builder, for scenes written before the focus strength was saved
+fn default_focus_strength() -> f32 {
+    1.0
+}
+
+//a scene plus the metadata needed to reproduce a run exactly, attached to bug reports
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DebugBundle {
+    pub app_version: String,//crate version that wrote the bundle
+    pub seed: Option<u64>,//rng seed the particles were generated from, if known
+    pub tick: u64,//physics steps taken so far
+    pub sim_time: f32,//simulated seconds so far
+    pub scene: Scene,//all parameters and particle states
+}
+
+impl DebugBundle {
+    //reading a bundle from a json file, checking its scene like `Scene::load`
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SimError> {
+        let text = std::fs::read_to_string(path)?;
+        let bundle: Self = serde_json::from_str(&text)?;
+        bundle.scene.validate()?;
+        Ok(bundle)
+    }
+
+    //writing the bundle to a single json file
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), SimError> {
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    //rebuilding the simulation the bundle was captured from
+    pub fn to_particles(&self) -> Result<Particles, SimError> {
+        let mut particles = Particles::from_scene(&self.scene)?;
+        particles.sim_time = self.sim_time;
+        Ok(particles)
+    }
+}
+
+//checking that every per type table is sized for `id_count` types, naming the expected and
+//actual length of the first one that isn't
+pub(crate) fn check_type_tables(
+    id_count: usize,
+    attraction_matrix: usize,
+    pair_force_scale: usize,
+    colors: usize,
+    gravity_scale: usize,
+    friction_scale: usize,
+) -> Result<(), SimError> {
+    let tables = [
+        ("attraction matrix", attraction_matrix, id_count * id_count),
+        ("pair force scale", pair_force_scale, id_count * id_count),
+        ("colors", colors, id_count),
+        ("gravity scale", gravity_scale, id_count),
+        ("friction scale", friction_scale, id_count),
+    ];
+    for (name, actual, expected) in tables {
+        if actual != expected {
+            return Err(SimError::SizeMismatch {
+                table: name,
+                actual,
+                expected,
+                id_count,
+            });
+        }
+    }
+    Ok(())
+}
+
+//checking every per type mass is positive (the table may be shorter than the type count)
+pub(crate) fn check_masses(masses: &[f32]) -> Result<(), SimError> {
+    match masses.iter().position(|&mass| !(mass > 0.0 && mass.is_finite())) {
+        Some(id) => Err(SimError::InvalidParameter {
+            name: "mass",
+            reason: format!("type {} has mass {}, masses must be positive", id, masses[id]),
+        }),
+        None => Ok(()),
+    }
+}
+
+//checking the per pair radii are either left out or given for every type pair
+pub(crate) fn check_radius_matrix(len: usize, id_count: usize) -> Result<(), SimError> {
+    if len == 0 || len == id_count * id_count {
+        Ok(())
+    } else {
+        Err(SimError::SizeMismatch {
+            table: "radius matrix",
+            actual: len,
+            expected: id_count * id_count,
+            id_count,
+        })
+    }
+}
+
+//checking every side of the world box is a real length and the force cutoff fits inside the
+//shortest one
+pub(crate) fn check_world_size(extents: cgmath::Vector3<f32>, effect_radius: f32) -> Result<(), SimError> {
+    if !(extents.x > 0.0 && extents.y > 0.0 && extents.z > 0.0) {
+        return Err(SimError::InvalidParameter {
+            name: "world extents",
+            reason: format!("{:?} must be positive on every axis", extents),
+        });
+    }
+    let world_size = extents.x.min(extents.y).min(extents.z);
+    if world_size >= 2.0 * effect_radius {
+        Ok(())
+    } else {
+        Err(SimError::WorldTooSmall {
+            world_size,
+            effect_radius,
+        })
+    }
+}
+
+impl Scene {
+    //checking that the scene's parts agree with each other before using it
+    pub fn validate(&self) -> Result<(), SimError> {
+        let id_count = self.id_count as usize;
+        check_type_tables(
+            id_count,
+            self.attraction_matrix.len(),
+            self.pair_force_scale.len(),
+            self.colors.len(),
+            self.gravity_scale.len(),
+            self.friction_scale.len(),
+        )?;
+        if let Some(particle) = self.particles.iter().find(|p| p.id >= self.id_count) {
+            return Err(SimError::InvalidParameter {
+                name: "particle type",
+                reason: format!("{} is out of range for {} types", particle.id, id_count),
+            });
+        }
+        if let Some(index) = self.particles.iter().position(|p| {
+            !p.position.iter().chain(&p.velocity).all(|v| v.is_finite())
+        }) {
+            return Err(SimError::NonFiniteState { index });
+        }
+        check_masses(&self.masses)?;
+        check_radius_matrix(self.radius_matrix.len(), id_count)?;
+        check_world_size(
+            cgmath::Vector3::from(self.world_aspect) * self.world_size,
+            crate::largest_radius(&self.radius_matrix, self.particle_effect_radius),
+        )
+    }
+
+    //reading a scene from a json file
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SimError> {
+        let text = std::fs::read_to_string(path)?;
+        let scene: Self = serde_json::from_str(&text)?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    //writing a scene to a json file
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), SimError> {
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+impl Particles {
+    //building a simulation from a scene after checking it's consistent
+    pub fn from_scene(scene: &Scene) -> Result<Self, SimError> {
+        scene.validate()?;
+        Ok(Self {
+            world_size: scene.world_size,
+            world_aspect: scene.world_aspect.into(),
+            active_particles: scene
+                .particles
+                .iter()
+                .map(|p| Particle {
+                    position: p.position.into(),
+                    velocity: p.velocity.into(),
+                    id: p.id,
+                })
+                .collect(),
+            past_particles: vec![],
+            id_count: scene.id_count,
+            attraction_matrix: scene.attraction_matrix.clone().into(),
+            attraction_range: scene.attraction_range,
+            colors: scene.colors.iter().map(|&c| c.into()).collect(),
+            coefficient: scene.coefficient,
+            friction_scale: scene.friction_scale.clone(),
+            interaction_force: scene.interaction_force,
+            pair_force_scale: scene.pair_force_scale.clone(),
+            symmetric_forces: scene.symmetric_forces,
+            min_pull_ratio: scene.min_pull_ratio,
+            particle_effect_radius: scene.particle_effect_radius,
+            radius_matrix: scene.radius_matrix.clone(),
+            hard_radius: scene.hard_radius,
+            hard_iterations: scene.hard_iterations,
+            boundary: scene.boundary,
+            restitution: scene.restitution,
+            gravity: scene.gravity,
+            gravity_scale: scene.gravity_scale.clone(),
+            masses: scene.masses.clone(),
+            flow_field: scene.flow_field,
+            max_speed: scene.max_speed,
+            lock_y: scene.lock_y,
+            dimensions: scene.dimensions,
+            focus_point: scene.focus_point.map(cgmath::Vector3::from),
+            focus_strength: scene.focus_strength,
+            deterministic: scene.deterministic,
+            precision: scene.precision,
+            integrator: scene.integrator,
+            previous_accelerations: scene.previous_accelerations.iter().map(|&a| a.into()).collect(),
+            double_state: scene
+                .double_state
+                .iter()
+                .map(|state| state.map(cgmath::Vector3::from))
+                .collect(),
+            force_curve: scene.force_curve.clone(),
+            incremental_hash: true,
+            cell_size: scene.cell_size,
+            hash_cache: Default::default(),
+            sim_time: scene.sim_time,
+            collect_stats: false,
+            step_stats: Default::default(),
+            energy_absorbed_by_walls: 0.0,
+            wrapped: vec![],
+            on_step: None,
+            post_step: None,
+        })
+    }
+
+    //writing the full simulation (parameters and every particle) as scene json, e.g. to a file
+    //or network stream, positions and velocities read back bit for bit
+    pub fn save_to_writer(&self, writer: impl std::io::Write) -> Result<(), SimError> {
+        serde_json::to_writer_pretty(writer, &self.to_scene())?;
+        Ok(())
+    }
+
+    //writing every particle as a csv row `id,x,y,z,vx,vy,vz` (with that header) for offline
+    //analysis, rows are streamed through a buffer instead of built up in memory
+    pub fn export_csv(&self, writer: impl std::io::Write) -> Result<(), SimError> {
+        use std::io::Write;
+        let mut writer = std::io::BufWriter::new(writer);
+        writeln!(writer, "id,x,y,z,vx,vy,vz")?;
+        for particle in &self.active_particles {
+            let (p, v) = (particle.position, particle.velocity);
+            writeln!(writer, "{},{},{},{},{},{},{}", particle.id, p.x, p.y, p.z, v.x, v.y, v.z)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    //reading a simulation written by `save_to_writer` (or a scene file), checking it like
+    //`from_scene`
+    pub fn load_from_reader(reader: impl std::io::Read) -> Result<Particles, SimError> {
+        let scene: Scene = serde_json::from_reader(reader)?;
+        Self::from_scene(&scene)
+    }
+
+    //capturing the current simulation and run metadata for a bug report
+    pub fn debug_bundle(&self, tick: u64, seed: Option<u64>) -> DebugBundle {
+        DebugBundle {
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            seed,
+            tick,
+            sim_time: self.sim_time,
+            scene: self.to_scene(),
+        }
+    }
+
+    //capturing the current simulation as a scene
+    pub fn to_scene(&self) -> Scene {
+        Scene {
+            world_size: self.world_size,
+            world_aspect: self.world_aspect.into(),
+            particles: self
+                .active_particles
+                .iter()
+                .map(|p| SceneParticle {
+                    position: p.position.into(),
+                    velocity: p.velocity.into(),
+                    id: p.id,
+                })
+                .collect(),
+            id_count: self.id_count,
+            attraction_matrix: self.attraction_matrix.values().to_vec(),
+            attraction_range: self.attraction_range,
+            colors: self.colors.iter().map(|&c| c.into()).collect(),
+            coefficient: self.coefficient,
+            friction_scale: self.friction_scale.clone(),
+            interaction_force: self.interaction_force,
+            pair_force_scale: self.pair_force_scale.clone(),
+            symmetric_forces: self.symmetric_forces,
+            min_pull_ratio: self.min_pull_ratio,
+            particle_effect_radius: self.particle_effect_radius,
+            radius_matrix: self.radius_matrix.clone(),
+            hard_radius: self.hard_radius,
+            hard_iterations: self.hard_iterations,
+            boundary: self.boundary,
+            restitution: self.restitution,
+            gravity: self.gravity,
+            gravity_scale: self.gravity_scale.clone(),
+            masses: self.masses.clone(),
+            flow_field: self.flow_field,
+            max_speed: self.max_speed,
+            lock_y: self.lock_y,
+            dimensions: self.dimensions,
+            deterministic: self.deterministic,
+            precision: self.precision,
+            integrator: self.integrator,
+            force_curve: self.force_curve.clone(),
+            cell_size: self.cell_size,
+            focus_point: self.focus_point.map(Into::into),
+            focus_strength: self.focus_strength,
+            sim_time: self.sim_time,
+            previous_accelerations: self.previous_accelerations.iter().map(|&a| a.into()).collect(),
+            double_state: self.double_state.iter().map(|state| state.map(Into::into)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_particles_seeded;
+
+    //a simulation with most settings away from their defaults
+    fn tuned() -> Particles {
+        Particles::builder()
+            .world_size(12.0)
+            .boundary(BoundaryMode::Walls)
+            .restitution(0.5)
+            .friction(0.8)
+            .gravity(GravityMode::Point { center: cgmath::vec3(1.0, 0.0, -1.0), strength: 0.3 })
+            .flow_field(Some(FlowField::Vortex { axis: [0.0, 1.0, 0.0], strength: 0.2 }))
+            .max_speed(Some(4.0))
+            .symmetric_forces(true)
+            .precision(Precision::Double)
+            .deterministic(true)
+            .particles(generate_particles_seeded(12.0, 300, 5, 21))
+            .build()
+            .map(|mut sim| {
+                sim.focus_point = Some(cgmath::vec3(2.0, 1.0, 0.0));
+                sim.focus_strength = 0.4;
+                sim
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn saved_simulation_loads_and_steps_identically() {
+        let bits = |particles: &[Particle]| {
+            particles
+                .iter()
+                .map(|p| (p.position.map(f32::to_bits), p.velocity.map(f32::to_bits), p.id))
+                .collect::<Vec<_>>()
+        };
+        //verlet also needs its acceleration history and double precision its f64 state back
+        for integrator in [Integrator::SemiImplicitEuler, Integrator::VelocityVerlet] {
+            let mut sim = tuned();
+            sim.integrator = integrator;
+            for _ in 0..5 {
+                sim.update(0.02).unwrap();
+            }
+            let mut json = Vec::new();
+            sim.save_to_writer(&mut json).unwrap();
+            let mut loaded = Particles::load_from_reader(json.as_slice()).unwrap();
+
+            assert_eq!(bits(&loaded.active_particles), bits(&sim.active_particles));
+            assert_eq!(loaded.sim_time, sim.sim_time);
+            assert_eq!(loaded.previous_accelerations, sim.previous_accelerations);
+            assert_eq!(loaded.double_state, sim.double_state);
+            assert_eq!((loaded.focus_point, loaded.focus_strength), (sim.focus_point, sim.focus_strength));
+            for _ in 0..3 {
+                sim.update(0.02).unwrap();
+                loaded.update(0.02).unwrap();
+                assert_eq!(bits(&loaded.active_particles), bits(&sim.active_particles), "{:?}", integrator);
+            }
+        }
+    }
+
+    #[test]
+    fn scene_file_round_trips_exactly() {
+        let scene = Scene {
+            radius_matrix: vec![1.0, 1.5, 1.5, 2.0],
+            force_curve: vec![(0.0, -1.0), (0.5, 1.0), (1.0, 0.0)],
+            cell_size: Some(0.75),
+            integrator: Integrator::VelocityVerlet,
+            dimensions: Dim::Two,
+            lock_y: true,
+            hard_radius: 0.1,
+            ..Particles::builder()
+                .id_count(2)
+                .colors(vec![[1.0, 0.0, 0.0].into(), [0.0, 0.5, 1.0].into()])
+                .attraction_matrix(vec![0.1, -0.7, 0.3, 1.0])
+                .particles(generate_particles_seeded(10.0, 50, 2, 4))
+                .build()
+                .unwrap()
+                .to_scene()
+        };
+        let path = std::env::temp_dir().join(format!("scene_round_trip_{}.json", std::process::id()));
+        scene.save(&path).unwrap();
+        let loaded = Scene::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, scene);
+        assert_eq!(Particles::from_scene(&loaded).unwrap().to_scene(), scene);
+    }
+
+    #[test]
+    fn debug_bundle_round_trips_and_reproduces_the_run() {
+        let mut sim = tuned();
+        for _ in 0..4 {
+            sim.update(0.02).unwrap();
+        }
+        let bundle = sim.debug_bundle(4, Some(21));
+        assert_eq!((bundle.tick, bundle.seed, bundle.sim_time), (4, Some(21), sim.sim_time));
+        assert_eq!(bundle.app_version, env!("CARGO_PKG_VERSION"));
+
+        let path = std::env::temp_dir().join(format!("debug_bundle_{}.json", std::process::id()));
+        bundle.save(&path).unwrap();
+        let loaded = DebugBundle::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, bundle);
+
+        let mut reproduced = loaded.to_particles().unwrap();
+        assert_eq!(reproduced.sim_time, sim.sim_time);
+        for _ in 0..3 {
+            sim.update(0.02).unwrap();
+            reproduced.update(0.02).unwrap();
+        }
+        assert_eq!(reproduced.to_scene(), sim.to_scene());
+    }
+
+    #[test]
+    fn csv_export_has_a_header_and_rows_that_parse_back() {
+        let sim = tuned();
+        let mut csv = Vec::new();
+        sim.export_csv(&mut csv).unwrap();
+        let text = String::from_utf8(csv).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("id,x,y,z,vx,vy,vz"));
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows.len(), sim.active_particles.len());
+        for (row, particle) in rows.iter().zip(&sim.active_particles) {
+            let fields: Vec<&str> = row.split(',').collect();
+            assert_eq!(fields.len(), 7);
+            assert_eq!(fields[0].parse::<u32>().unwrap(), particle.id);
+            let values: Vec<f32> = fields[1..].iter().map(|field| field.parse().unwrap()).collect();
+            let (p, v) = (particle.position, particle.velocity);
+            //rust prints the shortest text that parses back to the same float
+            assert_eq!(values, [p.x, p.y, p.z, v.x, v.y, v.z]);
+        }
+    }
+
+    #[test]
+    fn scenes_without_focus_settings_get_the_builder_defaults() {
+        let mut json = serde_json::to_value(tuned().to_scene()).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("focus_point");
+        fields.remove("focus_strength");
+        let scene: Scene = serde_json::from_value(json).unwrap();
+
+        let loaded = Particles::from_scene(&scene).unwrap();
+        let built = Particles::builder().build().unwrap();
+        assert_eq!(loaded.focus_point, built.focus_point);
+        assert_eq!(loaded.focus_strength, built.focus_strength);
+    }
+}
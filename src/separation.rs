@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::prelude::*;
+use rayon::prelude::*;
+
+use crate::{BoundaryMode, Dim, Particles};
+
+impl Particles {
+    //hard-core constraint: pushing apart particles closer than hard_radius after integration,
+    //every pass moves both particles of an overlapping pair half the overlap away from each other
+    //(more passes settle dense packings where fixing one overlap creates another)
+    //returns how many pushes took a particle outside the box and the kinetic energy the walls
+    //took from them, pushes that wrap are flagged in `wrapped` like the step's own wraps
+    pub(crate) fn enforce_min_separation(&mut self) -> (usize, f32) {
+        let radius = self.hard_radius;
+        if radius <= 0.0 || self.active_particles.len() < 2 {
+            return (0, 0.0);
+        }
+
+        //grid of at least `radius` sized cells covering the world, so overlaps are always in
+        //neighboring cells
+        let extents = self.world_extents();
+        let cells_per_axis = extents.map(|extent| ((extent / radius).floor() as isize).max(1));
+        let cell_of = |axis: usize, v: f32| {
+            let cell_size = extents[axis] / cells_per_axis[axis] as f32;
+            (((v + extents[axis] * 0.5) / cell_size).floor() as isize).clamp(0, cells_per_axis[axis] - 1)
+        };
+        let wrap = self.boundary == BoundaryMode::Wrap;
+        let mut boundary_events = 0;
+        let mut energy_absorbed = 0.0;
+
+        for _ in 0..self.hard_iterations.max(1) {
+            let mut grid: HashMap<(isize, isize, isize), Vec<usize>> = HashMap::new();
+            for (i, particle) in self.active_particles.iter().enumerate() {
+                let p = particle.position;
+                grid.entry((cell_of(0, p.x), cell_of(1, p.y), cell_of(2, p.z)))
+                    .or_default()
+                    .push(i);
+            }
+
+            let particles = &self.active_particles;
+            let corrections: Vec<cgmath::Vector3<f32>> = particles
+                .par_iter()
+                .enumerate()
+                .map(|(i, particle)| {
+                    let p = particle.position;
+                    let cell = (cell_of(0, p.x), cell_of(1, p.y), cell_of(2, p.z));
+
+                    //collecting distinct neighbor cells (small grids would otherwise visit a cell twice)
+                    let mut neighbor_cells = HashSet::new();
+                    for dx in -1..=1 {
+                        for dy in -1..=1 {
+                            for dz in -1..=1 {
+                                let wrap_cell = |axis: usize, c: isize| {
+                                    if wrap {
+                                        Some(c.rem_euclid(cells_per_axis[axis]))
+                                    } else {
+                                        Some(c).filter(|&c| (0..cells_per_axis[axis]).contains(&c))
+                                    }
+                                };
+                                if let (Some(x), Some(y), Some(z)) =
+                                    (wrap_cell(0, cell.0 + dx), wrap_cell(1, cell.1 + dy), wrap_cell(2, cell.2 + dz))
+                                {
+                                    neighbor_cells.insert((x, y, z));
+                                }
+                            }
+                        }
+                    }
+
+                    let mut correction = cgmath::Vector3::zero();
+                    for &j in neighbor_cells.iter().filter_map(|cell| grid.get(cell)).flatten() {
+                        if j == i {
+                            continue;
+                        }
+                        let mut delta = particles[j].position - p;
+                        //taking the shortest way around when the world wraps
+                        if wrap {
+                            for axis in 0..3 {
+                                delta[axis] -= extents[axis] * (delta[axis] / extents[axis]).round();
+                            }
+                        }
+                        let sqr_distance = delta.magnitude2();
+                        //exactly coincident particles have no direction to separate along
+                        if sqr_distance > 0.0 && sqr_distance < radius * radius {
+                            let distance = sqr_distance.sqrt();
+                            correction -= delta / distance * (radius - distance) * 0.5;
+                        }
+                    }
+                    correction
+                })
+                .collect();
+
+            //moving particles and keeping them inside the box
+            let mut particles = std::mem::take(&mut self.active_particles);
+            for (index, (particle, mut correction)) in particles.iter_mut().zip(corrections).enumerate() {
+                //pushing apart only within the xz plane when y is locked
+                if self.lock_y {
+                    correction.y = 0.0;
+                }
+                if self.dimensions == Dim::Two {
+                    correction.z = 0.0;
+                }
+                if correction != cgmath::Vector3::zero() {
+                    particle.position += correction;
+                    let (outside, absorbed) = self.handle_wall_collision(particle);
+                    if outside {
+                        boundary_events += 1;
+                        if let (true, Some(wrapped)) = (wrap, self.wrapped.get_mut(index)) {
+                            *wrapped = true;
+                        }
+                    }
+                    energy_absorbed += absorbed;
+                }
+            }
+            self.active_particles = particles;
+        }
+        (boundary_events, energy_absorbed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Particle;
+
+    //two types that don't interact, two particles 0.1 apart along x next to the +x face
+    fn pushed_into_the_face(boundary: BoundaryMode, hard_radius: f32) -> Particles {
+        let mut sim = Particles::builder()
+            .world_size(10.0)
+            .id_count(2)
+            .colors(vec![cgmath::vec3(1.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, 1.0)])
+            .attraction_matrix(vec![0.0; 4])
+            .boundary(boundary)
+            .hard_radius(hard_radius, 1)
+            .build()
+            .unwrap();
+        let particle = |x: f32| Particle {
+            position: cgmath::vec3(x, 0.0, 0.0),
+            velocity: cgmath::vec3(1.0, 0.0, 0.0),
+            id: 0,
+        };
+        sim.active_particles = vec![particle(4.7), particle(4.8)];
+        sim.update(0.01).unwrap();
+        sim
+    }
+
+    #[test]
+    fn separation_through_a_wall_counts_the_absorbed_energy() {
+        assert_eq!(pushed_into_the_face(BoundaryMode::Walls, 0.0).energy_absorbed_by_walls, 0.0);
+        let sim = pushed_into_the_face(BoundaryMode::Walls, 0.5);
+        //the outer particle lands on the wall and loses its speed into it
+        assert_eq!(sim.active_particles[1].position.x, 5.0);
+        assert!(sim.energy_absorbed_by_walls > 0.4, "{}", sim.energy_absorbed_by_walls);
+    }
+
+    #[test]
+    fn separation_across_the_edge_flags_the_wrap() {
+        assert_eq!(pushed_into_the_face(BoundaryMode::Wrap, 0.0).wrapped, vec![false, false]);
+        let sim = pushed_into_the_face(BoundaryMode::Wrap, 0.5);
+        assert!(sim.active_particles[1].position.x < 0.0);
+        assert_eq!(sim.wrapped, vec![false, true]);
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+use rayon::prelude::*;
+
+use crate::Particle;
+
+//particle indices grouped by grid cell: every particle sits in one slot of a hash table with as
+//many slots as particles, and each slot's particles are stored next to each other
+#[derive(Default)]
+pub struct SpatialHash {
+    starts: Vec<usize>,//slot i holds indices[starts[i]..starts[i + 1]], one extra entry at the end
+    indices: Vec<usize>,//particle indices ordered by slot
+    cells: Vec<cgmath::Vector3<isize>>,//grid cell of every particle
+    sorted: bool,//every slot is in particle index order
+    search_range: cgmath::Vector3<isize>,//cells `neighbors` scans each way along every axis
+}
+
+//converting a 3D grid cell into a single number for the hash table
+pub(crate) fn hash_cell(cell: cgmath::Vector3<isize>) -> usize {
+    let mut hasher = DefaultHasher::new();
+    cell.x.hash(&mut hasher);
+    cell.y.hash(&mut hasher);
+    cell.z.hash(&mut hasher);
+    hasher.finish() as usize
+}
+
+impl SpatialHash {
+    //grouping particles into cubic cells of `cell_size` covering a world of `world_size` centered
+    //on the origin (as many whole cells as fit per axis, particles outside go to the edge cells)
+    pub fn build(particles: &[Particle], cell_size: f32, world_size: f32) -> Self {
+        if particles.is_empty() {
+            return Self::from_cells(Vec::new(), false);
+        }
+        let cells_per_axis = ((world_size / cell_size).floor() as isize).max(1);
+        let cell_size = world_size / cells_per_axis as f32;
+        let half_world = world_size * 0.5;
+        let cells: Vec<_> = particles
+            .par_iter()
+            .map(|particle| {
+                particle
+                    .position
+                    .map(|c| (((c + half_world) / cell_size).floor() as isize).clamp(0, cells_per_axis - 1))
+            })
+            .collect();
+        Self::from_cells(cells, false)
+    }
+
+    //grouping particles given each one's grid cell, `sorted` orders every slot by particle index
+    //so anything summing over a slot gets the same order every run
+    pub(crate) fn from_cells(cells: Vec<cgmath::Vector3<isize>>, sorted: bool) -> Self {
+        let table_length = cells.len();
+        let buckets: Vec<usize> = cells
+            .par_iter()
+            .map(|&cell| hash_cell(cell) % table_length)
+            .collect();
+        Self::from_buckets(cells, &buckets, sorted)
+    }
+
+    //grouping particles given each one's grid cell and hash table slot (`hash_cell(cell)` modulo
+    //the particle count)
+    fn from_buckets(cells: Vec<cgmath::Vector3<isize>>, buckets: &[usize], sorted: bool) -> Self {
+        let table_length = buckets.len();
+        let slot_ends: Vec<_> = std::iter::repeat_with(|| AtomicUsize::new(0))
+            .take(table_length + 1)
+            .collect();
+
+        //parallely counting how many particles are in each slot
+        buckets.par_iter().for_each(|&slot| {
+            slot_ends[slot].fetch_add(1, Relaxed);
+        });
+
+        //converting counts to running totals, each entry is now the end of its slot's range
+        for i in 1..slot_ends.len() {
+            slot_ends[i].fetch_add(slot_ends[i - 1].load(Relaxed), Relaxed);
+        }
+
+        //filling every slot from its end, which leaves each entry at the start of its slot
+        let indices: Vec<_> = std::iter::repeat_with(|| AtomicUsize::new(0))
+            .take(table_length)
+            .collect();
+        buckets.par_iter().enumerate().for_each(|(i, &slot)| {
+            let end = slot_ends[slot].fetch_sub(1, Relaxed);
+            indices[end - 1].store(i, Relaxed);
+        });
+
+        let starts: Vec<usize> = slot_ends.into_iter().map(AtomicUsize::into_inner).collect();
+        let mut indices: Vec<usize> = indices.into_iter().map(AtomicUsize::into_inner).collect();
+        if sorted {
+            for slot in 0..table_length {
+                indices[starts[slot]..starts[slot + 1]].sort_unstable();
+            }
+        }
+        Self { starts, indices, cells, sorted, search_range: cgmath::vec3(1, 1, 1) }
+    }
+
+    //the same grouping as `from_cells(cells, sorted)`, but only moving the particles whose cell
+    //changed since this hash was built to their new slots, everything else stays where it is
+    //(rebuilds from scratch when the particle count or order option changed or most particles moved)
+    pub(crate) fn rebucket(self, cells: Vec<cgmath::Vector3<isize>>, sorted: bool) -> Self {
+        if cells.len() != self.cells.len() || sorted != self.sorted {
+            return Self::from_cells(cells, sorted);
+        }
+        let moved: Vec<usize> = cells
+            .par_iter()
+            .zip(self.cells.par_iter())
+            .enumerate()
+            .filter(|(_, (cell, last_cell))| cell != last_cell)
+            .map(|(index, _)| index)
+            .collect();
+        if moved.is_empty() {
+            return self;
+        }
+        if moved.len() > cells.len() / 2 {
+            return Self::from_cells(cells, sorted);
+        }
+
+        //new slot of every particle that moved, grouped by slot
+        let table_length = cells.len();
+        let mut arrivals: Vec<(usize, usize)> = moved
+            .iter()
+            .map(|&index| (hash_cell(cells[index]) % table_length, index))
+            .collect();
+        arrivals.sort_unstable();
+        let mut leaving = vec![false; table_length];
+        for &index in &moved {
+            leaving[index] = true;
+        }
+
+        //copying every slot without the particles that left it and adding the ones that arrived
+        let mut starts = Vec::with_capacity(table_length + 1);
+        let mut indices = Vec::with_capacity(table_length);
+        let mut arrivals = arrivals.into_iter().peekable();
+        for slot in 0..table_length {
+            let start = indices.len();
+            starts.push(start);
+            indices.extend(
+                self.indices[self.starts[slot]..self.starts[slot + 1]]
+                    .iter()
+                    .copied()
+                    .filter(|&index| !leaving[index]),
+            );
+            let mut arrived = false;
+            while let Some((_, index)) = arrivals.next_if(|&(arrival_slot, _)| arrival_slot == slot) {
+                indices.push(index);
+                arrived = true;
+            }
+            if sorted && arrived {
+                indices[start..].sort_unstable();
+            }
+        }
+        starts.push(indices.len());
+        Self { starts, indices, cells, sorted, search_range: self.search_range }
+    }
+
+    //grid cell of the particle at `index`
+    pub fn cell_of(&self, index: usize) -> cgmath::Vector3<isize> {
+        self.cells[index]
+    }
+
+    //indices of the particles in exactly this cell (other cells sharing the slot are skipped)
+    pub fn particles_in_cell(&self, cell: cgmath::Vector3<isize>) -> impl Iterator<Item = usize> + '_ {
+        let slot_range = if self.indices.is_empty() {
+            0..0
+        } else {
+            let slot = hash_cell(cell) % self.indices.len();
+            self.starts[slot]..self.starts[slot + 1]
+        };
+        self.indices[slot_range]
+            .iter()
+            .copied()
+            .filter(move |&index| self.cells[index] == cell)
+    }
+
+    //scanning `search_range` cells each way in `neighbors` instead of one, for cells smaller than
+    //the radius being searched (cells are not wrapped around the world edges)
+    pub fn with_search_range(mut self, search_range: cgmath::Vector3<isize>) -> Self {
+        self.search_range = search_range;
+        self
+    }
+
+    //indices of the particles in this cell and the cells within the search range around it (the
+    //26 adjacent ones unless set with `with_search_range`), cell by cell
+    pub fn neighbors(&self, cell: cgmath::Vector3<isize>) -> impl Iterator<Item = usize> + '_ {
+        let range = self.search_range;
+        (-range.x..=range.x)
+            .flat_map(move |dx| {
+                (-range.y..=range.y).flat_map(move |dy| (-range.z..=range.z).map(move |dz| cgmath::vec3(dx, dy, dz)))
+            })
+            .flat_map(move |offset| self.particles_in_cell(cell + offset))
+    }
+
+    //cell holding the most particles and how many it holds, counted slot by slot (a slot can
+    //hold several cells, those are told apart), ties go to the smallest cell coordinates
+    pub fn densest_cell(&self) -> Option<(cgmath::Vector3<isize>, usize)> {
+        self.starts
+            .par_windows(2)
+            .filter_map(|slot| {
+                let mut cells: Vec<_> = self.indices[slot[0]..slot[1]]
+                    .iter()
+                    .map(|&index| {
+                        let cell = self.cells[index];
+                        (cell.x, cell.y, cell.z)
+                    })
+                    .collect();
+                cells.sort_unstable();
+                //runs of the same cell, keeping the first longest one
+                let mut densest: Option<((isize, isize, isize), usize)> = None;
+                for run in cells.chunk_by(|a, b| a == b) {
+                    if densest.is_none_or(|(_, count)| run.len() > count) {
+                        densest = Some((run[0], run.len()));
+                    }
+                }
+                densest
+            })
+            .max_by(|(a_cell, a_count), (b_cell, b_count)| a_count.cmp(b_count).then(b_cell.cmp(a_cell)))
+            .map(|((x, y, z), count)| (cgmath::vec3(x, y, z), count))
+    }
+
+    //number of particles that were grouped
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //particles of every cell in the block around the origin, sorted so groupings compare equal
+    fn grouping(hash: &SpatialHash) -> Vec<Vec<usize>> {
+        let mut groups = Vec::new();
+        for x in -4..=4 {
+            for y in -4..=4 {
+                for z in -4..=4 {
+                    let mut group: Vec<_> = hash.particles_in_cell(cgmath::vec3(x, y, z)).collect();
+                    group.sort_unstable();
+                    groups.push(group);
+                }
+            }
+        }
+        groups
+    }
+
+    //a deterministic scatter of cells in the -4..=4 block
+    fn cells(count: usize, salt: isize) -> Vec<cgmath::Vector3<isize>> {
+        (0..count as isize)
+            .map(|i| cgmath::vec3((i * 7 + salt) % 9 - 4, (i * 5) % 9 - 4, (i * 3 + salt * 2) % 9 - 4))
+            .collect()
+    }
+
+    #[test]
+    fn rebucket_matches_full_rebuild() {
+        let before = cells(500, 0);
+        //a few particles change cell, the rest stay
+        let mut after = before.clone();
+        for i in (0..after.len()).step_by(17) {
+            after[i] = cgmath::vec3(after[i].y, after[i].z, after[i].x);
+        }
+        for sorted in [false, true] {
+            let patched = SpatialHash::from_cells(before.clone(), sorted).rebucket(after.clone(), sorted);
+            let rebuilt = SpatialHash::from_cells(after.clone(), sorted);
+            assert_eq!(grouping(&patched), grouping(&rebuilt));
+            assert_eq!(patched.len(), rebuilt.len());
+            assert_eq!(patched.starts, rebuilt.starts);
+            if sorted {
+                assert_eq!(patched.indices, rebuilt.indices);
+            }
+        }
+    }
+
+    #[test]
+    fn rebucket_falls_back_when_most_particles_moved() {
+        let before = cells(200, 0);
+        let after = cells(200, 3);
+        let patched = SpatialHash::from_cells(before, false).rebucket(after.clone(), false);
+        assert_eq!(grouping(&patched), grouping(&SpatialHash::from_cells(after, false)));
+    }
+
+    fn particle_at(x: f32, y: f32, z: f32) -> Particle {
+        Particle { position: cgmath::vec3(x, y, z), velocity: cgmath::vec3(0.0, 0.0, 0.0), id: 0 }
+    }
+
+    #[test]
+    fn slots_partition_every_particle() {
+        let particles = crate::generate_particles_seeded(10.0, 400, 3, 7);
+        let hash = SpatialHash::build(&particles, 1.0, 10.0);
+        assert_eq!(hash.len(), particles.len());
+        assert_eq!(hash.starts.len(), particles.len() + 1);
+        assert_eq!(hash.starts[0], 0);
+        assert_eq!(*hash.starts.last().unwrap(), particles.len());
+        assert!(hash.starts.windows(2).all(|pair| pair[0] <= pair[1]));
+        //every index in exactly one slot
+        let mut indices = hash.indices.clone();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..particles.len()).collect::<Vec<_>>());
+        //and every particle found in its own cell
+        for index in 0..particles.len() {
+            let in_cell: Vec<_> = hash.particles_in_cell(hash.cell_of(index)).collect();
+            assert_eq!(in_cell.iter().filter(|&&i| i == index).count(), 1);
+        }
+    }
+
+    #[test]
+    fn densest_cell_tells_apart_cells_sharing_a_slot() {
+        let mut cells = cells(300, 1);
+        //three particles in one cell, more than any cell of the scatter (300 over 729 cells)
+        cells.extend([cgmath::vec3(2, -3, 1); 3]);
+        let hash = SpatialHash::from_cells(cells.clone(), false);
+        let mut counts = std::collections::HashMap::new();
+        for cell in &cells {
+            *counts.entry((cell.x, cell.y, cell.z)).or_insert(0) += 1;
+        }
+        let most = *counts.values().max().unwrap();
+        let expected = counts
+            .iter()
+            .filter(|(_, count)| **count == most)
+            .map(|(&cell, _)| cell)
+            .min()
+            .unwrap();
+        assert_eq!(hash.densest_cell(), Some((cgmath::vec3(expected.0, expected.1, expected.2), most)));
+        assert_eq!(SpatialHash::from_cells(Vec::new(), false).densest_cell(), None);
+    }
+
+    #[test]
+    fn build_of_nothing_is_empty() {
+        for (cell_size, world_size) in [(1.0, 10.0), (0.0, 0.0)] {
+            let hash = SpatialHash::build(&[], cell_size, world_size);
+            assert!(hash.is_empty());
+            assert_eq!(hash.neighbors(cgmath::vec3(0, 0, 0)).count(), 0);
+        }
+    }
+
+    #[test]
+    fn neighbors_scan_the_search_range() {
+        //cells 0, 2 and 3 along x in a 10 wide world of unit cells
+        let particles = [particle_at(-4.5, 0.5, 0.5), particle_at(-2.5, 0.5, 0.5), particle_at(-1.5, 0.5, 0.5)];
+        let hash = SpatialHash::build(&particles, 1.0, 10.0);
+        let origin = hash.cell_of(0);
+        let mut found: Vec<_> = hash.neighbors(origin).collect();
+        assert_eq!(found, vec![0]);
+        let hash = hash.with_search_range(cgmath::vec3(2, 1, 1));
+        found = hash.neighbors(origin).collect();
+        found.sort_unstable();
+        assert_eq!(found, vec![0, 1]);
+        let hash = hash.with_search_range(cgmath::vec3(3, 0, 0));
+        found = hash.neighbors(origin).collect();
+        found.sort_unstable();
+        assert_eq!(found, vec![0, 1, 2]);
+    }
+}
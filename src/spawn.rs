@@ -0,0 +1,382 @@
+use rand::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Particle, Particles, SimError};
+
+//area of the world where a group of particles is placed
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum SpawnRegion {
+    //uniformly inside an axis aligned box
+    Box { min: [f32; 3], max: [f32; 3] },
+    //uniformly between two spheres (inner_radius 0.0 gives a solid ball, close radii give a shell)
+    Sphere { center: [f32; 3], inner_radius: f32, outer_radius: f32 },
+    //gaussian cloud around a center, spread is the standard deviation
+    Blob { center: [f32; 3], spread: f32 },
+}
+
+//how many particles of one type to place and where
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SpeciesSpawn {
+    pub id: u32,//particle type
+    pub count: usize,//how many particles of this type
+    pub region: SpawnRegion,//where to put them
+}
+
+//random direction evenly spread over the unit sphere (rejection sampling in a cube)
+pub(crate) fn random_direction(rng: &mut impl Rng) -> cgmath::Vector3<f32> {
+    use cgmath::InnerSpace;
+    loop {
+        let v = cgmath::vec3(
+            rng.gen_range(-1.0..=1.0),
+            rng.gen_range(-1.0..=1.0),
+            rng.gen_range(-1.0..=1.0),
+        );
+        let sqr_length: f32 = v.magnitude2();
+        if sqr_length > 1e-6 && sqr_length <= 1.0 {
+            return v / sqr_length.sqrt();
+        }
+    }
+}
+
+//standard normal sample using the box-muller transform
+fn random_normal(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+impl SpawnRegion {
+    //picking one random position inside this region
+    pub fn sample(&self, rng: &mut impl Rng) -> cgmath::Vector3<f32> {
+        match *self {
+            SpawnRegion::Box { min, max } => cgmath::vec3(
+                min[0] + (max[0] - min[0]) * rng.gen_range(0.0..=1.0),
+                min[1] + (max[1] - min[1]) * rng.gen_range(0.0..=1.0),
+                min[2] + (max[2] - min[2]) * rng.gen_range(0.0..=1.0),
+            ),
+            SpawnRegion::Sphere { center, inner_radius, outer_radius } => {
+                //picking the radius by volume so the density is even
+                let inner = inner_radius.powi(3);
+                let outer = outer_radius.powi(3);
+                let radius = (inner + (outer - inner) * rng.gen_range(0.0..=1.0)).cbrt();
+                cgmath::Vector3::from(center) + random_direction(rng) * radius
+            }
+            SpawnRegion::Blob { center, spread } => {
+                cgmath::Vector3::from(center)
+                    + cgmath::vec3(random_normal(rng), random_normal(rng), random_normal(rng)) * spread
+            }
+        }
+    }
+}
+
+//one particle at rest with a random position anywhere in the world and a random type
+pub fn random_particle(rng: &mut impl Rng, world_size: f32, id_count: u32) -> Particle {
+    random_particle_in_box(rng, cgmath::vec3(world_size, world_size, world_size), id_count)
+}
+
+//same as `random_particle` for a box world with the given edge lengths
+pub fn random_particle_in_box(rng: &mut impl Rng, extents: cgmath::Vector3<f32>, id_count: u32) -> Particle {
+    let half_extents = extents * 0.5;//calculate half of the box for positioning
+    let position = cgmath::Vector3::new(
+        rng.gen_range(-half_extents.x..=half_extents.x),//random X position
+        rng.gen_range(-half_extents.y..=half_extents.y),
+        rng.gen_range(-half_extents.z..=half_extents.z),
+    );
+    //starting with no movement
+    let velocity = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    //assigning a random type ID
+    let id = rng.gen_range(0..id_count);
+
+    Particle {//storing generated values
+        position,
+        velocity,
+        id,
+    }
+}
+
+//`count` random particles that are the same on every run: every index gets its own rng seeded
+//from a mix of `seed` and the index, so the result doesn't depend on how rayon splits the work
+pub fn generate_particles_seeded(world_size: f32, count: usize, id_count: u32, seed: u64) -> Vec<Particle> {
+    generate_particles_seeded_in_box(cgmath::vec3(world_size, world_size, world_size), count, id_count, seed)
+}
+
+//same as `generate_particles_seeded` for a box world with the given edge lengths
+pub fn generate_particles_seeded_in_box(
+    extents: cgmath::Vector3<f32>,
+    count: usize,
+    id_count: u32,
+    seed: u64,
+) -> Vec<Particle> {
+    (0..count)
+        .into_par_iter()
+        .map(|index| {
+            let mut rng = StdRng::seed_from_u64(particle_seed(seed, index as u64));
+            random_particle_in_box(&mut rng, extents, id_count)
+        })
+        .collect()
+}
+
+//splitmix64 finalizer
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+//seed of the particle at `index`, the splitmix64 stream of the mixed seed, so neighbouring seeds
+//give unrelated particles (`seed ^ index` gave seeds 0 and 1 the same particles, pairwise swapped)
+fn particle_seed(seed: u64, index: u64) -> u64 {
+    mix(mix(seed).wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+}
+
+impl Particles {
+    //brush spawning: adding `count` particles of type `id` at rest, evenly spread inside the
+    //sphere around `center` (anything outside the box is handled by the next step's boundary)
+    pub fn spawn_sphere(
+        &mut self,
+        center: cgmath::Vector3<f32>,
+        radius: f32,
+        count: usize,
+        id: u32,
+    ) -> Result<(), SimError> {
+        if id >= self.id_count {
+            return Err(SimError::InvalidParameter {
+                name: "particle type",
+                reason: format!("{} is out of range for {} types", id, self.id_count),
+            });
+        }
+        if !(radius >= 0.0 && radius.is_finite()) {
+            return Err(SimError::InvalidParameter {
+                name: "brush radius",
+                reason: format!("{} is not a non-negative finite radius", radius),
+            });
+        }
+        let region = SpawnRegion::Sphere {
+            center: center.into(),
+            inner_radius: 0.0,
+            outer_radius: radius,
+        };
+        let mut rng = rand::thread_rng();
+        self.active_particles.extend((0..count).map(|_| Particle {
+            position: region.sample(&mut rng),
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            id,
+        }));
+        //the next step starts without verlet history, same as a fresh simulation (a zero
+        //acceleration would be a made-up history for the new particles)
+        self.previous_accelerations.clear();
+        Ok(())
+    }
+
+    //fresh layout without touching any tuning: replacing the particles with the same number of
+    //seeded random ones (see `generate_particles_seeded`) and starting the clock over
+    pub fn reseed(&mut self, seed: u64) {
+        self.active_particles = generate_particles_seeded_in_box(
+            self.world_extents(),
+            self.active_particles.len(),
+            self.id_count,
+            seed,
+        );
+        self.apply_dimensions();
+        self.past_particles.clear();
+        self.previous_accelerations.clear();
+        self.sim_time = 0.0;
+    }
+
+    //brush erasing: removing every particle strictly inside `radius` of `center`, returns how
+    //many were removed
+    pub fn despawn_sphere(&mut self, center: cgmath::Vector3<f32>, radius: f32) -> usize {
+        use cgmath::InnerSpace;
+        let outside: Vec<bool> = self
+            .active_particles
+            .iter()
+            .map(|particle| (particle.position - center).magnitude2() >= radius * radius)
+            .collect();
+        let before = self.active_particles.len();
+        let mut keep = outside.iter();
+        self.active_particles.retain(|_| *keep.next().unwrap());
+        //keeping the verlet accelerations and wrap flags lined up with the particles that are left
+        if self.previous_accelerations.len() == outside.len() {
+            let mut keep = outside.iter();
+            self.previous_accelerations.retain(|_| *keep.next().unwrap());
+        }
+        if self.wrapped.len() == outside.len() {
+            let mut keep = outside.iter();
+            self.wrapped.retain(|_| *keep.next().unwrap());
+        }
+        if self.double_state.len() == outside.len() {
+            let mut keep = outside.iter();
+            self.double_state.retain(|_| *keep.next().unwrap());
+        }
+        before - self.active_particles.len()
+    }
+}
+
+//placing particles of each type in their own region, starting at rest
+pub fn spawn_particles(spec: &[SpeciesSpawn], rng: &mut impl Rng) -> Vec<Particle> {
+    spec.iter()
+        .flat_map(|species| std::iter::repeat(species).take(species.count))
+        .map(|species| Particle {
+            position: species.region.sample(rng),
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            id: species.id,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_generation_is_reproducible() {
+        let first = generate_particles_seeded(10.0, 1000, 3, 42);
+        let second = generate_particles_seeded(10.0, 1000, 3, 42);
+        assert_eq!(first.len(), 1000);
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.velocity, b.velocity);
+            assert_eq!(a.id, b.id);
+        }
+    }
+
+    #[test]
+    fn regions_sample_inside_their_bounds() {
+        use cgmath::InnerSpace;
+        let mut rng = StdRng::seed_from_u64(3);
+        let cube = SpawnRegion::Box { min: [-1.0, 2.0, 0.0], max: [1.0, 3.0, 0.5] };
+        let shell = SpawnRegion::Sphere { center: [1.0, 1.0, 1.0], inner_radius: 2.0, outer_radius: 2.5 };
+        let blob = SpawnRegion::Blob { center: [0.0, -3.0, 0.0], spread: 0.5 };
+        let mut blob_sum = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        for _ in 0..2000 {
+            let p = cube.sample(&mut rng);
+            assert!((-1.0..=1.0).contains(&p.x) && (2.0..=3.0).contains(&p.y) && (0.0..=0.5).contains(&p.z));
+            let distance = (shell.sample(&mut rng) - cgmath::vec3(1.0, 1.0, 1.0)).magnitude();
+            assert!((2.0 - 1e-4..=2.5 + 1e-4).contains(&distance), "{}", distance);
+            blob_sum += blob.sample(&mut rng);
+        }
+        //a gaussian has no bounds, but its samples center on the middle
+        let blob_mean = blob_sum / 2000.0;
+        assert!((blob_mean - cgmath::vec3(0.0, -3.0, 0.0)).magnitude() < 0.1, "{:?}", blob_mean);
+    }
+
+    #[test]
+    fn spawned_species_have_their_counts_regions_and_rest() {
+        let spec = [
+            SpeciesSpawn { id: 2, count: 30, region: SpawnRegion::Box { min: [0.0; 3], max: [1.0; 3] } },
+            SpeciesSpawn {
+                id: 0,
+                count: 50,
+                region: SpawnRegion::Sphere { center: [-3.0, 0.0, 0.0], inner_radius: 0.0, outer_radius: 1.0 },
+            },
+            SpeciesSpawn { id: 1, count: 0, region: SpawnRegion::Blob { center: [0.0; 3], spread: 1.0 } },
+        ];
+        let particles = spawn_particles(&spec, &mut StdRng::seed_from_u64(8));
+        assert_eq!(particles.len(), 80);
+        let count = |id: u32| particles.iter().filter(|p| p.id == id).count();
+        assert_eq!((count(0), count(1), count(2)), (50, 0, 30));
+        for particle in &particles {
+            assert_eq!(particle.velocity, cgmath::vec3(0.0, 0.0, 0.0));
+            let inside = match particle.id {
+                2 => particle.position.x >= 0.0 && particle.position.x <= 1.0,
+                _ => particle.position.x <= -2.0 + 1e-4,
+            };
+            assert!(inside, "{:?}", particle);
+        }
+        //the same rng seed places them the same way
+        let again = spawn_particles(&spec, &mut StdRng::seed_from_u64(8));
+        assert!(particles.iter().zip(&again).all(|(a, b)| a.position == b.position && a.id == b.id));
+    }
+
+    #[test]
+    fn despawning_keeps_the_per_particle_state_lined_up() {
+        let mut sim = Particles::builder()
+            .integrator(crate::Integrator::VelocityVerlet)
+            .particles(generate_particles_seeded(10.0, 300, 5, 6))
+            .build()
+            .unwrap();
+        sim.update(0.01).unwrap();
+        //marking particles by where they are, so every flag names its particle
+        let in_front = |p: &Particle| p.position.x > 0.0;
+        sim.wrapped = sim.active_particles.iter().map(in_front).collect();
+        let accelerations: Vec<_> = sim.active_particles.iter().map(|p| p.position * 2.0).collect();
+        sim.previous_accelerations = accelerations;
+
+        let removed = sim.despawn_sphere(cgmath::vec3(1.0, 0.0, 0.0), 3.0);
+        assert!(removed > 0);
+        assert_eq!(sim.wrapped.len(), sim.active_particles.len());
+        assert_eq!(sim.previous_accelerations.len(), sim.active_particles.len());
+        for ((particle, &wrapped), &acceleration) in
+            sim.active_particles.iter().zip(&sim.wrapped).zip(&sim.previous_accelerations)
+        {
+            assert_eq!(wrapped, in_front(particle));
+            assert_eq!(acceleration, particle.position * 2.0);
+        }
+    }
+
+    #[test]
+    fn spawned_particles_step_like_a_fresh_simulation() {
+        let builder = || {
+            Particles::builder()
+                .integrator(crate::Integrator::VelocityVerlet)
+                .deterministic(true)
+                .particles(generate_particles_seeded(10.0, 200, 5, 3))
+        };
+        let mut sim = builder().build().unwrap();
+        sim.update(0.01).unwrap();
+        sim.spawn_sphere(cgmath::vec3(0.0, 0.0, 0.0), 1.0, 20, 1).unwrap();
+        assert!(sim.previous_accelerations.is_empty());
+
+        let mut fresh = builder().particles(sim.active_particles.clone()).build().unwrap();
+        sim.update(0.01).unwrap();
+        fresh.update(0.01).unwrap();
+        for (a, b) in sim.active_particles.iter().zip(&fresh.active_particles) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.velocity, b.velocity);
+        }
+    }
+
+    #[test]
+    fn neighbouring_seeds_give_different_particles() {
+        let positions = |seed| {
+            generate_particles_seeded(10.0, 200, 3, seed)
+                .into_iter()
+                .map(|particle| particle.position)
+                .collect::<Vec<_>>()
+        };
+        let first = positions(0);
+        for seed in [1, 2, 3] {
+            let other = positions(seed);
+            assert!(other.iter().all(|position| !first.contains(position)), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn reseed_replaces_only_the_particles() {
+        let mut sim = Particles::builder()
+            .integrator(crate::Integrator::VelocityVerlet)
+            .attraction_matrix(vec![0.5; 25])
+            .particles(generate_particles_seeded(10.0, 150, 5, 1))
+            .build()
+            .unwrap();
+        sim.update(0.01).unwrap();
+        sim.reseed(8);
+
+        let expected = generate_particles_seeded(10.0, 150, 5, 8);
+        assert_eq!(sim.active_particles.len(), expected.len());
+        for (a, b) in sim.active_particles.iter().zip(&expected) {
+            assert_eq!((a.position, a.velocity, a.id), (b.position, b.velocity, b.id));
+        }
+        assert!(sim.past_particles.is_empty());
+        assert!(sim.previous_accelerations.is_empty());
+        assert_eq!(sim.sim_time, 0.0);
+        assert_eq!(sim.attraction_matrix.values(), &[0.5; 25][..]);
+    }
+
+    #[test]
+    fn seeded_generation_uses_only_the_given_types() {
+        let particles = generate_particles_seeded(10.0, 1000, 2, 7);
+        assert!(particles.iter().all(|particle| particle.id < 2));
+    }
+}
@@ -0,0 +1,138 @@
+use cgmath::prelude::*;
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::{spawn::random_direction, Dim, Particles};
+
+//how many random waves make up the stirring field, more gives a finer, less regular pattern
+const STIR_MODES: usize = 8;
+
+//one sine wave of a divergence-free velocity field, its amplitude is perpendicular to its wave
+//vector so the wave neither compresses nor spreads the particles
+struct StirMode {
+    wave: cgmath::Vector3<f32>,//direction and spatial frequency
+    amplitude: cgmath::Vector3<f32>,//velocity at the wave's peak, perpendicular to `wave`
+    phase: f32,
+}
+
+impl Particles {
+    //one-shot "stirring": adding a smooth, divergence-free random velocity field so neighboring
+    //particles move together (unlike per-particle noise), keeping a settled system from freezing
+    //`strength` is roughly the rms speed added, the same seed always gives the same field,
+    //and the field's mass weighted mean is removed so the net momentum doesn't change (no kicks
+    //along y when it's locked or along z in 2D)
+    pub fn stir(&mut self, strength: f32, seed: u64) {
+        if self.active_particles.is_empty() {
+            return;
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        //waves from one to three wavelengths per world extent along each axis
+        let base_frequency = self.world_extents().map(|extent| std::f32::consts::TAU / extent);
+        //each sine contributes half its amplitude squared to the mean square speed
+        let amplitude = strength * (2.0 / STIR_MODES as f32).sqrt();
+        let modes: Vec<StirMode> = (0..STIR_MODES)
+            .map(|_| {
+                let wave = random_direction(&mut rng).mul_element_wise(base_frequency)
+                    * rng.gen_range(1..=3) as f32;
+                //dropping the part of a random direction along the wave
+                let direction = random_direction(&mut rng);
+                let mut perpendicular = direction - wave * (direction.dot(wave) / wave.magnitude2());
+                if perpendicular.magnitude2() < 1e-8 {
+                    perpendicular = wave.cross(cgmath::vec3(1.0, 0.0, 0.0));
+                    if perpendicular.magnitude2() < 1e-8 {
+                        perpendicular = wave.cross(cgmath::vec3(0.0, 1.0, 0.0));
+                    }
+                }
+                StirMode {
+                    wave,
+                    amplitude: perpendicular.normalize() * amplitude,
+                    phase: rng.gen_range(0.0..std::f32::consts::TAU),
+                }
+            })
+            .collect();
+
+        let kicks: Vec<cgmath::Vector3<f32>> = self
+            .active_particles
+            .par_iter()
+            .map(|particle| {
+                let mut kick: cgmath::Vector3<f32> = modes
+                    .iter()
+                    .map(|mode| mode.amplitude * (mode.wave.dot(particle.position) + mode.phase).sin())
+                    .sum();
+                if self.lock_y {
+                    kick.y = 0.0;
+                }
+                if self.dimensions == Dim::Two {
+                    kick.z = 0.0;
+                }
+                kick
+            })
+            .collect();
+        let mass = |id: u32| self.masses.get(id as usize).copied().unwrap_or(1.0);
+        let total_mass: f32 = self.active_particles.iter().map(|p| mass(p.id)).sum();
+        let mean = self
+            .active_particles
+            .iter()
+            .zip(&kicks)
+            .map(|(particle, kick)| kick * mass(particle.id))
+            .sum::<cgmath::Vector3<f32>>()
+            / total_mass;
+
+        for (particle, kick) in self.active_particles.iter_mut().zip(kicks) {
+            particle.velocity += kick - mean;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_particles_seeded_in_box;
+
+    fn momentum(sim: &Particles) -> cgmath::Vector3<f32> {
+        sim.active_particles
+            .iter()
+            .map(|p| p.velocity * sim.masses[p.id as usize])
+            .sum()
+    }
+
+    #[test]
+    fn stirring_keeps_the_net_momentum_of_unequal_masses() {
+        let extents = cgmath::vec3(20.0, 5.0, 5.0);
+        let mut sim = Particles::builder()
+            .world_extents(extents)
+            .particles(generate_particles_seeded_in_box(extents, 400, 5, 2))
+            .build()
+            .unwrap();
+        sim.masses = vec![1.0, 4.0, 0.5, 2.0, 8.0];
+        let before = momentum(&sim);
+        sim.stir(1.0, 7);
+
+        assert!((momentum(&sim) - before).magnitude() < 1e-2);
+        let rms = (sim.active_particles.iter().map(|p| p.velocity.magnitude2()).sum::<f32>()
+            / sim.active_particles.len() as f32)
+            .sqrt();
+        assert!(rms > 0.3, "{}", rms);
+    }
+
+    #[test]
+    fn stirring_stays_in_the_allowed_plane() {
+        let mut flat = Particles::builder()
+            .dimensions(Dim::Two)
+            .particles(crate::generate_particles_seeded(10.0, 200, 5, 1))
+            .build()
+            .unwrap();
+        flat.apply_dimensions();
+        flat.stir(1.0, 3);
+        assert!(flat.active_particles.iter().all(|p| p.velocity.z == 0.0));
+
+        let mut tabletop = Particles::builder()
+            .particles(crate::generate_particles_seeded(10.0, 200, 5, 1))
+            .build()
+            .unwrap();
+        tabletop.lock_y = true;
+        tabletop.stir(1.0, 3);
+        assert!(tabletop.active_particles.iter().all(|p| p.velocity.y == 0.0));
+        assert!(tabletop.active_particles.iter().any(|p| p.velocity.x != 0.0));
+    }
+}